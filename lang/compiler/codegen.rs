@@ -1,12 +1,13 @@
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::{cell::RefCell, collections::HashMap, fmt, rc::Rc};
 
 use lang_component::{
     bullet::StateId,
+    span::Span,
     syntax::{Body, Expr, Name, Op2, Signature, Symbol, SyntaxTree, Type},
     vm::{ExternalOperation, Inst},
 };
 
-use crate::BulletCode;
+use crate::{timeline, BulletCode};
 
 type VarInfo = (Type, String);
 
@@ -18,6 +19,17 @@ enum StackData {
     String,
 }
 
+impl StackData {
+    fn r#type(&self) -> Type {
+        match self {
+            StackData::Var((t, _)) => *t,
+            StackData::Float => Type::Float,
+            StackData::Bool => Type::Bool,
+            StackData::String => Type::String,
+        }
+    }
+}
+
 impl From<Type> for StackData {
     fn from(t: Type) -> Self {
         match t {
@@ -53,7 +65,26 @@ impl From<Symbol> for StackData {
                     StateId::InputSlow => {
                         StackData::Var((Type::Bool, format!("{}.input_slow", bid)))
                     }
+                    StateId::InputBomb => {
+                        StackData::Var((Type::Bool, format!("{}.input_bomb", bid)))
+                    }
                     StateId::Enabled => StackData::Var((Type::Bool, format!("{}.enabled", bid))),
+                    StateId::Frames => StackData::Var((Type::Float, format!("{}.frames", bid))),
+                    StateId::Age => StackData::Var((Type::Float, format!("{}.age", bid))),
+                    StateId::VelX => StackData::Var((Type::Float, format!("{}.vx", bid))),
+                    StateId::VelY => StackData::Var((Type::Float, format!("{}.vy", bid))),
+                    StateId::Speed => StackData::Var((Type::Float, format!("{}.speed", bid))),
+                    StateId::Angle => StackData::Var((Type::Float, format!("{}.angle", bid))),
+                    StateId::Accel => StackData::Var((Type::Float, format!("{}.accel", bid))),
+                    StateId::AngularVelocity => {
+                        StackData::Var((Type::Float, format!("{}.angular_velocity", bid)))
+                    }
+                    StateId::Hit => StackData::Var((Type::Bool, format!("{}.hit", bid))),
+                    StateId::Score => StackData::Var((Type::Float, format!("{}.score", bid))),
+                    StateId::Graze => StackData::Var((Type::Float, format!("{}.graze", bid))),
+                    StateId::Hp => StackData::Var((Type::Float, format!("{}.hp", bid))),
+                    StateId::Lives => StackData::Var((Type::Float, format!("{}.lives", bid))),
+                    StateId::Bombs => StackData::Var((Type::Float, format!("{}.bombs", bid))),
                 }
             }
         }
@@ -82,9 +113,9 @@ impl StackInfo {
         self.info.pop()
     }
 
+    // Finds the innermost variable of `name` and returns its depth from the stack top.
     fn get(&self, name: &str) -> Option<(usize, StackData)> {
-        //let  len = self.info.len();
-        if let Some((idx, sd)) = self.info.iter().enumerate().find(|(_, sd)| match sd {
+        if let Some((idx, sd)) = self.info.iter().enumerate().rev().find(|(_, sd)| match sd {
             StackData::Var((_, n)) => n == &name[..],
             _ => false,
         }) {
@@ -123,11 +154,7 @@ impl MemoryInfo {
         let mut offset = 0;
 
         for mi in info_list.borrow().iter() {
-            let size = match mi.r#type {
-                Type::Float => 4,
-                Type::Bool => 1,
-                Type::String => 0, // string stored as [size, ch0, ch1, ...]
-            };
+            let size = mi.r#type.size();
 
             if self.name == mi.name && self.r#type == mi.r#type {
                 break;
@@ -164,6 +191,33 @@ impl Proc {
     }
 }
 
+// A bullet defined with `bullet` in the source.
+#[derive(Debug, Clone)]
+struct BulletDef {
+    id: usize,
+    signature: Signature,
+}
+
+// A loop being generated.
+#[derive(Debug, Clone)]
+struct LoopInfo {
+    // stack depth at the head of the loop body
+    depth: usize,
+    // positions of jumps by `break` and `continue`, resolved at the end of the loop
+    break_jumps: Vec<usize>,
+    continue_jumps: Vec<usize>,
+}
+
+impl LoopInfo {
+    fn new(depth: usize) -> Self {
+        Self {
+            depth,
+            break_jumps: Vec::new(),
+            continue_jumps: Vec::new(),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct CodegenState<'a> {
     current_proc: Option<String>,
@@ -171,10 +225,12 @@ pub struct CodegenState<'a> {
     proc_map: Rc<RefCell<HashMap<String, Proc>>>,
     proc_order: Rc<RefCell<Vec<String>>>,
     stack: StackInfo,
+    loops: Vec<LoopInfo>,
     pub memory: Vec<u8>,
     memory_info: Rc<RefCell<Vec<MemoryInfo>>>,
     current_unresolved: Rc<RefCell<Vec<ResolveInfo>>>,
     compiled_code_vec: &'a Vec<Rc<BulletCode>>,
+    bullet_map: Rc<HashMap<String, BulletDef>>,
 }
 
 impl<'a> CodegenState<'a> {
@@ -182,6 +238,7 @@ impl<'a> CodegenState<'a> {
         proc_map: Rc<RefCell<HashMap<String, Proc>>>,
         memory_info: Rc<RefCell<Vec<MemoryInfo>>>,
         compiled_code_vec: &'a Vec<Rc<BulletCode>>,
+        bullet_map: Rc<HashMap<String, BulletDef>>,
     ) -> Self {
         Self {
             current_proc: None,
@@ -189,10 +246,12 @@ impl<'a> CodegenState<'a> {
             proc_map,
             proc_order: Rc::new(RefCell::new(Vec::new())),
             stack: StackInfo::new(),
+            loops: Vec::new(),
             memory: Vec::from([0; 128]),
             memory_info: memory_info,
             current_unresolved: Rc::new(RefCell::new(Vec::new())),
             compiled_code_vec,
+            bullet_map,
         }
     }
 
@@ -220,78 +279,395 @@ macro_rules! emit {
 }
 
 #[derive(Debug, Clone)]
-pub enum CodegenError {
+pub enum CodegenErrorKind {
     UnknownVMState(String),
     UnknownVariable(String),
     UnknownName(String),
     ProcAlreadyDefined(String),
     MainProcIsNotDefined,
     UndefinedProc(String),
-    GlobalDefineOnlyAllowsLiteral(Expr),
+    UndefinedBullet(String),
+    BulletCallExpected,
+    BulletAlreadyDefined(String),
+    WrongParamNumberWhileFiringBullet(String),
+    WrongParamTypeWhileFiringBullet(String),
+    GlobalDefineOnlyAllowsLiteral(Box<Expr>),
     GlobalDefineOnlyAllowsToVar(Symbol),
     WrongParamNumberWhileInvokingExternalOp,
     WrongTypeWhileInvokingExternalOp,
+    LoopCountIsNotAFloat,
+    BreakOutsideLoop,
+    ContinueOutsideLoop,
     StringIsNotSupportedHere,
     NotAString,
     BulletRefNotAllowedHere,
+    MemoryIsFull(String),
 }
 
-fn codegen_external_op_fire(args: Vec<Expr>, state: &mut CodegenState) -> Result<(), CodegenError> {
-    if args.len() != 3 {
-        return Err(CodegenError::WrongParamNumberWhileInvokingExternalOp);
+impl fmt::Display for CodegenErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CodegenErrorKind::UnknownVMState(name) => write!(f, "unknown bullet state `{}`", name),
+            CodegenErrorKind::UnknownVariable(name) => write!(f, "unknown variable `{}`", name),
+            CodegenErrorKind::UnknownName(name) => write!(f, "unknown bullet code `{}`", name),
+            CodegenErrorKind::ProcAlreadyDefined(name) => {
+                write!(f, "proc `{}` is already defined", name)
+            }
+            CodegenErrorKind::MainProcIsNotDefined => write!(f, "proc `main` is not defined"),
+            CodegenErrorKind::UndefinedProc(name) => write!(f, "proc `{}` is not defined", name),
+            CodegenErrorKind::UndefinedBullet(name) => {
+                write!(f, "bullet `{}` is not defined", name)
+            }
+            CodegenErrorKind::BulletCallExpected => {
+                write!(
+                    f,
+                    "a bullet with its parameters like `name(params...)` is expected"
+                )
+            }
+            CodegenErrorKind::BulletAlreadyDefined(name) => {
+                write!(f, "bullet `{}` is already defined", name)
+            }
+            CodegenErrorKind::WrongParamNumberWhileFiringBullet(name) => {
+                write!(f, "wrong number of parameters for bullet `{}`", name)
+            }
+            CodegenErrorKind::WrongParamTypeWhileFiringBullet(name) => {
+                write!(f, "wrong type of parameter for bullet `{}`", name)
+            }
+            CodegenErrorKind::GlobalDefineOnlyAllowsLiteral(_) => {
+                write!(f, "global variables can only be initialized with a literal")
+            }
+            CodegenErrorKind::GlobalDefineOnlyAllowsToVar(_) => {
+                write!(f, "only variables can be defined with `global`")
+            }
+            CodegenErrorKind::WrongParamNumberWhileInvokingExternalOp => {
+                write!(f, "wrong number of arguments for a builtin operation")
+            }
+            CodegenErrorKind::WrongTypeWhileInvokingExternalOp => {
+                write!(f, "wrong type of argument for a builtin operation")
+            }
+            CodegenErrorKind::LoopCountIsNotAFloat => {
+                write!(f, "loop counts and ranges must be floats")
+            }
+            CodegenErrorKind::BreakOutsideLoop => write!(f, "`break` outside of a loop"),
+            CodegenErrorKind::ContinueOutsideLoop => write!(f, "`continue` outside of a loop"),
+            CodegenErrorKind::StringIsNotSupportedHere => {
+                write!(f, "strings are not supported here")
+            }
+            CodegenErrorKind::NotAString => write!(f, "a string is expected"),
+            CodegenErrorKind::BulletRefNotAllowedHere => {
+                write!(f, "bullet states are not allowed here")
+            }
+            CodegenErrorKind::MemoryIsFull(name) => {
+                write!(f, "no memory is left for global `{}`", name)
+            }
+        }
     }
+}
 
-    let bullet_id = if let Expr::String(bullet_name) = &args[0] {
-        if let Some((idx, _)) = state
-            .compiled_code_vec
-            .iter()
-            .enumerate()
-            .find(|(_, bc)| &bc.name == bullet_name)
-        {
-            idx
-        } else {
-            return Err(CodegenError::UnknownName(bullet_name.to_string()));
+impl CodegenErrorKind {
+    fn at(self, span: Span) -> CodegenError {
+        CodegenError {
+            kind: self,
+            span: Some(span),
         }
-    } else {
-        return Err(CodegenError::NotAString);
-    };
+    }
+}
+
+// Represents codegen errors. `span` points the source code which causes the error.
+#[derive(Debug, Clone)]
+pub struct CodegenError {
+    pub kind: CodegenErrorKind,
+    pub span: Option<Span>,
+}
+
+impl CodegenError {
+    // Sets `span` unless the error already knows more precise span.
+    fn or_at(self, span: Span) -> Self {
+        match self.span {
+            Some(_) => self,
+            None => Self {
+                kind: self.kind,
+                span: Some(span),
+            },
+        }
+    }
+}
+
+impl From<CodegenErrorKind> for CodegenError {
+    fn from(kind: CodegenErrorKind) -> Self {
+        Self { kind, span: None }
+    }
+}
 
-    codegen_expr(&args[1], state)?;
+// Generates `op` firing or spawning a bullet at (`x`, `y`) with `params`, which are checked
+// with `signature` of the bullet. `span` points the bullet name.
+fn codegen_fire(
+    op: ExternalOperation,
+    name: &str,
+    (x, y): (&Expr, &Expr),
+    params: &[Expr],
+    signature: &Signature,
+    span: Span,
+    state: &mut CodegenState,
+) -> Result<(), CodegenError> {
+    codegen_expr(x, state)?;
     if !matches!(state.stack.peek(0), Some(StackData::Float)) {
-        return Err(CodegenError::WrongTypeWhileInvokingExternalOp);
+        return Err(CodegenErrorKind::WrongTypeWhileInvokingExternalOp.at(x.span()));
     }
 
-    codegen_expr(&args[2], state)?;
+    codegen_expr(y, state)?;
     if !matches!(state.stack.peek(0), Some(StackData::Float)) {
-        return Err(CodegenError::WrongTypeWhileInvokingExternalOp);
+        return Err(CodegenErrorKind::WrongTypeWhileInvokingExternalOp.at(y.span()));
     }
 
-    let _ = state.stack.pop();
-    let _ = state.stack.pop();
-    let _ = state.stack.pop();
+    if params.len() != signature.args.len() {
+        return Err(CodegenErrorKind::WrongParamNumberWhileFiringBullet(name.to_string()).at(span));
+    }
+
+    for (param, arg) in params.iter().zip(signature.args.iter()) {
+        codegen_expr(param, state)?;
+        if state.stack.peek(0).map(|sd| sd.r#type()) != Some(arg.r#type) {
+            return Err(
+                CodegenErrorKind::WrongParamTypeWhileFiringBullet(name.to_string())
+                    .at(param.span()),
+            );
+        }
+    }
+
+    for _ in 0..(2 + params.len()) {
+        let _ = state.stack.pop();
+    }
 
-    emit!(state, Inst::Operate(ExternalOperation::Fire(bullet_id)));
+    emit!(state, Inst::Operate(op));
     state.stack.push(StackData::Bool);
 
     Ok(())
 }
 
+// Generates `fire("name", x, y, params...)`, which fires a bullet by its name.
+// The bullet may be compiled from another source.
+fn codegen_external_op_fire_by_name(
+    op: fn(usize, usize) -> ExternalOperation,
+    args: Vec<Expr>,
+    state: &mut CodegenState,
+) -> Result<(), CodegenError> {
+    if args.len() < 3 {
+        return Err(CodegenErrorKind::WrongParamNumberWhileInvokingExternalOp.into());
+    }
+
+    let (name, span) = if let Expr::String(name, span) = &args[0] {
+        (name, *span)
+    } else {
+        return Err(CodegenErrorKind::NotAString.at(args[0].span()));
+    };
+
+    let (id, signature) = if let Some(bullet) = state.bullet_map.get(name) {
+        (bullet.id, bullet.signature.clone())
+    } else if let Some((idx, bc)) = state
+        .compiled_code_vec
+        .iter()
+        .enumerate()
+        .find(|(_, bc)| &bc.name == name)
+    {
+        (idx, bc.signature.clone())
+    } else {
+        return Err(CodegenErrorKind::UnknownName(name.to_string()).at(span));
+    };
+
+    codegen_fire(
+        op(id, args.len() - 3),
+        name,
+        (&args[1], &args[2]),
+        &args[3..],
+        &signature,
+        span,
+        state,
+    )
+}
+
+// Generates `fire(x, y, name(params...))`, which fires a bullet defined in the source.
+fn codegen_external_op_fire(args: Vec<Expr>, state: &mut CodegenState) -> Result<(), CodegenError> {
+    codegen_bullet_op(ExternalOperation::Fire, args, state)
+}
+
+// Generates `spawn(x, y, name(params...))` and `spawn("name", x, y, params...)`, which
+// spawn an enemy running the bullet code.
+fn codegen_external_op_spawn(
+    args: Vec<Expr>,
+    state: &mut CodegenState,
+) -> Result<(), CodegenError> {
+    codegen_bullet_op(ExternalOperation::Spawn, args, state)
+}
+
+fn codegen_bullet_op(
+    op: fn(usize, usize) -> ExternalOperation,
+    args: Vec<Expr>,
+    state: &mut CodegenState,
+) -> Result<(), CodegenError> {
+    if let Some(Expr::String(_, _)) = args.first() {
+        return codegen_external_op_fire_by_name(op, args, state);
+    }
+
+    if args.len() != 3 {
+        return Err(CodegenErrorKind::WrongParamNumberWhileInvokingExternalOp.into());
+    }
+
+    let (name, params, span) = match &args[2] {
+        Expr::ProcCall(Name(name), params, span) => (name, params, *span),
+        expr => return Err(CodegenErrorKind::BulletCallExpected.at(expr.span())),
+    };
+    let bullet = match state.bullet_map.get(name) {
+        Some(bullet) => bullet.clone(),
+        None => return Err(CodegenErrorKind::UndefinedBullet(name.to_string()).at(span)),
+    };
+
+    codegen_fire(
+        op(bullet.id, params.len()),
+        name,
+        (&args[0], &args[1]),
+        params,
+        &bullet.signature,
+        span,
+        state,
+    )
+}
+
 fn codegen_external_op_die(args: Vec<Expr>, state: &mut CodegenState) -> Result<(), CodegenError> {
     if args.len() != 0 {
-        return Err(CodegenError::WrongParamNumberWhileInvokingExternalOp);
+        return Err(CodegenErrorKind::WrongParamNumberWhileInvokingExternalOp.into());
     }
 
     emit!(state, Inst::Operate(ExternalOperation::Die));
+    state.stack.push(StackData::Bool);
+
+    Ok(())
+}
+
+// Generates `rand()`, which returns a float in [0, 1).
+fn codegen_external_op_rand(args: Vec<Expr>, state: &mut CodegenState) -> Result<(), CodegenError> {
+    if !args.is_empty() {
+        return Err(CodegenErrorKind::WrongParamNumberWhileInvokingExternalOp.into());
+    }
+
+    emit!(state, Inst::Rand);
+    state.stack.push(StackData::Float);
+
+    Ok(())
+}
+
+// Generates `wait(frames)`, which suspends the program and resumes it after the frames.
+fn codegen_external_op_wait(args: Vec<Expr>, state: &mut CodegenState) -> Result<(), CodegenError> {
+    if args.len() != 1 {
+        return Err(CodegenErrorKind::WrongParamNumberWhileInvokingExternalOp.into());
+    }
+
+    codegen_expr(&args[0], state)?;
+    if !matches!(state.stack.pop(), Some(sd) if sd.r#type() == Type::Float) {
+        return Err(CodegenErrorKind::WrongTypeWhileInvokingExternalOp.at(args[0].span()));
+    }
+
+    emit!(state, Inst::Yield);
+    state.stack.push(StackData::Bool);
+
+    Ok(())
+}
+
+// Generates `yield()` as `wait(1)`, which resumes the program at the next frame.
+fn codegen_external_op_yield(
+    args: Vec<Expr>,
+    state: &mut CodegenState,
+) -> Result<(), CodegenError> {
+    if !args.is_empty() {
+        return Err(CodegenErrorKind::WrongParamNumberWhileInvokingExternalOp.into());
+    }
+
+    emit!(state, Inst::Float(1.0));
+    emit!(state, Inst::Yield);
+    state.stack.push(StackData::Bool);
+
+    Ok(())
+}
+
+// Generates `rand_range(a, b)` as `a + rand() * (b - a)`, which returns a float in [a, b).
+fn codegen_external_op_rand_range(
+    args: Vec<Expr>,
+    state: &mut CodegenState,
+) -> Result<(), CodegenError> {
+    if args.len() != 2 {
+        return Err(CodegenErrorKind::WrongParamNumberWhileInvokingExternalOp.into());
+    }
+
+    for arg in args.iter() {
+        codegen_expr(arg, state)?;
+        if !matches!(state.stack.peek(0), Some(sd) if sd.r#type() == Type::Float) {
+            return Err(CodegenErrorKind::WrongTypeWhileInvokingExternalOp.at(arg.span()));
+        }
+    }
+
+    // a b -> a (b - a) -> a (b - a) * rand -> a + (b - a) * rand
+    emit!(state, Inst::Float(1.0));
+    emit!(state, Inst::Index);
+    emit!(state, Inst::Sub);
+    emit!(state, Inst::Rand);
+    emit!(state, Inst::Mul);
+    emit!(state, Inst::Add);
+    let _ = state.stack.pop();
+    let _ = state.stack.pop();
+    state.stack.push(StackData::Float);
+
+    Ok(())
+}
+
+// Generates a math builtin, which takes `arg_num` floats and returns a float.
+fn codegen_math_op(
+    args: Vec<Expr>,
+    arg_num: usize,
+    inst: Inst,
+    state: &mut CodegenState,
+) -> Result<(), CodegenError> {
+    if args.len() != arg_num {
+        return Err(CodegenErrorKind::WrongParamNumberWhileInvokingExternalOp.into());
+    }
+
+    for arg in args.iter() {
+        codegen_expr(arg, state)?;
+        if !matches!(state.stack.peek(0), Some(sd) if sd.r#type() == Type::Float) {
+            return Err(CodegenErrorKind::WrongTypeWhileInvokingExternalOp.at(arg.span()));
+        }
+    }
+
+    for _ in 0..arg_num {
+        let _ = state.stack.pop();
+    }
+    emit!(state, inst);
+    state.stack.push(StackData::Float);
 
     Ok(())
 }
 
+// Math builtins with their number of args. Angles are in degrees like `atan2(y, x)`.
+const MATH_OPS: [(&str, usize, Inst); 8] = [
+    ("sin", 1, Inst::Sin),
+    ("cos", 1, Inst::Cos),
+    ("atan2", 2, Inst::Atan2),
+    ("sqrt", 1, Inst::Sqrt),
+    ("abs", 1, Inst::Abs),
+    ("min", 2, Inst::Min),
+    ("max", 2, Inst::Max),
+    ("floor", 1, Inst::Floor),
+];
+
 const EXTERNAL_OPS: [(
     &str,
     &dyn Fn(Vec<Expr>, &mut CodegenState) -> Result<(), CodegenError>,
-); 2] = [
+); 7] = [
     ("fire", &codegen_external_op_fire),
+    ("spawn", &codegen_external_op_spawn),
     ("die", &codegen_external_op_die),
+    ("rand", &codegen_external_op_rand),
+    ("rand_range", &codegen_external_op_rand_range),
+    ("wait", &codegen_external_op_wait),
+    ("yield", &codegen_external_op_yield),
 ];
 
 fn codegen_external_op(
@@ -299,10 +675,14 @@ fn codegen_external_op(
     args: Vec<Expr>,
     state: &mut CodegenState,
 ) -> Result<(), CodegenError> {
+    if let Some((_, arg_num, inst)) = MATH_OPS.iter().find(|(n, _, _)| n == &name) {
+        return codegen_math_op(args, *arg_num, inst.clone(), state);
+    }
+
     let op = EXTERNAL_OPS.iter().find(|(n, _)| n == &name);
 
     if op.is_none() {
-        return Err(CodegenError::UndefinedProc(name.to_string()));
+        return Err(CodegenErrorKind::UndefinedProc(name.to_string()).into());
     }
 
     let (_, codegen_fn) = op.unwrap();
@@ -311,35 +691,26 @@ fn codegen_external_op(
 
 fn codegen_expr(expr: &Expr, state: &mut CodegenState) -> Result<(), CodegenError> {
     match expr {
-        Expr::Float(f) => {
+        Expr::Float(f, _) => {
             emit!(state, Inst::Float(*f));
             state.stack.push(StackData::Float);
         }
-        Expr::Bool(b) => {
+        Expr::Bool(b, _) => {
             emit!(state, Inst::Bool(*b));
             state.stack.push(StackData::Bool);
         }
-        Expr::String(_) => todo!("treat strings"),
-        Expr::Symbol(sym) => match sym {
+        // strings are only for names of bullets in `fire()` and `spawn()`
+        Expr::String(_, span) => return Err(CodegenErrorKind::StringIsNotSupportedHere.at(*span)),
+        Expr::Symbol(sym, span) => match sym {
             Symbol::Ref(bid, sid) => {
-                let sd = match *sid {
-                    StateId::PosX => StackData::Float,
-                    StateId::PosY => StackData::Float,
-                    StateId::InputUp => StackData::Bool,
-                    StateId::InputDown => StackData::Bool,
-                    StateId::InputLeft => StackData::Bool,
-                    StateId::InputRight => StackData::Bool,
-                    StateId::InputShot => StackData::Bool,
-                    StateId::InputSlow => StackData::Bool,
-                    StateId::Enabled => StackData::Bool,
-                };
-                state.stack.push(sd);
+                state.stack.push(sid.r#type().into());
                 emit!(state, Inst::RefRead(*bid, *sid));
             }
             Symbol::Var(Name(name)) => {
-                if let Some((idx, StackData::Var((_, _)))) = state.stack.get(&name[..]) {
+                if let Some((idx, StackData::Var((t, _)))) = state.stack.get(&name[..]) {
                     emit!(state, Inst::Float(idx as f32));
-                    state.stack.push(StackData::Float);
+                    // this is replaced with the variable by `Index`
+                    state.stack.push(t.into());
 
                     emit!(state, Inst::Index);
 
@@ -360,10 +731,10 @@ fn codegen_expr(expr: &Expr, state: &mut CodegenState) -> Result<(), CodegenErro
                     return Ok(());
                 }
 
-                return Err(CodegenError::UnknownVariable(name.to_string()));
+                return Err(CodegenErrorKind::UnknownVariable(name.to_string()).at(*span));
             }
         },
-        Expr::Op2(op, expr1, expr2) => {
+        Expr::Op2(op, expr1, expr2, _) => {
             codegen_expr(expr1, state)?;
             codegen_expr(expr2, state)?;
 
@@ -396,7 +767,7 @@ fn codegen_expr(expr: &Expr, state: &mut CodegenState) -> Result<(), CodegenErro
                 }
             };
         }
-        Expr::If(cond, tru, fls) => {
+        Expr::If(cond, tru, fls, _) => {
             codegen_expr(cond, state)?;
             let _ = state.stack.pop();
             emit!(state, Inst::JumpIfFalse(-10000));
@@ -418,16 +789,20 @@ fn codegen_expr(expr: &Expr, state: &mut CodegenState) -> Result<(), CodegenErro
 
             let _ = state.stack.pop();
         }
-        Expr::ProcCall(Name(name), args) => {
+        Expr::ProcCall(Name(name), args, span) => {
             match codegen_external_op(name, args.to_vec(), state) {
                 Ok(()) => return Ok(()),
-                Err(CodegenError::UndefinedProc(_)) => (),
-                Err(err) => return Err(err),
+                Err(CodegenError {
+                    kind: CodegenErrorKind::UndefinedProc(_),
+                    ..
+                }) => (),
+                Err(err) => return Err(err.or_at(*span)),
             }
 
-            if let None = state.proc_map.borrow().get(name) {
-                return Err(CodegenError::UndefinedProc(name.to_string()));
-            }
+            let ret = match state.proc_map.borrow().get(name) {
+                Some(proc) => proc.signature.ret,
+                None => return Err(CodegenErrorKind::UndefinedProc(name.to_string()).at(*span)),
+            };
 
             for arg in args.iter() {
                 codegen_expr(arg, state)?;
@@ -441,22 +816,239 @@ fn codegen_expr(expr: &Expr, state: &mut CodegenState) -> Result<(), CodegenErro
             emit!(state, Inst::Float(-2000.0)); // dummy proc's address
 
             emit!(state, Inst::Call);
+
+            // args are replaced with the return value, or a dummy value if the proc has no return
+            for _ in 0..args.len() {
+                let _ = state.stack.pop();
+            }
+            state.stack.push(ret.unwrap_or(Type::Float).into());
         }
     };
 
     Ok(())
 }
 
+// Resolves the jump at `from` to go to `to`.
+fn resolve_jump(from: usize, to: usize, state: &mut CodegenState) {
+    let offset = to as i32 - from as i32;
+    *state.code.get_mut(from).unwrap() = match state.code[from] {
+        Inst::JumpIfFalse(_) => Inst::JumpIfFalse(offset),
+        _ => Inst::Jump(offset),
+    };
+}
+
+// Generates the body of a loop. `break` and `continue` in it are returned as `LoopInfo`
+// to be resolved by the loop.
+fn codegen_loop_body(
+    name: &str,
+    ret: Option<Type>,
+    body: &[Body],
+    state: &mut CodegenState,
+) -> Result<LoopInfo, CodegenError> {
+    state.loops.push(LoopInfo::new(state.stack.info.len()));
+    let result = codegen_block(name, ret, body, state);
+    let info = state.loops.pop().unwrap();
+    result?;
+
+    Ok(info)
+}
+
+// Generates `break` or `continue`, which drop locals in the loop body and jump.
+fn codegen_loop_jump(
+    is_break: bool,
+    span: Span,
+    state: &mut CodegenState,
+) -> Result<(), CodegenError> {
+    let depth = match state.loops.last() {
+        Some(info) => info.depth,
+        None if is_break => return Err(CodegenErrorKind::BreakOutsideLoop.at(span)),
+        None => return Err(CodegenErrorKind::ContinueOutsideLoop.at(span)),
+    };
+
+    for _ in depth..state.stack.info.len() {
+        emit!(state, Inst::Drop);
+    }
+    emit!(state, Inst::Jump(-30000));
+
+    let from = state.code.len() - 1;
+    let info = state.loops.last_mut().unwrap();
+    if is_break {
+        info.break_jumps.push(from);
+    } else {
+        info.continue_jumps.push(from);
+    }
+
+    Ok(())
+}
+
+// Generates `for NAME in FROM..TO { ... }`. TO and the loop variable are kept on the stack
+// while looping, and the variable is incremented on the stack top after each iteration.
+// TO is evaluated before FROM to be placed under the variable.
+fn codegen_for(
+    name: &str,
+    ret: Option<Type>,
+    var: &str,
+    (from, to): (&Expr, &Expr),
+    body: &[Body],
+    state: &mut CodegenState,
+) -> Result<(), CodegenError> {
+    codegen_expr(to, state)?;
+    if !matches!(state.stack.pop(), Some(sd) if sd.r#type() == Type::Float) {
+        return Err(CodegenErrorKind::LoopCountIsNotAFloat.at(to.span()));
+    }
+    // the end of the range cannot be referred by its name in the loop
+    state.stack.push(StackData::Float);
+
+    codegen_expr(from, state)?;
+    if !matches!(state.stack.pop(), Some(sd) if sd.r#type() == Type::Float) {
+        return Err(CodegenErrorKind::LoopCountIsNotAFloat.at(from.span()));
+    }
+    state
+        .stack
+        .push(StackData::Var((Type::Float, var.to_string())));
+
+    // loop while `var < to`
+    let loop_head = state.code.len();
+    emit!(state, Inst::Float(0.0));
+    emit!(state, Inst::Index);
+    emit!(state, Inst::Float(2.0));
+    emit!(state, Inst::Index);
+    emit!(state, Inst::Lt);
+    emit!(state, Inst::JumpIfFalse(-10000));
+    let exit_jump_from = state.code.len() - 1;
+
+    let info = codegen_loop_body(name, ret, body, state)?;
+
+    let loop_next = state.code.len();
+    emit!(state, Inst::Float(1.0));
+    emit!(state, Inst::Add);
+    emit!(state, Inst::Jump(-20000));
+    resolve_jump(state.code.len() - 1, loop_head, state);
+
+    let loop_exit = state.code.len();
+    resolve_jump(exit_jump_from, loop_exit, state);
+    for from in info.continue_jumps.into_iter() {
+        resolve_jump(from, loop_next, state);
+    }
+    for from in info.break_jumps.into_iter() {
+        resolve_jump(from, loop_exit, state);
+    }
+
+    emit!(state, Inst::Drop);
+    emit!(state, Inst::Drop);
+    let _ = state.stack.pop();
+    let _ = state.stack.pop();
+
+    Ok(())
+}
+
+// Generates `times N { ... }` as `for #n in 0..N { ... }`.
+fn codegen_times(
+    name: &str,
+    ret: Option<Type>,
+    count: &Expr,
+    body: &[Body],
+    state: &mut CodegenState,
+) -> Result<(), CodegenError> {
+    let from = Expr::Float(0.0, count.span());
+    codegen_for(name, ret, "#n", (&from, count), body, state)
+}
+
+// Generates `while COND { ... }`.
+fn codegen_while(
+    name: &str,
+    ret: Option<Type>,
+    cond: &Expr,
+    body: &[Body],
+    state: &mut CodegenState,
+) -> Result<(), CodegenError> {
+    let loop_head = state.code.len();
+    codegen_expr(cond, state)?;
+    let _ = state.stack.pop();
+    emit!(state, Inst::JumpIfFalse(-10000));
+    let exit_jump_from = state.code.len() - 1;
+
+    let info = codegen_loop_body(name, ret, body, state)?;
+
+    emit!(state, Inst::Jump(-20000));
+    resolve_jump(state.code.len() - 1, loop_head, state);
+
+    let loop_exit = state.code.len();
+    resolve_jump(exit_jump_from, loop_exit, state);
+    for from in info.continue_jumps.into_iter() {
+        resolve_jump(from, loop_head, state);
+    }
+    for from in info.break_jumps.into_iter() {
+        resolve_jump(from, loop_exit, state);
+    }
+
+    Ok(())
+}
+
+// Generates the `if` statement. Each branch is a block so the stack depth is the same
+// after both branches.
+fn codegen_if(
+    name: &str,
+    ret: Option<Type>,
+    cond: &Expr,
+    (then, els): (&[Body], &[Body]),
+    state: &mut CodegenState,
+) -> Result<(), CodegenError> {
+    codegen_expr(cond, state)?;
+    let _ = state.stack.pop();
+    emit!(state, Inst::JumpIfFalse(-10000));
+    let else_jump_from = state.code.len() - 1;
+
+    codegen_block(name, ret, then, state)?;
+
+    if els.is_empty() {
+        resolve_jump(else_jump_from, state.code.len(), state);
+        return Ok(());
+    }
+
+    emit!(state, Inst::Jump(-20000));
+    let end_jump_from = state.code.len() - 1;
+    resolve_jump(else_jump_from, state.code.len(), state);
+
+    codegen_block(name, ret, els, state)?;
+    resolve_jump(end_jump_from, state.code.len(), state);
+
+    Ok(())
+}
+
+// Generates statements in a block. Local variables defined in the block are dropped at its end.
+fn codegen_block(
+    name: &str,
+    ret: Option<Type>,
+    body: &[Body],
+    state: &mut CodegenState,
+) -> Result<(), CodegenError> {
+    let depth = state.stack.info.len();
+    codegen_proc_body(name, ret, body, state)?;
+
+    // locals are already dropped when the block ends with jumps
+    if !matches!(
+        body.last(),
+        Some(Body::Return(_, _) | Body::Break(_) | Body::Continue(_))
+    ) {
+        for _ in depth..state.stack.info.len() {
+            emit!(state, Inst::Drop);
+        }
+    }
+    state.stack.info.truncate(depth);
+
+    Ok(())
+}
+
 fn codegen_proc_body(
     name: &str,
-    arg_num: usize,
     ret: Option<Type>,
     body: &[Body],
     state: &mut CodegenState,
 ) -> Result<(), CodegenError> {
     for b in body.iter() {
         match b {
-            Body::Assignment(sym, expr) => match sym {
+            Body::Assignment(sym, expr, span) => match sym {
                 Symbol::Ref(bid, sid) => {
                     codegen_expr(expr, state)?;
                     let _ = state.stack.pop();
@@ -467,7 +1059,10 @@ fn codegen_proc_body(
                     codegen_expr(expr, state)?;
                     let _ = state.stack.pop();
 
-                    if let Some(mi) = state
+                    if let Some((idx, _)) = state.stack.get(&name[..]) {
+                        emit!(state, Inst::Float(idx as f32));
+                        emit!(state, Inst::Store);
+                    } else if let Some(mi) = state
                         .memory_info
                         .borrow()
                         .iter()
@@ -477,18 +1072,24 @@ fn codegen_proc_body(
 
                         emit!(state, Inst::Write(offset));
                     } else {
-                        return Err(CodegenError::UnknownVariable(name.to_string()));
+                        return Err(CodegenErrorKind::UnknownVariable(name.to_string()).at(*span));
                     }
                 }
             },
-            Body::LexicalDefine(sym, expr) => {
+            Body::LexicalDefine(sym, expr, _) => {
                 let sd: StackData = sym.clone().into();
                 codegen_expr(expr, state)?;
                 // remove StackData::Value of expr to replace Var or State
-                let _ = state.stack.pop();
+                let value = state.stack.pop();
+                let sd = match (sd, value) {
+                    (StackData::Var((_, name)), Some(value)) => {
+                        StackData::Var((value.r#type(), name))
+                    }
+                    (sd, _) => sd,
+                };
                 state.stack.push(sd);
             }
-            Body::Return(val) => {
+            Body::Return(val, _) => {
                 if let Some(expr) = val {
                     codegen_expr(expr, state)?;
                     let _ = state.stack.pop();
@@ -504,11 +1105,21 @@ fn codegen_proc_body(
                         // push dummy value that will be dropped by caller
                         emit!(state, Inst::Float(-4200000.0));
                     }
-                    emit!(state, Inst::Ret(arg_num));
+                    // drops args and locals under the return value
+                    emit!(state, Inst::Ret(state.stack.info.len()));
                 }
 
                 return Ok(());
             }
+            Body::Times(count, body, _) => codegen_times(name, ret, count, body, state)?,
+            Body::While(cond, body, _) => codegen_while(name, ret, cond, body, state)?,
+            Body::For(Name(var), from, to, body, _) => {
+                codegen_for(name, ret, var, (from, to), body, state)?
+            }
+            Body::If(cond, then, els, _) => codegen_if(name, ret, cond, (then, els), state)?,
+            // statements after jumps are never run
+            Body::Break(span) => return codegen_loop_jump(true, *span, state),
+            Body::Continue(span) => return codegen_loop_jump(false, *span, state),
             Body::Expr(expr) => {
                 codegen_expr(expr, state)?;
                 emit!(state, Inst::Drop);
@@ -520,32 +1131,34 @@ fn codegen_proc_body(
     Ok(())
 }
 
-fn insert_return_to_body(name: &str, body: &[Body]) -> Vec<Body> {
+// `span` is used for the inserted return, which has no source code.
+fn insert_return_to_body(name: &str, body: &[Body], span: Span) -> Vec<Body> {
     let mut body = body.to_vec();
     let mut should_be_replaced = false;
 
     if let Some(statement) = body.last() {
         match statement {
-            Body::Return(_) => (),
+            Body::Return(_, _) => (),
             Body::Expr(_) => {
                 if name == "main" {
-                    body.push(Body::Return(None));
+                    body.push(Body::Return(None, span));
                 } else {
                     should_be_replaced = true;
                 }
             }
             _ => {
-                body.push(Body::Return(None));
+                body.push(Body::Return(None, span));
             }
         }
     } else {
-        body.push(Body::Return(None));
+        body.push(Body::Return(None, span));
     }
 
     if should_be_replaced {
         if let Body::Expr(expr) = body[body.len() - 1].clone() {
             let len = body.len();
-            body[len - 1] = Body::Return(Some(*expr.clone()));
+            let span = expr.span();
+            body[len - 1] = Body::Return(Some(*expr.clone()), span);
         }
     }
 
@@ -556,12 +1169,13 @@ fn codegen_proc(
     name: &str,
     sig: &Signature,
     body: &[Body],
+    span: Span,
     state: &mut CodegenState,
 ) -> Result<(), CodegenError> {
     let name = name.to_string();
 
     if let Some(_) = state.proc_map.borrow().get(&name[..]) {
-        return Err(CodegenError::ProcAlreadyDefined(name.to_string()));
+        return Err(CodegenErrorKind::ProcAlreadyDefined(name.to_string()).at(span));
     }
 
     let mut proc_stack = StackInfo::new();
@@ -569,7 +1183,7 @@ fn codegen_proc(
         let sd = match arg.r#type {
             Type::Float => StackData::Var((Type::Float, arg.name.0.clone())),
             Type::Bool => StackData::Var((Type::Bool, arg.name.0.clone())),
-            Type::String => return Err(CodegenError::StringIsNotSupportedHere),
+            Type::String => return Err(CodegenErrorKind::StringIsNotSupportedHere.at(span)),
         };
         proc_stack.push(sd);
     }
@@ -579,14 +1193,8 @@ fn codegen_proc(
     proc_state.stack = proc_stack;
     proc_state.code = vec![];
 
-    let body = insert_return_to_body(&name[..], body);
-    codegen_proc_body(
-        &name[..],
-        sig.args.len(),
-        sig.ret,
-        body.as_slice(),
-        &mut proc_state,
-    )?;
+    let body = insert_return_to_body(&name[..], body, Span::new(span.end, span.end));
+    codegen_proc_body(&name[..], sig.ret, body.as_slice(), &mut proc_state)?;
 
     let mut proc = Proc::new();
     proc.signature = Signature::new(sig.args.to_vec(), sig.ret);
@@ -605,13 +1213,16 @@ fn codegen_syntax_trees(
 ) -> Result<(), CodegenError> {
     for st in stvec.iter() {
         match st {
-            SyntaxTree::DefProc(Name(name), signature, body) => {
-                codegen_proc(name, signature, body, state)?;
+            SyntaxTree::DefProc(Name(name), signature, body, span) => {
+                codegen_proc(name, signature, body, *span, state)?;
             }
-            SyntaxTree::GlobalDefine(Symbol::Var(Name(name)), expr) => match expr {
-                Expr::Float(f) => {
+            SyntaxTree::GlobalDefine(Symbol::Var(Name(name)), expr, span) => match expr {
+                Expr::Float(f, _) => {
                     let mi = MemoryInfo::new(name.to_string(), Type::Float);
                     let offset = mi.calculate_offset(state.memory_info.clone());
+                    if offset + Type::Float.size() > state.memory.len() {
+                        return Err(CodegenErrorKind::MemoryIsFull(name.clone()).at(*span));
+                    }
 
                     state.memory_info.borrow_mut().push(mi);
 
@@ -623,20 +1234,31 @@ fn codegen_syntax_trees(
                         *byte = le_4bytes[idx];
                     }
                 }
-                Expr::Bool(b) => {
+                Expr::Bool(b, _) => {
                     let mi = MemoryInfo::new(name.to_string(), Type::Bool);
                     let offset = mi.calculate_offset(state.memory_info.clone());
+                    if offset + Type::Bool.size() > state.memory.len() {
+                        return Err(CodegenErrorKind::MemoryIsFull(name.clone()).at(*span));
+                    }
 
                     state.memory_info.borrow_mut().push(mi);
 
                     state.memory[offset] = if *b { 1 } else { 0 };
                 }
                 expr => {
-                    return Err(CodegenError::GlobalDefineOnlyAllowsLiteral(expr.clone()));
+                    return Err(CodegenErrorKind::GlobalDefineOnlyAllowsLiteral(Box::new(
+                        expr.clone(),
+                    ))
+                    .at(*span));
                 }
             },
-            SyntaxTree::GlobalDefine(sym, _) => {
-                return Err(CodegenError::GlobalDefineOnlyAllowsToVar(sym.clone()));
+            SyntaxTree::GlobalDefine(sym, _, span) => {
+                return Err(CodegenErrorKind::GlobalDefineOnlyAllowsToVar(sym.clone()).at(*span));
+            }
+            // bullets are compiled into separated programs
+            SyntaxTree::DefBullet(_, _, _, _) => (),
+            SyntaxTree::DefTimeline(Name(name), signature, steps, span) => {
+                codegen_syntax_trees(timeline::expand(name, signature, steps, *span), state)?;
             }
         };
     }
@@ -664,7 +1286,7 @@ fn place_proc_into_code(
             *offset += 1;
         }
     } else {
-        return Err(CodegenError::MainProcIsNotDefined);
+        return Err(CodegenErrorKind::MainProcIsNotDefined.into());
     }
 
     Ok(())
@@ -700,7 +1322,7 @@ fn resolve_jumps_in_proc(
             }
         }
     } else {
-        return Err(CodegenError::MainProcIsNotDefined);
+        return Err(CodegenErrorKind::MainProcIsNotDefined.into());
     }
 
     Ok(())
@@ -720,40 +1342,120 @@ pub struct CodegenResult {
     pub code: Vec<Inst>,
     pub memory: Vec<u8>,
     pub signature: Signature,
+    // bullets defined in the source. their ids follow ids of `compiled_bullet_vec`.
+    pub bullets: Vec<BulletCode>,
 }
 
-pub fn codegen(
-    source: Vec<SyntaxTree>,
+// Generates the program of `bullet`, or of `main` proc if `bullet` is `None`.
+// All programs in a source share procs and globals but have their own memory.
+//
+// Params of the bullet or args of `main` are placed at the head of memory,
+// to be filled when the bullet is fired.
+fn codegen_program(
+    source: &[SyntaxTree],
+    bullet: Option<&SyntaxTree>,
+    bullet_map: Rc<HashMap<String, BulletDef>>,
     compiled_bullet_vec: &Vec<Rc<BulletCode>>,
 ) -> Result<CodegenResult, CodegenError> {
     let proc_map = Rc::new(RefCell::new(HashMap::new()));
     let memory_info = Rc::new(RefCell::new(Vec::new()));
-    let mut state = CodegenState::new(proc_map, memory_info, compiled_bullet_vec);
+    let mut source = source.to_vec();
+
+    let entry = match bullet {
+        Some(SyntaxTree::DefBullet(_, signature, body, span)) => {
+            Some((signature.clone(), body.clone(), *span))
+        }
+        _ => source.iter().find_map(|st| match st {
+            SyntaxTree::DefProc(Name(name), signature, body, span) if name == "main" => {
+                Some((signature.clone(), body.clone(), *span))
+            }
+            _ => None,
+        }),
+    };
+
+    if let Some((signature, body, span)) = &entry {
+        for arg in signature.args.iter() {
+            if arg.r#type == Type::String {
+                return Err(CodegenErrorKind::StringIsNotSupportedHere.at(*span));
+            }
+            let mi = MemoryInfo::new(arg.name.0.clone(), arg.r#type);
+            memory_info.borrow_mut().push(mi);
+        }
+
+        // the entry is run as `main` proc, which takes no args on the stack
+        source
+            .retain(|st| !matches!(st, SyntaxTree::DefProc(Name(name), _, _, _) if name == "main"));
+        source.push(SyntaxTree::DefProc(
+            Name("main".to_string()),
+            Signature::new(vec![], signature.ret),
+            body.clone(),
+            *span,
+        ));
+    }
+
+    let mut state = CodegenState::new(proc_map, memory_info, compiled_bullet_vec, bullet_map);
 
     codegen_pass1_generate_proc_code(source, &mut state)?;
     codegen_pass2_place_proc_code(&mut state)?;
     codegen_pass3_resolve_jumps(&mut state)?;
 
-    let signature = state
-        .proc_map
-        .borrow()
-        .get("main")
-        .unwrap()
-        .signature
-        .clone();
+    let signature = match entry {
+        Some((signature, _, _)) => signature,
+        None => return Err(CodegenErrorKind::MainProcIsNotDefined.into()),
+    };
 
     let result = CodegenResult {
         code: state.code,
         memory: state.memory,
         signature,
+        bullets: Vec::new(),
     };
 
     Ok(result)
 }
 
+pub fn codegen(
+    source: Vec<SyntaxTree>,
+    compiled_bullet_vec: &Vec<Rc<BulletCode>>,
+) -> Result<CodegenResult, CodegenError> {
+    let mut bullet_map = HashMap::new();
+    for st in source.iter() {
+        if let SyntaxTree::DefBullet(Name(name), signature, _, span) = st {
+            if bullet_map.contains_key(name) {
+                return Err(CodegenErrorKind::BulletAlreadyDefined(name.to_string()).at(*span));
+            }
+
+            let id = compiled_bullet_vec.len() + bullet_map.len();
+            let signature = signature.clone();
+            bullet_map.insert(name.to_string(), BulletDef { id, signature });
+        }
+    }
+    let bullet_map = Rc::new(bullet_map);
+
+    let mut bullets = Vec::new();
+    for st in source.iter() {
+        if let SyntaxTree::DefBullet(Name(name), _, _, _) = st {
+            let result =
+                codegen_program(&source, Some(st), bullet_map.clone(), compiled_bullet_vec)?;
+            bullets.push(BulletCode {
+                id: bullet_map.get(name).unwrap().id,
+                name: name.to_string(),
+                code: Rc::new(result.code),
+                initial_memory: result.memory,
+                signature: result.signature,
+            });
+        }
+    }
+
+    let mut result = codegen_program(&source, None, bullet_map, compiled_bullet_vec)?;
+    result.bullets = bullets;
+
+    Ok(result)
+}
+
 #[cfg(test)]
 mod codegen_test {
-    use lang_component::bullet::BulletId;
+    use lang_component::{bullet::BulletId, syntax::Arg};
 
     use super::super::parse::parse;
     use super::super::tokenize::tokenize;
@@ -1103,7 +1805,7 @@ mod codegen_test {
             vec![
                 Inst::RefRead(BulletId::Itself, StateId::PosX),
                 Inst::RefRead(BulletId::Itself, StateId::PosY),
-                Inst::Operate(ExternalOperation::Fire(1)),
+                Inst::Operate(ExternalOperation::Fire(1, 0)),
                 Inst::Drop,
                 Inst::Term,
             ],
@@ -1114,4 +1816,674 @@ mod codegen_test {
             "##,
         );
     }
+
+    #[test]
+    fn test_codegen_times() {
+        test_codegen(
+            vec![
+                Inst::Float(2.0),
+                Inst::Float(0.0),
+                // while #n < 2
+                Inst::Float(0.0),
+                Inst::Index,
+                Inst::Float(2.0),
+                Inst::Index,
+                Inst::Lt,
+                Inst::JumpIfFalse(14),
+                // let d = #n * 10
+                Inst::Float(0.0),
+                Inst::Index,
+                Inst::Float(10.0),
+                Inst::Mul,
+                // fire("bullet_1", d, 0)
+                Inst::Float(0.0),
+                Inst::Index,
+                Inst::Float(0.0),
+                Inst::Operate(ExternalOperation::Fire(1, 0)),
+                Inst::Drop,
+                // drop d and increment #n
+                Inst::Drop,
+                Inst::Float(1.0),
+                Inst::Add,
+                Inst::Jump(-18),
+                Inst::Drop,
+                Inst::Drop,
+                Inst::Term,
+            ],
+            r##"
+            proc main() {
+              times 2 {
+                let d = #n * 10
+                fire("bullet_1", d, 0)
+              }
+            }
+            "##,
+        );
+    }
+
+    #[test]
+    fn test_codegen_nested_times() {
+        test_codegen(
+            vec![
+                Inst::Float(2.0),
+                Inst::Float(0.0),
+                Inst::Float(0.0),
+                Inst::Index,
+                Inst::Float(2.0),
+                Inst::Index,
+                Inst::Lt,
+                Inst::JumpIfFalse(20),
+                Inst::Float(3.0),
+                Inst::Float(0.0),
+                Inst::Float(0.0),
+                Inst::Index,
+                Inst::Float(2.0),
+                Inst::Index,
+                Inst::Lt,
+                Inst::JumpIfFalse(7),
+                // `#n` is the counter of the inner loop
+                Inst::Float(0.0),
+                Inst::Index,
+                Inst::RefWrite(BulletId::Itself, StateId::PosX),
+                Inst::Float(1.0),
+                Inst::Add,
+                Inst::Jump(-11),
+                Inst::Drop,
+                Inst::Drop,
+                Inst::Float(1.0),
+                Inst::Add,
+                Inst::Jump(-24),
+                Inst::Drop,
+                Inst::Drop,
+                Inst::Term,
+            ],
+            r##"
+            proc main() {
+              times 2 {
+                times 3 {
+                  self.x = #n
+                }
+              }
+            }
+            "##,
+        );
+    }
+
+    #[test]
+    fn test_codegen_times_with_bool_count() {
+        let result = test_codegen_with_bullets(
+            r##"
+            proc main() {
+              times true {}
+            }
+            "##,
+        );
+        assert!(matches!(
+            result,
+            Err(CodegenError {
+                kind: CodegenErrorKind::LoopCountIsNotAFloat,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_codegen_string_in_value() {
+        let result = test_codegen_with_bullets(
+            r##"
+            proc main() {
+              let s = "abc"
+            }
+            "##,
+        );
+        assert!(matches!(
+            result,
+            Err(CodegenError {
+                kind: CodegenErrorKind::StringIsNotSupportedHere,
+                span: Some(_),
+            })
+        ));
+    }
+
+    #[test]
+    fn test_codegen_while_with_break() {
+        test_codegen(
+            vec![
+                Inst::Float(3.0),
+                // while n > 0
+                Inst::Float(0.0),
+                Inst::Index,
+                Inst::Float(0.0),
+                Inst::Gt,
+                Inst::JumpIfFalse(12),
+                // let d = n
+                Inst::Float(0.0),
+                Inst::Index,
+                // n = n - 1
+                Inst::Float(1.0),
+                Inst::Index,
+                Inst::Float(1.0),
+                Inst::Sub,
+                Inst::Float(1.0),
+                Inst::Store,
+                // break drops d
+                Inst::Drop,
+                Inst::Jump(2),
+                Inst::Jump(-15),
+                Inst::Drop,
+                Inst::Term,
+            ],
+            r##"
+            proc main() {
+              let n = 3
+              while n > 0 {
+                let d = n
+                n = n - 1
+                break
+              }
+            }
+            "##,
+        );
+    }
+
+    #[test]
+    fn test_codegen_for_with_continue() {
+        test_codegen(
+            vec![
+                Inst::Float(5.0),
+                Inst::Float(1.0),
+                // while i < 5
+                Inst::Float(0.0),
+                Inst::Index,
+                Inst::Float(2.0),
+                Inst::Index,
+                Inst::Lt,
+                Inst::JumpIfFalse(8),
+                Inst::Float(0.0),
+                Inst::Index,
+                Inst::RefWrite(BulletId::Itself, StateId::PosX),
+                Inst::Jump(1),
+                // i = i + 1
+                Inst::Float(1.0),
+                Inst::Add,
+                Inst::Jump(-12),
+                Inst::Drop,
+                Inst::Drop,
+                Inst::Term,
+            ],
+            r##"
+            proc main() {
+              for i in 1..5 {
+                self.x = i
+                continue
+              }
+            }
+            "##,
+        );
+    }
+
+    #[test]
+    fn test_codegen_break_outside_loop() {
+        let result = test_codegen_with_bullets(
+            r##"
+            proc main() {
+              break
+            }
+            "##,
+        );
+        assert!(matches!(
+            result,
+            Err(CodegenError {
+                kind: CodegenErrorKind::BreakOutsideLoop,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_codegen_if_statement() {
+        test_codegen(
+            vec![
+                Inst::RefRead(BulletId::Itself, StateId::PosX),
+                Inst::Float(0.0),
+                Inst::Gt,
+                Inst::JumpIfFalse(7),
+                // let d = 1 is dropped at the end of the branch
+                Inst::Float(1.0),
+                Inst::Float(0.0),
+                Inst::Index,
+                Inst::RefWrite(BulletId::Itself, StateId::PosY),
+                Inst::Drop,
+                Inst::Jump(5),
+                // else if
+                Inst::Bool(false),
+                Inst::JumpIfFalse(3),
+                Inst::Operate(ExternalOperation::Die),
+                Inst::Drop,
+                Inst::Term,
+            ],
+            r##"
+            proc main() {
+              if self.x > 0 {
+                let d = 1
+                self.y = d
+              } else if false {
+                die()
+              }
+            }
+            "##,
+        );
+    }
+
+    #[test]
+    fn test_codegen_math_builtins() {
+        test_codegen(
+            vec![
+                Inst::Float(1.0),
+                Inst::Float(2.0),
+                Inst::Atan2,
+                Inst::Cos,
+                Inst::Float(3.0),
+                Inst::Abs,
+                Inst::Max,
+                Inst::RefWrite(BulletId::Itself, StateId::PosX),
+                Inst::Term,
+            ],
+            r##"
+            proc main() {
+              self.x = max(cos(atan2(1, 2)), abs(3))
+            }
+            "##,
+        );
+
+        let result = test_codegen_with_bullets(
+            r##"
+            proc main() {
+              self.x = sin(true)
+            }
+            "##,
+        );
+        assert!(matches!(
+            result,
+            Err(CodegenError {
+                kind: CodegenErrorKind::WrongTypeWhileInvokingExternalOp,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_codegen_rand() {
+        test_codegen(
+            vec![
+                Inst::Float(10.0),
+                Inst::Float(20.0),
+                Inst::Float(1.0),
+                Inst::Index,
+                Inst::Sub,
+                Inst::Rand,
+                Inst::Mul,
+                Inst::Add,
+                Inst::Rand,
+                Inst::Add,
+                Inst::RefWrite(BulletId::Itself, StateId::PosX),
+                Inst::Term,
+            ],
+            r##"
+            proc main() {
+              self.x = rand_range(10, 20) + rand()
+            }
+            "##,
+        );
+    }
+
+    #[test]
+    fn test_codegen_frame_counters() {
+        test_codegen(
+            vec![
+                Inst::RefRead(BulletId::Itself, StateId::Age),
+                Inst::RefRead(BulletId::Player, StateId::Frames),
+                Inst::Add,
+                Inst::RefWrite(BulletId::Itself, StateId::PosX),
+                Inst::Term,
+            ],
+            r##"
+            proc main() {
+              self.x = self.age + player.frames
+            }
+            "##,
+        );
+    }
+
+    #[test]
+    fn test_codegen_motion_states() {
+        test_codegen(
+            vec![
+                Inst::Float(3.0),
+                Inst::RefWrite(BulletId::Itself, StateId::Speed),
+                Inst::RefRead(BulletId::Itself, StateId::Angle),
+                Inst::RefRead(BulletId::Itself, StateId::AngularVelocity),
+                Inst::Add,
+                Inst::RefWrite(BulletId::Itself, StateId::Angle),
+                Inst::Term,
+            ],
+            r##"
+            proc main() {
+              self.speed = 3
+              self.angle = self.angle + self.angular_velocity
+            }
+            "##,
+        );
+    }
+
+    #[test]
+    fn test_codegen_wait() {
+        test_codegen(
+            vec![
+                Inst::Float(30.0),
+                Inst::Yield,
+                Inst::Drop,
+                Inst::Float(1.0),
+                Inst::Yield,
+                Inst::Drop,
+                Inst::Operate(ExternalOperation::Die),
+                Inst::Drop,
+                Inst::Term,
+            ],
+            r##"
+            proc main() {
+              wait(30)
+              yield()
+              die()
+            }
+            "##,
+        );
+    }
+
+    #[test]
+    fn test_codegen_spawn() {
+        let result = test_codegen_with_bullets(
+            r##"
+            bullet enemy(hp: float) {}
+
+            proc main() {
+              spawn(self.x, 0, enemy(20))
+              spawn("bullet_0", 100, 0)
+            }
+            "##,
+        )
+        .unwrap();
+
+        assert_eq!(
+            result.code,
+            vec![
+                Inst::RefRead(BulletId::Itself, StateId::PosX),
+                Inst::Float(0.0),
+                Inst::Float(20.0),
+                Inst::Operate(ExternalOperation::Spawn(1, 1)),
+                Inst::Drop,
+                Inst::Float(100.0),
+                Inst::Float(0.0),
+                Inst::Operate(ExternalOperation::Spawn(0, 0)),
+                Inst::Drop,
+                Inst::Term,
+            ]
+        );
+    }
+
+    fn test_codegen_with_bullets(string: &str) -> Result<CodegenResult, CodegenError> {
+        let compiled_bullet_vec = vec![Rc::new(BulletCode::new("bullet_0"))];
+        let (_, tokens) = tokenize(string).unwrap();
+        let (_, stvec) = parse(&tokens).unwrap();
+
+        codegen(stvec, &compiled_bullet_vec)
+    }
+
+    #[test]
+    fn test_codegen_fire_bullet_defined_in_source() {
+        let result = test_codegen_with_bullets(
+            r##"
+            global speed = 2
+
+            bullet linear(dir: float, v: float) {
+              self.x = self.x + dir * v * speed
+            }
+
+            proc main() {
+              let d = 1.0
+              fire(self.x, self.y, linear(d, 5))
+            }
+            "##,
+        )
+        .unwrap();
+
+        assert_eq!(
+            result.code,
+            vec![
+                Inst::Float(1.0),
+                Inst::RefRead(BulletId::Itself, StateId::PosX),
+                Inst::RefRead(BulletId::Itself, StateId::PosY),
+                Inst::Float(2.0),
+                Inst::Index,
+                Inst::Float(5.0),
+                Inst::Operate(ExternalOperation::Fire(1, 2)),
+                Inst::Drop,
+                Inst::Drop,
+                Inst::Term,
+            ]
+        );
+
+        assert_eq!(result.bullets.len(), 1);
+        let bullet = &result.bullets[0];
+        assert_eq!(bullet.id, 1);
+        assert_eq!(bullet.name, "linear");
+        assert_eq!(bullet.signature.args.len(), 2);
+        // params are at the head of memory and globals follow them
+        assert_eq!(
+            *bullet.code,
+            vec![
+                Inst::RefRead(BulletId::Itself, StateId::PosX),
+                Inst::Read(0, Type::Float),
+                Inst::Read(4, Type::Float),
+                Inst::Mul,
+                Inst::Read(8, Type::Float),
+                Inst::Mul,
+                Inst::Add,
+                Inst::RefWrite(BulletId::Itself, StateId::PosX),
+                Inst::Term,
+            ]
+        );
+        assert_eq!(bullet.initial_memory[8..12], 2.0f32.to_le_bytes());
+    }
+
+    #[test]
+    fn test_codegen_fire_bullet_with_wrong_params() {
+        let result = test_codegen_with_bullets(
+            r##"
+            bullet linear(dir: float) {}
+
+            proc main() {
+              fire(self.x, self.y, linear(true))
+            }
+            "##,
+        );
+        assert!(matches!(
+            result,
+            Err(CodegenError {
+                kind: CodegenErrorKind::WrongParamTypeWhileFiringBullet(_),
+                ..
+            })
+        ));
+
+        let result = test_codegen_with_bullets(
+            r##"
+            bullet linear(dir: float) {}
+
+            proc main() {
+              fire(self.x, self.y, linear(1, 2))
+            }
+            "##,
+        );
+        assert!(matches!(
+            result,
+            Err(CodegenError {
+                kind: CodegenErrorKind::WrongParamNumberWhileFiringBullet(_),
+                ..
+            })
+        ));
+    }
+
+    fn test_codegen_fire_by_name(string: &str) -> Result<CodegenResult, CodegenError> {
+        let mut linear = BulletCode::new("linear");
+        linear.signature = Signature::new(
+            vec![
+                Arg::new("dir".to_string(), Type::Float),
+                Arg::new("speed".to_string(), Type::Float),
+            ],
+            None,
+        );
+        let compiled_bullet_vec = vec![Rc::new(BulletCode::new("bullet_0")), Rc::new(linear)];
+        let (_, tokens) = tokenize(string).unwrap();
+        let (_, stvec) = parse(&tokens).unwrap();
+
+        codegen(stvec, &compiled_bullet_vec)
+    }
+
+    #[test]
+    fn test_codegen_fire_by_name_with_params() {
+        let result = test_codegen_fire_by_name(
+            r##"
+            proc main() {
+              fire("linear", self.x, self.y, 90, 2.5)
+            }
+            "##,
+        )
+        .unwrap();
+        assert_eq!(
+            result.code,
+            vec![
+                Inst::RefRead(BulletId::Itself, StateId::PosX),
+                Inst::RefRead(BulletId::Itself, StateId::PosY),
+                Inst::Float(90.0),
+                Inst::Float(2.5),
+                Inst::Operate(ExternalOperation::Fire(1, 2)),
+                Inst::Drop,
+                Inst::Term,
+            ]
+        );
+
+        let result = test_codegen_fire_by_name(
+            r##"
+            proc main() {
+              fire("linear", self.x, self.y, 90)
+            }
+            "##,
+        );
+        assert!(matches!(
+            result,
+            Err(CodegenError {
+                kind: CodegenErrorKind::WrongParamNumberWhileFiringBullet(_),
+                ..
+            })
+        ));
+
+        let result = test_codegen_fire_by_name(
+            r##"
+            proc main() {
+              fire("linear", self.x, self.y, 90, false)
+            }
+            "##,
+        );
+        assert!(matches!(
+            result,
+            Err(CodegenError {
+                kind: CodegenErrorKind::WrongParamTypeWhileFiringBullet(_),
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_codegen_main_proc_params() {
+        let result = test_codegen_with_bullets(
+            r##"
+            global speed = 2
+
+            proc main(dir: float) {
+              self.x = dir * speed
+            }
+            "##,
+        )
+        .unwrap();
+
+        assert_eq!(result.signature.args.len(), 1);
+        // params of main are placed at the head of memory like bullet params
+        assert_eq!(
+            result.code,
+            vec![
+                Inst::Read(0, Type::Float),
+                Inst::Read(4, Type::Float),
+                Inst::Mul,
+                Inst::RefWrite(BulletId::Itself, StateId::PosX),
+                Inst::Term,
+            ]
+        );
+        assert_eq!(result.memory[4..8], 2.0f32.to_le_bytes());
+    }
+
+    #[test]
+    fn test_codegen_timeline() {
+        let result = test_codegen_with_bullets(
+            r##"
+            global a = 1
+
+            timeline pattern() [
+              wait(2) => nil
+              a > 0 => die()
+              repeat(3) => nil
+              frames_elapsed(10) => nil
+            ]
+
+            global b = 2
+
+            proc main() {
+              pattern()
+            }
+            "##,
+        )
+        .unwrap();
+
+        // hidden globals of the step and counters are placed in order of definitions
+        assert_eq!(result.memory[0..4], 1.0f32.to_le_bytes());
+        assert_eq!(result.memory[4..20], [0; 16]);
+        assert_eq!(result.memory[20..24], 2.0f32.to_le_bytes());
+        // the timeline is a proc starting with checking its step
+        assert_eq!(
+            result.code[0..5],
+            [
+                Inst::Float(4.0),
+                Inst::Call,
+                Inst::Drop,
+                Inst::Term,
+                Inst::Read(4, Type::Float),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_codegen_timeline_memory_is_full() {
+        let globals: Vec<String> = (0..31).map(|i| format!("global g{} = 0", i)).collect();
+        let source = format!(
+            "{}\ntimeline t() [\n  wait(1) => nil\n]\nproc main() {{ t() }}",
+            globals.join("\n")
+        );
+
+        match test_codegen_with_bullets(&source) {
+            Err(CodegenError {
+                kind: CodegenErrorKind::MemoryIsFull(name),
+                ..
+            }) => assert_eq!(name, "#t.1"),
+            result => panic!("unexpected result: {:?}", result.map(|r| r.code)),
+        }
+    }
 }
@@ -0,0 +1,154 @@
+use std::fmt;
+
+use lang_component::span::Span;
+
+use crate::CompileError;
+
+// Renders a `CompileError` with the offending source line, like:
+//
+//     error: unknown variable `vx`
+//      --> bullet.bl:3:12
+//       |
+//     3 |   self.x = vx
+//       |            ^^
+//
+// Parser errors also print their outer errors in the chain as notes, from inner to outer.
+pub struct Diagnostic<'a> {
+    name: &'a str,
+    source: &'a str,
+    error: &'a CompileError,
+}
+
+impl<'a> Diagnostic<'a> {
+    // `name` is a script name (e.g. a file path) printed with the error position.
+    pub fn new(name: &'a str, source: &'a str, error: &'a CompileError) -> Self {
+        Self {
+            name,
+            source,
+            error,
+        }
+    }
+
+    // Returns the outer parser errors from inner to outer.
+    fn notes(&self) -> Vec<(String, Option<Span>)> {
+        let mut notes = Vec::new();
+
+        if let CompileError::ParseError(err) = self.error {
+            let mut err = err;
+            while let Some(parent) = &err.parent {
+                notes.push((err.kind.to_string(), err.span));
+                err = parent;
+            }
+        }
+        notes.reverse();
+
+        notes
+    }
+
+    fn source_lines(&self, span: &Span) -> Vec<String> {
+        let line = match self.source.lines().nth(span.start.line - 1) {
+            Some(line) => line,
+            None => return Vec::new(),
+        };
+        let lineno = span.start.line.to_string();
+        let gutter = " ".repeat(lineno.len());
+
+        // keep tabs in the line to put the caret under the right column
+        let indent: String = line
+            .chars()
+            .take(span.start.column - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let width = if span.end.line == span.start.line {
+            span.end.column.saturating_sub(span.start.column)
+        } else {
+            line.chars().count().saturating_sub(span.start.column - 1)
+        };
+
+        vec![
+            format!("{} |", gutter),
+            format!("{} | {}", lineno, line),
+            format!("{} | {}{}", gutter, indent, "^".repeat(width.max(1))),
+        ]
+    }
+}
+
+impl<'a> fmt::Display for Diagnostic<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut lines = vec![format!("error: {}", self.error.message())];
+
+        match self.error.span() {
+            Some(span) => {
+                lines.push(format!(
+                    " --> {}:{}:{}",
+                    self.name, span.start.line, span.start.column
+                ));
+                lines.extend(self.source_lines(&span));
+            }
+            None => lines.push(format!(" --> {}", self.name)),
+        }
+
+        for (note, span) in self.notes() {
+            match span {
+                Some(span) => lines.push(format!(
+                    "  = note: {} (at {}:{})",
+                    note, span.start.line, span.start.column
+                )),
+                None => lines.push(format!("  = note: {}", note)),
+            }
+        }
+
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod diagnostic_test {
+    use super::*;
+    use crate::compile;
+
+    fn render(source: &str) -> String {
+        match compile(source.to_string(), &Vec::new()) {
+            Ok(_) => panic!("compilation should fail: {}", source),
+            Err(err) => Diagnostic::new("test.bl", source, &err).to_string(),
+        }
+    }
+
+    #[test]
+    fn test_diagnostic_codegen_error() {
+        let expected = r#"error: unknown variable `vx`
+ --> test.bl:2:12
+  |
+2 |   self.x = vx
+  |            ^^"#;
+        assert_eq!(render("proc main() {\n  self.x = vx\n}"), expected);
+    }
+
+    #[test]
+    fn test_diagnostic_parse_error() {
+        let expected = r#"error: unexpected `=`
+ --> test.bl:2:7
+  |
+2 |   let = 1.0
+  |       ^
+  = note: invalid local definition, expected `let NAME = EXPR` (at 2:3)
+  = note: invalid proc definition (at 1:1)"#;
+        assert_eq!(render("proc main() {\n  let = 1.0\n}"), expected);
+    }
+
+    #[test]
+    fn test_diagnostic_tokenizer_error() {
+        let expected = r#"error: unexpected character
+ --> test.bl:1:14
+  |
+1 | global x = 1 ~
+  |              ^"#;
+        assert_eq!(render("global x = 1 ~"), expected);
+    }
+
+    #[test]
+    fn test_diagnostic_without_span() {
+        let expected = "error: proc `main` is not defined\n --> test.bl";
+        assert_eq!(render("global a = 1.0"), expected);
+    }
+}
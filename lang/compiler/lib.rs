@@ -1,17 +1,25 @@
 mod codegen;
+mod diagnostic;
 mod parse;
+mod timeline;
 mod tokenize;
 
-use std::rc::Rc;
+use std::{fmt, rc::Rc};
 
 use nom::{error::ErrorKind, Err};
 
-use lang_component::{syntax::Signature, vm::Inst};
+use lang_component::{span::Span, syntax::Signature, vm::Inst};
 
 use crate::{
-    codegen::{codegen, CodegenError, CodegenResult},
-    parse::{parse, ParserError},
-    tokenize::tokenize,
+    codegen::{codegen, CodegenResult},
+    parse::parse,
+    tokenize::{position, tokenize},
+};
+
+pub use crate::{
+    codegen::{CodegenError, CodegenErrorKind},
+    diagnostic::Diagnostic,
+    parse::ParserError,
 };
 
 #[derive(Debug, Clone)]
@@ -38,6 +46,16 @@ impl BulletCode {
 #[derive(Debug)]
 pub struct TokenizerError {
     pub kind: ErrorKind,
+    pub span: Span,
+}
+
+impl fmt::Display for TokenizerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            ErrorKind::TakeUntil => write!(f, "unterminated block comment"),
+            _ => write!(f, "unexpected character"),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -47,19 +65,72 @@ pub enum CompileError {
     CodegenError(CodegenError),
 }
 
+impl CompileError {
+    // Returns the span of the error. Parser errors point the innermost error in the chain.
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            CompileError::TokenizeError(err) => Some(err.span),
+            CompileError::ParseError(err) => {
+                let mut err = err;
+                while let Some(parent) = &err.parent {
+                    err = parent;
+                }
+                err.span
+            }
+            CompileError::CodegenError(err) => err.span,
+        }
+    }
+
+    // Returns the explanation of the error without its position.
+    pub(crate) fn message(&self) -> String {
+        match self {
+            CompileError::TokenizeError(err) => err.to_string(),
+            CompileError::ParseError(err) => {
+                let mut err = err;
+                while let Some(parent) = &err.parent {
+                    err = parent;
+                }
+                err.kind.to_string()
+            }
+            CompileError::CodegenError(err) => err.kind.to_string(),
+        }
+    }
+}
+
+// Prints a one-line message. Use `Diagnostic` to print it with the source code.
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message())?;
+
+        match self.span() {
+            Some(span) => write!(f, " at {}:{}", span.start.line, span.start.column),
+            None => Ok(()),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct CompileResult {
     pub code: Vec<Inst>,
     pub memory: Vec<u8>,
     pub signature: Signature,
+    // bullets defined with `bullet` in the source. they must be registered in order just
+    // after `code_vec` passed to `compile()`, because their ids are decided at compile time.
+    pub bullets: Vec<BulletCode>,
 }
 
 impl CompileResult {
-    fn new(code: Vec<Inst>, memory: Vec<u8>, signature: Signature) -> Self {
+    fn new(
+        code: Vec<Inst>,
+        memory: Vec<u8>,
+        signature: Signature,
+        bullets: Vec<BulletCode>,
+    ) -> Self {
         Self {
             code,
             memory,
             signature,
+            bullets,
         }
     }
 }
@@ -75,15 +146,20 @@ pub fn compile(
                     code,
                     memory,
                     signature,
-                }) => Ok(CompileResult::new(code, memory, signature)),
+                    bullets,
+                }) => Ok(CompileResult::new(code, memory, signature, bullets)),
                 Err(err) => Err(CompileError::CodegenError(err)),
             },
             Err(Err::Error(err)) => Err(CompileError::ParseError(err.purge_input().unwrap())),
             Err(err) => panic!("parse error = {:?}", err),
         },
-        Err(Err::Error(err)) => Err(CompileError::TokenizeError(TokenizerError {
-            kind: err.code,
-        })),
+        Err(Err::Error(err)) | Err(Err::Failure(err)) => {
+            let pos = position(&source[..], err.input);
+            Err(CompileError::TokenizeError(TokenizerError {
+                kind: err.code,
+                span: Span::new(pos, pos),
+            }))
+        }
         Err(err) => panic!("tokenizer error = {:?}", err),
     }
 }
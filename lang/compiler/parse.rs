@@ -1,12 +1,14 @@
+use std::fmt;
+
 use nom::{
     branch::alt,
     combinator::{all_consuming, map, opt, peek, rest_len},
     multi::many0,
     sequence::{delimited, preceded, terminated, tuple},
-    Err, IResult,
+    Err, IResult, InputLength,
 };
 
-use lang_component::{bullet::*, syntax::*, token::*};
+use lang_component::{bullet::*, span::Span, syntax::*, token::*};
 
 // ErrorKinds for this bullet-hell lang.
 #[derive(Debug, Clone)]
@@ -18,14 +20,62 @@ pub enum ErrorKind {
     InvalidGlobalDefine,
     InvalidExpr,
     InvalidDefProc,
+    InvalidDefBullet,
+    InvalidDefTimeline,
+    InvalidTimelineStep,
     InvalidLexicalDefine,
+    InvalidTimes,
+    InvalidWhile,
+    InvalidFor,
+    InvalidIf,
     InvalidProcCall,
     EmptyName,
     NotAnExprTerm,
-    UnknownBulletId(String),
     UnknownStateId(String),
 }
 
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorKind::Nom(kind) => write!(f, "syntax error ({})", kind.description()),
+            ErrorKind::UnexpectedToken(token) => write!(f, "unexpected {}", token),
+            ErrorKind::UnexpectedEOF => write!(f, "unexpected end of file"),
+            ErrorKind::CannotParseExpression => write!(f, "cannot parse the expression"),
+            ErrorKind::InvalidGlobalDefine => {
+                write!(
+                    f,
+                    "invalid global definition, expected `global NAME = VALUE`"
+                )
+            }
+            ErrorKind::InvalidExpr => write!(f, "invalid expression"),
+            ErrorKind::InvalidDefProc => write!(f, "invalid proc definition"),
+            ErrorKind::InvalidDefBullet => write!(f, "invalid bullet definition"),
+            ErrorKind::InvalidDefTimeline => write!(f, "invalid timeline definition"),
+            ErrorKind::InvalidTimelineStep => {
+                write!(f, "invalid timeline step, expected `COND => ACTION`")
+            }
+            ErrorKind::InvalidLexicalDefine => {
+                write!(f, "invalid local definition, expected `let NAME = EXPR`")
+            }
+            ErrorKind::InvalidTimes => write!(f, "invalid loop, expected `times N {{ ... }}`"),
+            ErrorKind::InvalidWhile => write!(f, "invalid loop, expected `while COND {{ ... }}`"),
+            ErrorKind::InvalidFor => {
+                write!(f, "invalid loop, expected `for NAME in FROM..TO {{ ... }}`")
+            }
+            ErrorKind::InvalidIf => {
+                write!(
+                    f,
+                    "invalid if statement, expected `if COND {{ ... }} else {{ ... }}`"
+                )
+            }
+            ErrorKind::InvalidProcCall => write!(f, "invalid proc call"),
+            ErrorKind::EmptyName => write!(f, "a name is expected"),
+            ErrorKind::NotAnExprTerm => write!(f, "expected a value, a variable or a proc call"),
+            ErrorKind::UnknownStateId(name) => write!(f, "unknown bullet state `{}`", name),
+        }
+    }
+}
+
 // Represents parser errors.
 #[derive(Debug)]
 pub struct ParseError<I> {
@@ -34,9 +84,11 @@ pub struct ParseError<I> {
     pub parent: Option<Box<ParseError<I>>>,
 }
 
+// Represents parser errors without inputs. `span` points the token where the error occurs.
 #[derive(Debug)]
 pub struct ParserError {
     pub kind: ErrorKind,
+    pub span: Option<Span>,
     pub parent: Option<Box<ParserError>>,
 }
 
@@ -48,10 +100,13 @@ impl<I> ParseError<I> {
             parent,
         }
     }
+}
 
+impl<'a> ParseError<Input<'a>> {
     pub fn purge_input(&self) -> Option<ParserError> {
         let pe = ParserError {
             kind: self.kind.clone(),
+            span: self.input.first().map(|t| t.span),
             parent: if let Some(p) = &self.parent {
                 Some(Box::new((*p).purge_input().unwrap()))
             } else {
@@ -63,7 +118,26 @@ impl<I> ParseError<I> {
     }
 }
 
-impl<I> nom::error::ParseError<I> for ParseError<I> {
+impl<I: InputLength> ParseError<I> {
+    // Returns the length of the input remaining at the innermost error.
+    fn rest_len(&self) -> usize {
+        match &self.parent {
+            Some(parent) => parent.rest_len(),
+            None => self.input.input_len(),
+        }
+    }
+
+    // Wraps `err` with `kind` if the parser failed after consuming some of `t`.
+    fn context(t: I, kind: ErrorKind, err: Self) -> Self {
+        if err.rest_len() < t.input_len() {
+            ParseError::new(t, kind, Some(Box::new(err)))
+        } else {
+            err
+        }
+    }
+}
+
+impl<I: InputLength> nom::error::ParseError<I> for ParseError<I> {
     fn from_error_kind(input: I, kind: nom::error::ErrorKind) -> Self {
         ParseError {
             input: input,
@@ -75,23 +149,46 @@ impl<I> nom::error::ParseError<I> for ParseError<I> {
     fn append(_: I, _: nom::error::ErrorKind, other: Self) -> Self {
         other
     }
+
+    // Reports the error of the alternative which went further.
+    fn or(self, other: Self) -> Self {
+        if other.rest_len() < self.rest_len() {
+            other
+        } else {
+            self
+        }
+    }
 }
 
-pub type Input<'a> = &'a [Token];
+pub type Input<'a> = &'a [SpannedToken];
 
 type CombinatorResult<'a> = IResult<Input<'a>, &'a Token, ParseError<Input<'a>>>;
 
+// Returns the span of tokens consumed from `t` to `rest`. Trailing newlines are not included.
+fn span_of<'a>(t: Input<'a>, rest: Input<'a>) -> Span {
+    let mut consumed = t.len() - rest.len();
+    while consumed > 1 && t[consumed - 1].token == Token::Newline {
+        consumed -= 1;
+    }
+
+    match (t.first(), consumed) {
+        (Some(first), 0) => Span::new(first.span.start, first.span.start),
+        (Some(first), _) => first.span.join(&t[consumed - 1].span),
+        (None, _) => Span::default(),
+    }
+}
+
 fn token<'a>(token: Token) -> impl Fn(Input<'a>) -> CombinatorResult<'a> {
     move |t: Input<'a>| {
         let (t, len) = rest_len(t)?;
 
         if len > 0 {
-            if &t[0] == &token {
-                Ok((&t[1..], &t[0]))
+            if &t[0].token == &token {
+                Ok((&t[1..], &t[0].token))
             } else {
                 Err(Err::Error(ParseError::new(
                     &t[..],
-                    ErrorKind::UnexpectedToken(t[0].clone()),
+                    ErrorKind::UnexpectedToken(t[0].token.clone()),
                     None,
                 )))
             }
@@ -105,16 +202,16 @@ fn token<'a>(token: Token) -> impl Fn(Input<'a>) -> CombinatorResult<'a> {
     }
 }
 
-fn token_type<'a>(token: Token) -> impl Fn(&'a [Token]) -> CombinatorResult<'a> {
+fn token_type<'a>(token: Token) -> impl Fn(Input<'a>) -> CombinatorResult<'a> {
     move |t| {
         let (t, len) = rest_len(t)?;
         if len > 0 {
-            if token_type_eq(&t[0], &token) {
-                Ok((&t[1..], &t[0]))
+            if token_type_eq(&t[0].token, &token) {
+                Ok((&t[1..], &t[0].token))
             } else {
                 Err(Err::Error(ParseError::new(
                     &t[..],
-                    ErrorKind::UnexpectedToken(t[0].clone()),
+                    ErrorKind::UnexpectedToken(t[0].token.clone()),
                     None,
                 )))
             }
@@ -142,6 +239,8 @@ fn parse_bullet_ref_tokens<'a>(t: Input<'a>) -> IResult<Input<'a>, &Token, Parse
     alt((
         token(Token::Keyword(Box::new(Keyword::Player))),
         token(Token::Keyword(Box::new(Keyword::SelfKw))),
+        token(Token::Keyword(Box::new(Keyword::Parent))),
+        token(Token::Keyword(Box::new(Keyword::Child))),
     ))(t)
 }
 
@@ -152,29 +251,25 @@ fn parse_bullet_ref<'a>(t: Input<'a>) -> IResult<Input<'a>, Symbol, ParseError<I
         token_type(Token::Ident("".to_string())),
     ))(t)
     {
-        Ok((t, (Token::Keyword(kw), _, Token::Ident(state)))) => {
+        Ok((rest, (Token::Keyword(kw), _, Token::Ident(state)))) => {
+            // `parse_bullet_ref_tokens` accepts only keywords of bullets
             let bullet = match BulletId::try_from(*kw.clone()) {
                 Ok(bullet) => bullet,
-                _ => {
-                    return Err(Err::Error(ParseError::new(
-                        t,
-                        ErrorKind::UnknownBulletId(String::from(*kw.clone())),
-                        None,
-                    )))
-                }
+                _ => unreachable!(),
             };
             let state = match StateId::try_from(&state[..]) {
                 Ok(state) => state,
                 _ => {
+                    // points the state name after `player.`
                     return Err(Err::Error(ParseError::new(
-                        t,
+                        &t[2..],
                         ErrorKind::UnknownStateId(state.to_string()),
                         None,
-                    )))
+                    )));
                 }
             };
 
-            Ok((t, Symbol::Ref(bullet, state)))
+            Ok((rest, Symbol::Ref(bullet, state)))
         }
         Ok((_, _)) => unreachable!(),
         Err(err) => Err(err),
@@ -208,7 +303,7 @@ fn parse_body_block_return<'a>(t: Input<'a>) -> IResult<Input<'a>, Body, ParseEr
         )),
     ))(t)
     {
-        Ok((t, (_, retval, _))) => Ok((t, Body::Return(retval))),
+        Ok((rest, (_, retval, _))) => Ok((rest, Body::Return(retval, span_of(t, rest)))),
         Err(err) => Err(err),
     }
 }
@@ -227,11 +322,15 @@ fn parse_body_block_lexical_define<'a>(
         )),
     ))(t)
     {
-        Ok((t, (_, Token::Ident(target_name), _, expr, _))) => {
+        Ok((rest, (_, Token::Ident(target_name), _, expr, _))) => {
             if let Some(name) = make_symbol(target_name) {
-                Ok((t, Body::LexicalDefine(name, expr)))
+                Ok((rest, Body::LexicalDefine(name, expr, span_of(t, rest))))
             } else {
-                Err(Err::Error(ParseError::new(t, ErrorKind::EmptyName, None)))
+                Err(Err::Error(ParseError::new(
+                    rest,
+                    ErrorKind::EmptyName,
+                    None,
+                )))
             }
         }
         Ok((t, (_, _, _, _, _))) => Err(Err::Error(ParseError::new(
@@ -261,7 +360,7 @@ fn parse_body_block_assignment<'a>(
         )),
     ))(t)
     {
-        Ok((t, (sym, _, expr, _))) => Ok((t, Body::Assignment(sym, expr))),
+        Ok((rest, (sym, _, expr, _))) => Ok((rest, Body::Assignment(sym, expr, span_of(t, rest)))),
         Err(Err::Error(err)) => Err(Err::Error(ParseError::new(
             t,
             ErrorKind::InvalidLexicalDefine,
@@ -271,18 +370,157 @@ fn parse_body_block_assignment<'a>(
     }
 }
 
+fn parse_body_block_times<'a>(t: Input<'a>) -> IResult<Input<'a>, Body, ParseError<Input<'a>>> {
+    match tuple((
+        token(Token::Keyword(Box::new(Keyword::Times))),
+        parse_expr,
+        parse_body_block,
+        alt((
+            token(Token::Newline),
+            peek(token(Token::Delim(Box::new(Delimiter::CloseBrace)))),
+        )),
+    ))(t)
+    {
+        Ok((rest, (_, count, body, _))) => Ok((rest, Body::Times(count, body, span_of(t, rest)))),
+        Err(Err::Error(err)) => Err(Err::Error(ParseError::context(
+            t,
+            ErrorKind::InvalidTimes,
+            err,
+        ))),
+        Err(err) => Err(err),
+    }
+}
+
+fn parse_body_block_while<'a>(t: Input<'a>) -> IResult<Input<'a>, Body, ParseError<Input<'a>>> {
+    match tuple((
+        token(Token::Keyword(Box::new(Keyword::While))),
+        parse_expr,
+        parse_body_block,
+        alt((
+            token(Token::Newline),
+            peek(token(Token::Delim(Box::new(Delimiter::CloseBrace)))),
+        )),
+    ))(t)
+    {
+        Ok((rest, (_, cond, body, _))) => Ok((rest, Body::While(cond, body, span_of(t, rest)))),
+        Err(Err::Error(err)) => Err(Err::Error(ParseError::context(
+            t,
+            ErrorKind::InvalidWhile,
+            err,
+        ))),
+        Err(err) => Err(err),
+    }
+}
+
+fn parse_body_block_for<'a>(t: Input<'a>) -> IResult<Input<'a>, Body, ParseError<Input<'a>>> {
+    match tuple((
+        token(Token::Keyword(Box::new(Keyword::For))),
+        token_type(Token::Ident("".to_string())),
+        token(Token::Keyword(Box::new(Keyword::In))),
+        parse_expr,
+        token(Token::Delim(Box::new(Delimiter::Range))),
+        parse_expr,
+        parse_body_block,
+        alt((
+            token(Token::Newline),
+            peek(token(Token::Delim(Box::new(Delimiter::CloseBrace)))),
+        )),
+    ))(t)
+    {
+        Ok((rest, (_, Token::Ident(name), _, from, _, to, body, _))) => Ok((
+            rest,
+            Body::For(Name(name.to_string()), from, to, body, span_of(t, rest)),
+        )),
+        Ok((t, _)) => Err(Err::Error(ParseError::new(t, ErrorKind::InvalidFor, None))),
+        Err(Err::Error(err)) => Err(Err::Error(ParseError::context(
+            t,
+            ErrorKind::InvalidFor,
+            err,
+        ))),
+        Err(err) => Err(err),
+    }
+}
+
+// Parses `if COND { ... }` followed by optional `else { ... }` or `else if ...`.
+fn parse_if_chain<'a>(t: Input<'a>) -> IResult<Input<'a>, Body, ParseError<Input<'a>>> {
+    match tuple((
+        token(Token::Keyword(Box::new(Keyword::If))),
+        parse_expr,
+        parse_body_block,
+        opt(preceded(
+            token(Token::Keyword(Box::new(Keyword::Else))),
+            alt((map(parse_if_chain, |elif| vec![elif]), parse_body_block)),
+        )),
+    ))(t)
+    {
+        Ok((rest, (_, cond, then, els))) => Ok((
+            rest,
+            Body::If(cond, then, els.unwrap_or_default(), span_of(t, rest)),
+        )),
+        Err(Err::Error(err)) => Err(Err::Error(ParseError::context(
+            t,
+            ErrorKind::InvalidIf,
+            err,
+        ))),
+        Err(err) => Err(err),
+    }
+}
+
+fn parse_body_block_if<'a>(t: Input<'a>) -> IResult<Input<'a>, Body, ParseError<Input<'a>>> {
+    match tuple((
+        parse_if_chain,
+        alt((
+            token(Token::Newline),
+            peek(token(Token::Delim(Box::new(Delimiter::CloseBrace)))),
+        )),
+    ))(t)
+    {
+        Ok((rest, (body, _))) => Ok((rest, body)),
+        Err(err) => Err(err),
+    }
+}
+
+fn parse_body_block_break<'a>(t: Input<'a>) -> IResult<Input<'a>, Body, ParseError<Input<'a>>> {
+    match tuple((
+        alt((
+            token(Token::Keyword(Box::new(Keyword::Break))),
+            token(Token::Keyword(Box::new(Keyword::Continue))),
+        )),
+        alt((
+            token(Token::Newline),
+            peek(token(Token::Delim(Box::new(Delimiter::CloseBrace)))),
+        )),
+    ))(t)
+    {
+        Ok((rest, (Token::Keyword(kw), _))) if **kw == Keyword::Break => {
+            Ok((rest, Body::Break(span_of(t, rest))))
+        }
+        Ok((rest, _)) => Ok((rest, Body::Continue(span_of(t, rest)))),
+        Err(err) => Err(err),
+    }
+}
+
+fn parse_body_statement<'a>(
+    t: Input<'a>,
+) -> IResult<Input<'a>, Option<Body>, ParseError<Input<'a>>> {
+    alt((
+        map(parse_body_block_lexical_define, |ld| Some(ld)),
+        map(parse_body_block_times, Some),
+        map(parse_body_block_while, Some),
+        map(parse_body_block_for, Some),
+        map(parse_body_block_break, Some),
+        map(parse_body_block_assignment, |a| Some(a)),
+        map(parse_expr, |e| Some(Body::Expr(Box::new(e)))),
+        // `if` with single expressions is read as an expression above to be a return value
+        map(parse_body_block_if, Some),
+        map(token(Token::Newline), |_| None),
+    ))(t)
+}
+
 fn parse_body_block<'a>(t: Input<'a>) -> IResult<Input<'a>, Vec<Body>, ParseError<Input<'a>>> {
     match delimited(
         token(Token::Delim(Box::new(Delimiter::OpenBrace))),
-        tuple((
-            many0(alt((
-                map(parse_body_block_lexical_define, |ld| Some(ld)),
-                map(parse_body_block_assignment, |a| Some(a)),
-                map(parse_expr, |e| Some(Body::Expr(Box::new(e)))),
-                map(token(Token::Newline), |_| None),
-            ))),
-            opt(parse_body_block_return),
-        )),
+        tuple((many0(parse_body_statement), opt(parse_body_block_return))),
         token(Token::Delim(Box::new(Delimiter::CloseBrace))),
     )(t)
     {
@@ -302,6 +540,15 @@ fn parse_body_block<'a>(t: Input<'a>) -> IResult<Input<'a>, Vec<Body>, ParseErro
                 .map(|o| o.unwrap())
                 .collect(),
         )),
+        // `many0` drops the error of the statement which stopped it, so parse it again to report
+        Err(Err::Error(err)) => {
+            match alt((map(parse_body_block_return, Some), parse_body_statement))(err.input) {
+                Err(Err::Error(stmt_err)) => {
+                    Err(Err::Error(nom::error::ParseError::or(err, stmt_err)))
+                }
+                _ => Err(Err::Error(err)),
+            }
+        }
         Err(err) => Err(err),
     }
 }
@@ -334,12 +581,14 @@ fn parse_expr_proc_call<'a>(t: Input<'a>) -> IResult<Input<'a>, Expr, ParseError
         ),
     ))(t)
     {
-        Ok((t, (Token::Ident(name), (mut argvec, Some(arg))))) => {
+        Ok((rest, (Token::Ident(name), (mut argvec, Some(arg))))) => {
             argvec.push(arg);
-            Ok((t, Expr::ProcCall(Name(name.to_string()), argvec)))
+            let span = span_of(t, rest);
+            Ok((rest, Expr::ProcCall(Name(name.to_string()), argvec, span)))
         }
-        Ok((t, (Token::Ident(name), (argvec, None)))) => {
-            Ok((t, Expr::ProcCall(Name(name.to_string()), argvec)))
+        Ok((rest, (Token::Ident(name), (argvec, None)))) => {
+            let span = span_of(t, rest);
+            Ok((rest, Expr::ProcCall(Name(name.to_string()), argvec, span)))
         }
         Ok((t, _)) => Err(Err::Error(ParseError::new(
             t,
@@ -364,13 +613,13 @@ fn parse_expr_term<'a>(t: Input<'a>) -> IResult<Input<'a>, Expr, ParseError<Inpu
         parse_bullet_ref_tokens,
     ))(t)
     {
-        Ok((t, Token::True)) => Ok((t, Expr::Bool(true))),
-        Ok((t, Token::False)) => Ok((t, Expr::Bool(false))),
-        Ok((t, Token::Float(Float(f)))) => Ok((t, Expr::Float(*f))),
-        Ok((t, Token::String(s))) => Ok((t, Expr::String(s.to_string()))),
+        Ok((rest, Token::True)) => Ok((rest, Expr::Bool(true, span_of(t, rest)))),
+        Ok((rest, Token::False)) => Ok((rest, Expr::Bool(false, span_of(t, rest)))),
+        Ok((rest, Token::Float(Float(f)))) => Ok((rest, Expr::Float(*f, span_of(t, rest)))),
+        Ok((rest, Token::String(s))) => Ok((rest, Expr::String(s.to_string(), span_of(t, rest)))),
         Ok((rest, Token::Ident(name))) => {
             if let Some(name) = make_symbol(name) {
-                Ok((rest, Expr::Symbol(name)))
+                Ok((rest, Expr::Symbol(name, span_of(t, rest))))
             } else {
                 Err(Err::Error(ParseError::new(
                     rest,
@@ -380,7 +629,7 @@ fn parse_expr_term<'a>(t: Input<'a>) -> IResult<Input<'a>, Expr, ParseError<Inpu
             }
         }
         Ok((_, Token::Keyword(_))) => match parse_bullet_ref(t) {
-            Ok((t, sym)) => Ok((t, Expr::Symbol(sym))),
+            Ok((rest, sym)) => Ok((rest, Expr::Symbol(sym, span_of(t, rest)))),
             Err(err) => Err(err),
         },
         Ok((_, _)) => unreachable!(),
@@ -413,7 +662,8 @@ fn parse_expr_op_level1_foldl<'a>(expr1: Expr, opterms: Vec<(&'a Token, Expr)>)
                 BinOp::Percent => Op2::Mod,
                 _ => unreachable!(),
             };
-            let expr = Expr::Op2(op, Box::new(expr1), Box::new(expr2.clone()));
+            let span = expr1.span().join(&expr2.span());
+            let expr = Expr::Op2(op, Box::new(expr1), Box::new(expr2.clone()), span);
             parse_expr_op_level1_foldl(expr, rest.to_vec())
         }
         [(_, _), ..] => unreachable!(),
@@ -449,7 +699,8 @@ fn parse_expr_op_level2_foldl<'a>(expr1: Expr, opterms: Vec<(&'a Token, Expr)>)
                 BinOp::Minus => Op2::Sub,
                 _ => unreachable!(),
             };
-            let expr = Expr::Op2(op, Box::new(expr1), Box::new(expr2.clone()));
+            let span = expr1.span().join(&expr2.span());
+            let expr = Expr::Op2(op, Box::new(expr1), Box::new(expr2.clone()), span);
             parse_expr_op_level2_foldl(expr, rest.to_vec())
         }
         [(_, _), ..] => unreachable!(),
@@ -487,7 +738,8 @@ fn parse_expr_op_level3_foldl<'a>(expr1: Expr, opterms: Vec<(&'a Token, Expr)>)
                 BinOp::Eq => Op2::Eq,
                 _ => unreachable!(),
             };
-            let expr = Expr::Op2(op, Box::new(expr1), Box::new(expr2.clone()));
+            let span = expr1.span().join(&expr2.span());
+            let expr = Expr::Op2(op, Box::new(expr1), Box::new(expr2.clone()), span);
             parse_expr_op_level3_foldl(expr, rest.to_vec())
         }
         [(_, _), ..] => unreachable!(),
@@ -525,7 +777,8 @@ fn parse_expr_op_level4_foldl<'a>(expr1: Expr, opterms: Vec<(&'a Token, Expr)>)
                 BinOp::LogAnd => Op2::LogAnd,
                 _ => unreachable!(),
             };
-            let expr = Expr::Op2(op, Box::new(expr1), Box::new(expr2.clone()));
+            let span = expr1.span().join(&expr2.span());
+            let expr = Expr::Op2(op, Box::new(expr1), Box::new(expr2.clone()), span);
             parse_expr_op_level4_foldl(expr, rest.to_vec())
         }
         [(_, _), ..] => unreachable!(),
@@ -577,12 +830,13 @@ fn parse_expr_if<'a>(t: Input<'a>) -> IResult<Input<'a>, Expr, ParseError<Input<
         ),
     ))(t)
     {
-        Ok((t, (_, cond_clause, (_, true_clause, _), _, (_, false_clause, _)))) => Ok((
-            t,
+        Ok((rest, (_, cond_clause, (_, true_clause, _), _, (_, false_clause, _)))) => Ok((
+            rest,
             Expr::If(
                 Box::new(cond_clause),
                 Box::new(true_clause),
                 Box::new(false_clause),
+                span_of(t, rest),
             ),
         )),
         Err(err) => Err(err),
@@ -602,11 +856,12 @@ fn parse_global_define<'a>(t: Input<'a>) -> IResult<Input<'a>, SyntaxTree, Parse
         alt((token(Token::Newline), peek(token(Token::Eof)))),
     ))(t)
     {
-        Ok((t, (_, Token::Ident(target_name), _, expr, _))) => {
+        Ok((rest, (_, Token::Ident(target_name), _, expr, _))) => {
             if let Some(name) = make_symbol(target_name) {
+                let span = span_of(t, rest);
                 match expr {
-                    Expr::Float(_) => Ok((t, SyntaxTree::GlobalDefine(name, expr))),
-                    Expr::Bool(_) => Ok((t, SyntaxTree::GlobalDefine(name, expr))),
+                    Expr::Float(_, _) => Ok((rest, SyntaxTree::GlobalDefine(name, expr, span))),
+                    Expr::Bool(_, _) => Ok((rest, SyntaxTree::GlobalDefine(name, expr, span))),
                     _ => Err(Err::Error(ParseError::new(
                         t,
                         ErrorKind::InvalidGlobalDefine,
@@ -614,7 +869,11 @@ fn parse_global_define<'a>(t: Input<'a>) -> IResult<Input<'a>, SyntaxTree, Parse
                     ))),
                 }
             } else {
-                Err(Err::Error(ParseError::new(t, ErrorKind::EmptyName, None)))
+                Err(Err::Error(ParseError::new(
+                    rest,
+                    ErrorKind::EmptyName,
+                    None,
+                )))
             }
         }
         Ok((t, (_, _, _, _, _))) => Err(Err::Error(ParseError::new(
@@ -683,23 +942,160 @@ fn parse_defproc<'a>(t: Input<'a>) -> IResult<Input<'a>, SyntaxTree, ParseError<
         alt((token(Token::Newline), peek(token(Token::Eof)))),
     ))(t)
     {
-        Ok((t, (_, Token::Ident(name), args, Some(Token::Type(r#type)), body, _))) => Ok((
-            t,
+        Ok((rest, (_, Token::Ident(name), args, Some(Token::Type(r#type)), body, _))) => Ok((
+            rest,
             SyntaxTree::DefProc(
                 Name(name.to_string()),
                 Signature::new(args, Some(**r#type)),
                 body,
+                span_of(t, rest),
             ),
         )),
-        Ok((t, (_, Token::Ident(name), args, None, body, _))) => Ok((
-            t,
-            SyntaxTree::DefProc(Name(name.to_string()), Signature::new(args, None), body),
+        Ok((rest, (_, Token::Ident(name), args, None, body, _))) => Ok((
+            rest,
+            SyntaxTree::DefProc(
+                Name(name.to_string()),
+                Signature::new(args, None),
+                body,
+                span_of(t, rest),
+            ),
         )),
         Ok((t, (_, _, _, _, _, _))) => Err(Err::Error(ParseError::new(
             t,
             ErrorKind::InvalidDefProc,
             None,
         ))),
+        Err(Err::Error(err)) => Err(Err::Error(ParseError::context(
+            t,
+            ErrorKind::InvalidDefProc,
+            err,
+        ))),
+        Err(err) => Err(err),
+    }
+}
+
+fn parse_defbullet<'a>(t: Input<'a>) -> IResult<Input<'a>, SyntaxTree, ParseError<Input<'a>>> {
+    match tuple((
+        token(Token::Keyword(Box::new(Keyword::Bullet))),
+        token_type(Token::Ident("".to_string())),
+        parse_defproc_args,
+        parse_body_block,
+        alt((token(Token::Newline), peek(token(Token::Eof)))),
+    ))(t)
+    {
+        Ok((rest, (_, Token::Ident(name), args, body, _))) => Ok((
+            rest,
+            SyntaxTree::DefBullet(
+                Name(name.to_string()),
+                Signature::new(args, None),
+                body,
+                span_of(t, rest),
+            ),
+        )),
+        Ok((t, (_, _, _, _, _))) => Err(Err::Error(ParseError::new(
+            t,
+            ErrorKind::InvalidDefBullet,
+            None,
+        ))),
+        Err(Err::Error(err)) => Err(Err::Error(ParseError::context(
+            t,
+            ErrorKind::InvalidDefBullet,
+            err,
+        ))),
+        Err(err) => Err(err),
+    }
+}
+
+// Classifies a step condition. `repeat(N)`, `frames_elapsed(N)` and `wait(N)` are special.
+fn make_step_kind(cond: Expr) -> StepKind {
+    match cond {
+        Expr::ProcCall(Name(name), mut args, _) if args.len() == 1 && name == "repeat" => {
+            StepKind::Repeat(args.remove(0))
+        }
+        Expr::ProcCall(Name(name), mut args, _) if args.len() == 1 && name == "frames_elapsed" => {
+            StepKind::FramesElapsed(args.remove(0))
+        }
+        Expr::ProcCall(Name(name), mut args, _) if args.len() == 1 && name == "wait" => {
+            StepKind::Wait(args.remove(0))
+        }
+        cond => StepKind::Cond(cond),
+    }
+}
+
+fn parse_timeline_step<'a>(
+    t: Input<'a>,
+) -> IResult<Input<'a>, TimelineStep, ParseError<Input<'a>>> {
+    match tuple((
+        parse_expr,
+        token(Token::Delim(Box::new(Delimiter::FatArrow))),
+        alt((
+            map(token(Token::Keyword(Box::new(Keyword::Nil))), |_| None),
+            map(parse_expr, Some),
+        )),
+        alt((
+            token(Token::Newline),
+            peek(token(Token::Delim(Box::new(Delimiter::CloseBracket)))),
+        )),
+    ))(t)
+    {
+        Ok((rest, (cond, _, action, _))) => Ok((
+            rest,
+            TimelineStep::new(make_step_kind(cond), action, span_of(t, rest)),
+        )),
+        Err(Err::Error(err)) => Err(Err::Error(ParseError::context(
+            t,
+            ErrorKind::InvalidTimelineStep,
+            err,
+        ))),
+        Err(err) => Err(err),
+    }
+}
+
+fn parse_timeline_steps<'a>(
+    t: Input<'a>,
+) -> IResult<Input<'a>, Vec<TimelineStep>, ParseError<Input<'a>>> {
+    match delimited(
+        token(Token::Delim(Box::new(Delimiter::OpenBracket))),
+        many0(alt((
+            map(parse_timeline_step, Some),
+            map(token(Token::Newline), |_| None),
+        ))),
+        token(Token::Delim(Box::new(Delimiter::CloseBracket))),
+    )(t)
+    {
+        Ok((rest, steps)) => Ok((rest, steps.into_iter().flatten().collect())),
+        Err(err) => Err(err),
+    }
+}
+
+fn parse_deftimeline<'a>(t: Input<'a>) -> IResult<Input<'a>, SyntaxTree, ParseError<Input<'a>>> {
+    match tuple((
+        token(Token::Keyword(Box::new(Keyword::Timeline))),
+        token_type(Token::Ident("".to_string())),
+        parse_defproc_args,
+        parse_timeline_steps,
+        alt((token(Token::Newline), peek(token(Token::Eof)))),
+    ))(t)
+    {
+        Ok((rest, (_, Token::Ident(name), args, steps, _))) => Ok((
+            rest,
+            SyntaxTree::DefTimeline(
+                Name(name.to_string()),
+                Signature::new(args, None),
+                steps,
+                span_of(t, rest),
+            ),
+        )),
+        Ok((t, (_, _, _, _, _))) => Err(Err::Error(ParseError::new(
+            t,
+            ErrorKind::InvalidDefTimeline,
+            None,
+        ))),
+        Err(Err::Error(err)) => Err(Err::Error(ParseError::context(
+            t,
+            ErrorKind::InvalidDefTimeline,
+            err,
+        ))),
         Err(err) => Err(err),
     }
 }
@@ -708,10 +1104,11 @@ type Parse1Result<'a> = IResult<Input<'a>, Option<SyntaxTree>, ParseError<Input<
 
 fn parse_1<'a>(t: Input<'a>) -> Parse1Result<'a> {
     alt((
-        // value(None, token_type_of(Token::LineComment("".to_string()))),
         map(token(Token::Newline), |_| None),
         map(parse_global_define, |ga| Some(ga)),
         map(parse_defproc, |f| Some(f)),
+        map(parse_defbullet, Some),
+        map(parse_deftimeline, Some),
     ))(t)
 }
 
@@ -727,6 +1124,11 @@ pub fn parse<'a>(t: Input<'a>) -> ParseResult<'a> {
                 .map(|o| o.unwrap())
                 .collect(),
         )),
+        // `many0` drops the error of the definition which stopped it, so parse it again to report
+        Err(Err::Error(err)) => match parse_1(err.input) {
+            Err(Err::Error(def_err)) => Err(Err::Error(nom::error::ParseError::or(err, def_err))),
+            _ => Err(Err::Error(err)),
+        },
         Err(err) => Err(err),
     }
 }
@@ -736,6 +1138,77 @@ mod parser_test {
     use super::*;
     use crate::tokenize;
 
+    // Resets spans in the tree so that it can be compared with trees written by hand.
+    // Spans themselves are checked in `test_parse_spans`.
+    fn strip_spans(st: &SyntaxTree) -> SyntaxTree {
+        fn expr(e: &Expr) -> Expr {
+            let sp = Span::default();
+            match e {
+                Expr::Float(f, _) => Expr::Float(*f, sp),
+                Expr::Bool(b, _) => Expr::Bool(*b, sp),
+                Expr::String(s, _) => Expr::String(s.clone(), sp),
+                Expr::Symbol(sym, _) => Expr::Symbol(sym.clone(), sp),
+                Expr::Op2(op, l, r, _) => {
+                    Expr::Op2(op.clone(), Box::new(expr(l)), Box::new(expr(r)), sp)
+                }
+                Expr::If(c, t, f, _) => {
+                    Expr::If(Box::new(expr(c)), Box::new(expr(t)), Box::new(expr(f)), sp)
+                }
+                Expr::ProcCall(name, args, _) => {
+                    Expr::ProcCall(name.clone(), args.iter().map(expr).collect(), sp)
+                }
+            }
+        }
+        fn bodies(v: &[Body]) -> Vec<Body> {
+            v.iter().map(body).collect()
+        }
+        fn body(b: &Body) -> Body {
+            let sp = Span::default();
+            match b {
+                Body::LexicalDefine(sym, e, _) => Body::LexicalDefine(sym.clone(), expr(e), sp),
+                Body::Assignment(sym, e, _) => Body::Assignment(sym.clone(), expr(e), sp),
+                Body::Return(e, _) => Body::Return(e.as_ref().map(expr), sp),
+                Body::Times(e, v, _) => Body::Times(expr(e), bodies(v), sp),
+                Body::While(e, v, _) => Body::While(expr(e), bodies(v), sp),
+                Body::For(name, from, to, v, _) => {
+                    Body::For(name.clone(), expr(from), expr(to), bodies(v), sp)
+                }
+                Body::Break(_) => Body::Break(sp),
+                Body::Continue(_) => Body::Continue(sp),
+                Body::If(e, t, f, _) => Body::If(expr(e), bodies(t), bodies(f), sp),
+                Body::Expr(e) => Body::Expr(Box::new(expr(e))),
+            }
+        }
+        fn step(s: &TimelineStep) -> TimelineStep {
+            let kind = match &s.kind {
+                StepKind::Repeat(e) => StepKind::Repeat(expr(e)),
+                StepKind::FramesElapsed(e) => StepKind::FramesElapsed(expr(e)),
+                StepKind::Wait(e) => StepKind::Wait(expr(e)),
+                StepKind::Cond(e) => StepKind::Cond(expr(e)),
+            };
+            TimelineStep::new(kind, s.action.as_ref().map(expr), Span::default())
+        }
+
+        let sp = Span::default();
+        match st {
+            SyntaxTree::GlobalDefine(sym, e, _) => {
+                SyntaxTree::GlobalDefine(sym.clone(), expr(e), sp)
+            }
+            SyntaxTree::DefProc(name, sig, v, _) => {
+                SyntaxTree::DefProc(name.clone(), sig.clone(), bodies(v), sp)
+            }
+            SyntaxTree::DefBullet(name, sig, v, _) => {
+                SyntaxTree::DefBullet(name.clone(), sig.clone(), bodies(v), sp)
+            }
+            SyntaxTree::DefTimeline(name, sig, steps, _) => SyntaxTree::DefTimeline(
+                name.clone(),
+                sig.clone(),
+                steps.iter().map(step).collect(),
+                sp,
+            ),
+        }
+    }
+
     fn test_parse_1(expected: SyntaxTree, string: &str) {
         println!("text: {:?}", string);
         if let Ok(("", tokens)) = tokenize(string) {
@@ -746,7 +1219,7 @@ mod parser_test {
                     assert_eq!(1, vec.len());
 
                     if let Some(st) = vec.iter().nth(0) {
-                        assert_eq!(*st, expected);
+                        assert_eq!(strip_spans(st), expected);
                     } else {
                         println!("This test case itself is wrong....");
                         assert!(false);
@@ -766,7 +1239,11 @@ mod parser_test {
     #[test]
     fn test_parse_global_define() {
         test_parse_1(
-            SyntaxTree::GlobalDefine(Symbol::Var(Name("val".to_string())), Expr::Float(42.0)),
+            SyntaxTree::GlobalDefine(
+                Symbol::Var(Name("val".to_string())),
+                Expr::Float(42.0, Span::default()),
+                Span::default(),
+            ),
             "global val = 42.0",
         );
     }
@@ -781,10 +1258,13 @@ mod parser_test {
                     Symbol::Var(Name("a".to_string())),
                     Expr::Op2(
                         Op2::Eq,
-                        Box::new(Expr::Float(1.0)),
-                        Box::new(Expr::Float(2.0)),
+                        Box::new(Expr::Float(1.0, Span::default())),
+                        Box::new(Expr::Float(2.0, Span::default())),
+                        Span::default(),
                     ),
+                    Span::default(),
                 )],
+                Span::default(),
             ),
             "proc main() {let a = 1.0 == 2.0 }",
         );
@@ -796,10 +1276,13 @@ mod parser_test {
                     Symbol::Var(Name("a".to_string())),
                     Expr::Op2(
                         Op2::Add,
-                        Box::new(Expr::Float(1.0)),
-                        Box::new(Expr::Float(2.0)),
+                        Box::new(Expr::Float(1.0, Span::default())),
+                        Box::new(Expr::Float(2.0, Span::default())),
+                        Span::default(),
                     ),
+                    Span::default(),
                 )],
+                Span::default(),
             ),
             "proc main() { let a = 1.0 + 2.0 }",
         );
@@ -811,10 +1294,13 @@ mod parser_test {
                     Symbol::Var(Name("a".to_string())),
                     Expr::Op2(
                         Op2::Mul,
-                        Box::new(Expr::Float(1.0)),
-                        Box::new(Expr::Float(2.0)),
+                        Box::new(Expr::Float(1.0, Span::default())),
+                        Box::new(Expr::Float(2.0, Span::default())),
+                        Span::default(),
                     ),
+                    Span::default(),
                 )],
+                Span::default(),
             ),
             "proc main() { let a = 1.0 * 2.0 }",
         );
@@ -832,12 +1318,16 @@ mod parser_test {
                         Op2::Sub,
                         Box::new(Expr::Op2(
                             Op2::Add,
-                            Box::new(Expr::Float(1.0)),
-                            Box::new(Expr::Float(2.0)),
+                            Box::new(Expr::Float(1.0, Span::default())),
+                            Box::new(Expr::Float(2.0, Span::default())),
+                            Span::default(),
                         )),
-                        Box::new(Expr::Float(3.0)),
+                        Box::new(Expr::Float(3.0, Span::default())),
+                        Span::default(),
                     ),
+                    Span::default(),
                 )],
+                Span::default(),
             ),
             "proc main() { let a = 1.0 + 2.0 - 3.0 }",
         );
@@ -853,14 +1343,18 @@ mod parser_test {
                     Symbol::Var(Name("a".to_string())),
                     Expr::Op2(
                         Op2::Eq,
-                        Box::new(Expr::Float(1.0)),
+                        Box::new(Expr::Float(1.0, Span::default())),
                         Box::new(Expr::Op2(
                             Op2::Add,
-                            Box::new(Expr::Float(2.0)),
-                            Box::new(Expr::Float(3.0)),
+                            Box::new(Expr::Float(2.0, Span::default())),
+                            Box::new(Expr::Float(3.0, Span::default())),
+                            Span::default(),
                         )),
+                        Span::default(),
                     ),
+                    Span::default(),
                 )],
+                Span::default(),
             ),
             "proc main() { let a = 1.0 == 2.0 + 3.0 }",
         );
@@ -875,16 +1369,21 @@ mod parser_test {
                         Op2::Eq,
                         Box::new(Expr::Op2(
                             Op2::Mul,
-                            Box::new(Expr::Float(-1.0)),
-                            Box::new(Expr::Float(1.0)),
+                            Box::new(Expr::Float(-1.0, Span::default())),
+                            Box::new(Expr::Float(1.0, Span::default())),
+                            Span::default(),
                         )),
                         Box::new(Expr::Op2(
                             Op2::Add,
-                            Box::new(Expr::Float(2.0)),
-                            Box::new(Expr::Float(3.0)),
+                            Box::new(Expr::Float(2.0, Span::default())),
+                            Box::new(Expr::Float(3.0, Span::default())),
+                            Span::default(),
                         )),
+                        Span::default(),
                     ),
+                    Span::default(),
                 )],
+                Span::default(),
             ),
             "proc main() { let a = -1.0 * 1.0 == 2.0 + 3.0 }",
         );
@@ -900,14 +1399,19 @@ mod parser_test {
                             Op2::Add,
                             Box::new(Expr::Op2(
                                 Op2::Mul,
-                                Box::new(Expr::Float(1.0)),
-                                Box::new(Expr::Float(2.0)),
+                                Box::new(Expr::Float(1.0, Span::default())),
+                                Box::new(Expr::Float(2.0, Span::default())),
+                                Span::default(),
                             )),
-                            Box::new(Expr::Float(3.0)),
+                            Box::new(Expr::Float(3.0, Span::default())),
+                            Span::default(),
                         )),
-                        Box::new(Expr::Float(4.0)),
+                        Box::new(Expr::Float(4.0, Span::default())),
+                        Span::default(),
                     ),
+                    Span::default(),
                 )],
+                Span::default(),
             ),
             "proc main() { let a = 1.0 * 2.0 + 3.0 == 4.0 }",
         );
@@ -919,18 +1423,23 @@ mod parser_test {
                     Symbol::Var(Name("a".to_string())),
                     Expr::Op2(
                         Op2::Eq,
-                        Box::new(Expr::Float(1.0)),
+                        Box::new(Expr::Float(1.0, Span::default())),
                         Box::new(Expr::Op2(
                             Op2::Add,
-                            Box::new(Expr::Float(2.0)),
+                            Box::new(Expr::Float(2.0, Span::default())),
                             Box::new(Expr::Op2(
                                 Op2::Mul,
-                                Box::new(Expr::Float(3.0)),
-                                Box::new(Expr::Float(4.0)),
+                                Box::new(Expr::Float(3.0, Span::default())),
+                                Box::new(Expr::Float(4.0, Span::default())),
+                                Span::default(),
                             )),
+                            Span::default(),
                         )),
+                        Span::default(),
                     ),
+                    Span::default(),
                 )],
+                Span::default(),
             ),
             "proc main() { let a = 1.0 == 2.0 + 3.0 * 4.0 }",
         );
@@ -942,18 +1451,23 @@ mod parser_test {
                     Symbol::Var(Name("a".to_string())),
                     Expr::Op2(
                         Op2::Eq,
-                        Box::new(Expr::Float(1.0)),
+                        Box::new(Expr::Float(1.0, Span::default())),
                         Box::new(Expr::Op2(
                             Op2::Add,
-                            Box::new(Expr::Float(2.0)),
+                            Box::new(Expr::Float(2.0, Span::default())),
                             Box::new(Expr::Op2(
                                 Op2::Mul,
-                                Box::new(Expr::Float(3.0)),
-                                Box::new(Expr::Float(4.0)),
+                                Box::new(Expr::Float(3.0, Span::default())),
+                                Box::new(Expr::Float(4.0, Span::default())),
+                                Span::default(),
                             )),
+                            Span::default(),
                         )),
+                        Span::default(),
                     ),
+                    Span::default(),
                 )],
+                Span::default(),
             ),
             "proc main() { let a = 1.0 == 2.0 + 3.0 * 4.0 }",
         );
@@ -971,16 +1485,21 @@ mod parser_test {
                         Op2::Add,
                         Box::new(Expr::Op2(
                             Op2::Eq,
-                            Box::new(Expr::Float(1.0)),
-                            Box::new(Expr::Float(2.0)),
+                            Box::new(Expr::Float(1.0, Span::default())),
+                            Box::new(Expr::Float(2.0, Span::default())),
+                            Span::default(),
                         )),
                         Box::new(Expr::Op2(
                             Op2::Mul,
-                            Box::new(Expr::Float(3.0)),
-                            Box::new(Expr::Float(4.0)),
+                            Box::new(Expr::Float(3.0, Span::default())),
+                            Box::new(Expr::Float(4.0, Span::default())),
+                            Span::default(),
                         )),
+                        Span::default(),
                     ),
+                    Span::default(),
                 )],
+                Span::default(),
             ),
             "proc main() { let a = (1.0 == 2.0) + 3.0 * 4.0 }",
         );
@@ -992,18 +1511,23 @@ mod parser_test {
                     Symbol::Var(Name("a".to_string())),
                     Expr::Op2(
                         Op2::LogOr,
-                        Box::new(Expr::Bool(true)),
+                        Box::new(Expr::Bool(true, Span::default())),
                         Box::new(Expr::Op2(
                             Op2::Eq,
-                            Box::new(Expr::Float(42.0)),
+                            Box::new(Expr::Float(42.0, Span::default())),
                             Box::new(Expr::Op2(
                                 Op2::Mul,
-                                Box::new(Expr::Float(21.0)),
-                                Box::new(Expr::Float(2.0)),
+                                Box::new(Expr::Float(21.0, Span::default())),
+                                Box::new(Expr::Float(2.0, Span::default())),
+                                Span::default(),
                             )),
+                            Span::default(),
                         )),
+                        Span::default(),
                     ),
+                    Span::default(),
                 )],
+                Span::default(),
             ),
             "proc main() { let a = true || 42 == 21 * 2 }",
         );
@@ -1015,7 +1539,8 @@ mod parser_test {
             SyntaxTree::DefProc(
                 Name("main".to_string()),
                 Signature::new(vec![], None),
-                vec![Body::Return(None)],
+                vec![Body::Return(None, Span::default())],
+                Span::default(),
             ),
             "proc main() { return }",
         );
@@ -1029,12 +1554,18 @@ mod parser_test {
                         Symbol::Ref(BulletId::Itself, StateId::PosX),
                         Expr::Op2(
                             Op2::Add,
-                            Box::new(Expr::Symbol(Symbol::Ref(BulletId::Itself, StateId::PosX))),
-                            Box::new(Expr::Float(5.0)),
+                            Box::new(Expr::Symbol(
+                                Symbol::Ref(BulletId::Itself, StateId::PosX),
+                                Span::default(),
+                            )),
+                            Box::new(Expr::Float(5.0, Span::default())),
+                            Span::default(),
                         ),
+                        Span::default(),
                     ),
-                    Body::Return(None),
+                    Body::Return(None, Span::default()),
                 ],
+                Span::default(),
             ),
             r###"
             proc main() {
@@ -1054,14 +1585,17 @@ mod parser_test {
                 vec![Body::LexicalDefine(
                     Symbol::Var(Name("dp".to_string())),
                     Expr::If(
-                        Box::new(Expr::Symbol(Symbol::Ref(
-                            BulletId::Itself,
-                            StateId::InputSlow,
-                        ))),
-                        Box::new(Expr::Float(4.0)),
-                        Box::new(Expr::Float(7.0)),
+                        Box::new(Expr::Symbol(
+                            Symbol::Ref(BulletId::Itself, StateId::InputSlow),
+                            Span::default(),
+                        )),
+                        Box::new(Expr::Float(4.0, Span::default())),
+                        Box::new(Expr::Float(7.0, Span::default())),
+                        Span::default(),
                     ),
+                    Span::default(),
                 )],
+                Span::default(),
             ),
             "proc main() { let dp = if self.input_slow { 4.0 } else { 7.0 } }",
         );
@@ -1073,17 +1607,24 @@ mod parser_test {
                     Symbol::Ref(BulletId::Itself, StateId::PosX),
                     Expr::Op2(
                         Op2::Add,
-                        Box::new(Expr::Symbol(Symbol::Ref(BulletId::Itself, StateId::PosX))),
+                        Box::new(Expr::Symbol(
+                            Symbol::Ref(BulletId::Itself, StateId::PosX),
+                            Span::default(),
+                        )),
                         Box::new(Expr::If(
-                            Box::new(Expr::Symbol(Symbol::Ref(
-                                BulletId::Itself,
-                                StateId::InputSlow,
-                            ))),
-                            Box::new(Expr::Float(4.0)),
-                            Box::new(Expr::Float(7.0)),
+                            Box::new(Expr::Symbol(
+                                Symbol::Ref(BulletId::Itself, StateId::InputSlow),
+                                Span::default(),
+                            )),
+                            Box::new(Expr::Float(4.0, Span::default())),
+                            Box::new(Expr::Float(7.0, Span::default())),
+                            Span::default(),
                         )),
+                        Span::default(),
                     ),
+                    Span::default(),
                 )],
+                Span::default(),
             ),
             r##"
             proc main() {
@@ -1107,9 +1648,17 @@ mod parser_test {
                 ),
                 vec![Body::Expr(Box::new(Expr::Op2(
                     Op2::Add,
-                    Box::new(Expr::Symbol(Symbol::Var(Name("a".to_string())))),
-                    Box::new(Expr::Symbol(Symbol::Var(Name("b".to_string())))),
+                    Box::new(Expr::Symbol(
+                        Symbol::Var(Name("a".to_string())),
+                        Span::default(),
+                    )),
+                    Box::new(Expr::Symbol(
+                        Symbol::Var(Name("b".to_string())),
+                        Span::default(),
+                    )),
+                    Span::default(),
                 )))],
+                Span::default(),
             ),
             r##"
             proc test(a: float, b: float) -> bool { a + b }
@@ -1122,11 +1671,17 @@ mod parser_test {
                 vec![Body::Assignment(
                     Symbol::Ref(BulletId::Itself, StateId::PosX),
                     Expr::If(
-                        Box::new(Expr::Symbol(Symbol::Var(Name("b".to_string())))),
-                        Box::new(Expr::Float(1.0)),
-                        Box::new(Expr::Float(2.0)),
+                        Box::new(Expr::Symbol(
+                            Symbol::Var(Name("b".to_string())),
+                            Span::default(),
+                        )),
+                        Box::new(Expr::Float(1.0, Span::default())),
+                        Box::new(Expr::Float(2.0, Span::default())),
+                        Span::default(),
                     ),
+                    Span::default(),
                 )],
+                Span::default(),
             ),
             r##"
             proc test(b: bool) { self.x = if b { 1 } else { 2 } }
@@ -1142,8 +1697,10 @@ mod parser_test {
                 Signature::new(vec![], None),
                 vec![Body::Assignment(
                     Symbol::Ref(BulletId::Itself, StateId::PosX),
-                    Expr::ProcCall(Name("func".to_string()), vec![]),
+                    Expr::ProcCall(Name("func".to_string()), vec![], Span::default()),
+                    Span::default(),
                 )],
+                Span::default(),
             ),
             r##"
             proc main() {
@@ -1157,8 +1714,14 @@ mod parser_test {
                 Signature::new(vec![], None),
                 vec![Body::Assignment(
                     Symbol::Ref(BulletId::Itself, StateId::PosX),
-                    Expr::ProcCall(Name("func".to_string()), vec![Expr::Float(1.0)]),
+                    Expr::ProcCall(
+                        Name("func".to_string()),
+                        vec![Expr::Float(1.0, Span::default())],
+                        Span::default(),
+                    ),
+                    Span::default(),
                 )],
+                Span::default(),
             ),
             r##"
             proc main() {
@@ -1175,19 +1738,23 @@ mod parser_test {
                     Expr::ProcCall(
                         Name("func".to_string()),
                         vec![
-                            Expr::Float(42.0),
-                            Expr::Bool(false),
+                            Expr::Float(42.0, Span::default()),
+                            Expr::Bool(false, Span::default()),
                             Expr::Op2(
                                 Op2::Add,
-                                Box::new(Expr::Symbol(Symbol::Ref(
-                                    BulletId::Itself,
-                                    StateId::PosX,
-                                ))),
-                                Box::new(Expr::Float(10.0)),
+                                Box::new(Expr::Symbol(
+                                    Symbol::Ref(BulletId::Itself, StateId::PosX),
+                                    Span::default(),
+                                )),
+                                Box::new(Expr::Float(10.0, Span::default())),
+                                Span::default(),
                             ),
                         ],
+                        Span::default(),
                     ),
+                    Span::default(),
                 )],
+                Span::default(),
             ),
             r##"
             proc main() {
@@ -1205,8 +1772,10 @@ mod parser_test {
                 Signature::new(vec![], None),
                 vec![Body::Assignment(
                     Symbol::Ref(BulletId::Itself, StateId::PosX),
-                    Expr::String("mojiretsu".to_string()),
+                    Expr::String("mojiretsu".to_string(), Span::default()),
+                    Span::default(),
                 )],
+                Span::default(),
             ),
             r##"
             proc main() {
@@ -1226,10 +1795,19 @@ mod parser_test {
                     Symbol::Ref(BulletId::Itself, StateId::PosX),
                     Expr::Op2(
                         Op2::Sub,
-                        Box::new(Expr::Symbol(Symbol::Ref(BulletId::Player, StateId::PosX))),
-                        Box::new(Expr::Symbol(Symbol::Ref(BulletId::Itself, StateId::PosX))),
+                        Box::new(Expr::Symbol(
+                            Symbol::Ref(BulletId::Player, StateId::PosX),
+                            Span::default(),
+                        )),
+                        Box::new(Expr::Symbol(
+                            Symbol::Ref(BulletId::Itself, StateId::PosX),
+                            Span::default(),
+                        )),
+                        Span::default(),
                     ),
+                    Span::default(),
                 )],
+                Span::default(),
             ),
             r##"
             proc main() {
@@ -1238,4 +1816,231 @@ mod parser_test {
             "##,
         );
     }
+
+    #[test]
+    fn test_parse_parent_and_child_ref() {
+        test_parse_1(
+            SyntaxTree::DefProc(
+                Name("main".to_string()),
+                Signature::new(vec![], None),
+                vec![Body::Assignment(
+                    Symbol::Ref(BulletId::Child, StateId::VelX),
+                    Expr::Symbol(
+                        Symbol::Ref(BulletId::Parent, StateId::VelX),
+                        Span::default(),
+                    ),
+                    Span::default(),
+                )],
+                Span::default(),
+            ),
+            r##"
+            proc main() {
+              child.vx = parent.vx
+            }
+            "##,
+        );
+    }
+
+    #[test]
+    fn test_parse_defbullet() {
+        test_parse_1(
+            SyntaxTree::DefBullet(
+                Name("linear".to_string()),
+                Signature::new(
+                    vec![
+                        Arg::new("dir".to_string(), Type::Float),
+                        Arg::new("v".to_string(), Type::Float),
+                    ],
+                    None,
+                ),
+                vec![Body::Expr(Box::new(Expr::ProcCall(
+                    Name("die".to_string()),
+                    vec![],
+                    Span::default(),
+                )))],
+                Span::default(),
+            ),
+            r##"
+            bullet linear(dir: float, v: float) {
+              die()
+            }
+            "##,
+        );
+    }
+
+    #[test]
+    fn test_parse_times() {
+        test_parse_1(
+            SyntaxTree::DefProc(
+                Name("main".to_string()),
+                Signature::new(vec![], None),
+                vec![Body::Times(
+                    Expr::Float(3.0, Span::default()),
+                    vec![Body::LexicalDefine(
+                        Symbol::Var(Name("d".to_string())),
+                        Expr::Symbol(Symbol::Var(Name("#n".to_string())), Span::default()),
+                        Span::default(),
+                    )],
+                    Span::default(),
+                )],
+                Span::default(),
+            ),
+            r##"
+            proc main() {
+              times 3 {
+                let d = #n
+              }
+            }
+            "##,
+        );
+    }
+
+    #[test]
+    fn test_parse_for() {
+        test_parse_1(
+            SyntaxTree::DefProc(
+                Name("main".to_string()),
+                Signature::new(vec![], None),
+                vec![Body::For(
+                    Name("i".to_string()),
+                    Expr::Float(1.0, Span::default()),
+                    Expr::Symbol(Symbol::Var(Name("n".to_string())), Span::default()),
+                    vec![
+                        Body::While(
+                            Expr::Bool(true, Span::default()),
+                            vec![Body::Break(Span::default())],
+                            Span::default(),
+                        ),
+                        Body::Continue(Span::default()),
+                    ],
+                    Span::default(),
+                )],
+                Span::default(),
+            ),
+            r##"
+            proc main() {
+              for i in 1..n {
+                while true {
+                  break
+                }
+                continue
+              }
+            }
+            "##,
+        );
+    }
+
+    #[test]
+    fn test_parse_if_statement() {
+        test_parse_1(
+            SyntaxTree::DefProc(
+                Name("main".to_string()),
+                Signature::new(vec![], None),
+                vec![Body::If(
+                    Expr::Bool(true, Span::default()),
+                    vec![Body::LexicalDefine(
+                        Symbol::Var(Name("a".to_string())),
+                        Expr::Float(1.0, Span::default()),
+                        Span::default(),
+                    )],
+                    vec![Body::If(
+                        Expr::Bool(false, Span::default()),
+                        vec![Body::Expr(Box::new(Expr::ProcCall(
+                            Name("die".to_string()),
+                            vec![],
+                            Span::default(),
+                        )))],
+                        vec![],
+                        Span::default(),
+                    )],
+                    Span::default(),
+                )],
+                Span::default(),
+            ),
+            r##"
+            proc main() {
+              if true {
+                let a = 1
+              } else if false {
+                die()
+              }
+            }
+            "##,
+        );
+    }
+
+    #[test]
+    fn test_parse_deftimeline() {
+        let proc_call =
+            |name: &str, args| Expr::ProcCall(Name(name.to_string()), args, Span::default());
+        let float = |f| Expr::Float(f, Span::default());
+
+        test_parse_1(
+            SyntaxTree::DefTimeline(
+                Name("pattern".to_string()),
+                Signature::new(vec![Arg::new("n".to_string(), Type::Float)], None),
+                vec![
+                    TimelineStep::new(StepKind::Wait(float(2.0)), None, Span::default()),
+                    TimelineStep::new(
+                        StepKind::Repeat(float(3.0)),
+                        Some(proc_call("die", vec![])),
+                        Span::default(),
+                    ),
+                    TimelineStep::new(
+                        StepKind::FramesElapsed(float(10.0)),
+                        Some(proc_call("shoot", vec![float(1.0)])),
+                        Span::default(),
+                    ),
+                    TimelineStep::new(
+                        StepKind::Cond(proc_call("ready", vec![])),
+                        None,
+                        Span::default(),
+                    ),
+                ],
+                Span::default(),
+            ),
+            r##"
+            timeline pattern(n: float) [
+              wait(2) => nil
+              repeat(3) => die()
+
+              frames_elapsed(10) => shoot(1)
+              ready() => nil]
+            "##,
+        );
+    }
+
+    #[test]
+    fn test_parse_spans() {
+        let (_, tokens) = tokenize("proc main() {\n  let a = 1.0 + b\n}").unwrap();
+        let (_, stvec) = parse(&tokens).unwrap();
+
+        let proc_span = stvec[0].span();
+        assert_eq!((proc_span.start.line, proc_span.start.column), (1, 1));
+        assert_eq!((proc_span.end.line, proc_span.end.column), (3, 2));
+
+        if let SyntaxTree::DefProc(_, _, body, _) = &stvec[0] {
+            if let Body::LexicalDefine(_, expr, span) = &body[0] {
+                assert_eq!((span.start.line, span.start.column), (2, 3));
+                assert_eq!((span.end.line, span.end.column), (2, 18));
+                assert_eq!(expr.span().start.column, 11);
+                assert_eq!(expr.span().end.column, 18);
+                return;
+            }
+        }
+        panic!("unexpected tree: {:?}", stvec[0]);
+    }
+
+    #[test]
+    fn test_parse_error_span() {
+        let (_, tokens) = tokenize("global a = 1.0\nproc main( {\n}").unwrap();
+
+        match parse(&tokens) {
+            Err(nom::Err::Error(err)) => {
+                let span = err.purge_input().unwrap().span.unwrap();
+                assert_eq!((span.start.line, span.start.column), (2, 1));
+            }
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
 }
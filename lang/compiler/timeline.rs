@@ -0,0 +1,208 @@
+use lang_component::{
+    bullet::{BulletId, StateId},
+    span::Span,
+    syntax::{Body, Expr, Name, Op2, Signature, StepKind, Symbol, SyntaxTree, TimelineStep},
+};
+
+// Desugars `timeline NAME(args) [ ... ]` into hidden globals and a proc of NAME.
+//
+// The proc runs steps from the current one while their conditions hold, and stops at
+// the first step which is not ready yet. The current step and the counters of steps are
+// kept in globals named `#NAME.step` and `#NAME.K`, which cannot be written in scripts.
+// Steps are numbered from 1. The step `N + 1` is the end of the timeline and `N + 2`
+// means that the timeline is finished.
+//
+// `wait(N)` counts calls from the call reaching it, which is the first of them, so it
+// passes at the N-th call after that: `repeat(3) => nil`, `wait(2) => nil` and
+// `true => f()` call `f()` at frames 2, 4 and 6 from 0. `frames_elapsed(N)` passes
+// when `self.frames` has grown by N since the step is reached.
+//
+// When the timeline reaches the end, it goes back to the last `repeat` step if any.
+// Each `repeat(N)` lets the following steps run N times, then runs its action and
+// finishes the timeline.
+pub(crate) fn expand(
+    name: &str,
+    signature: &Signature,
+    steps: &[TimelineStep],
+    span: Span,
+) -> Vec<SyntaxTree> {
+    let tl = Timeline { name, steps, span };
+    let mut trees = Vec::new();
+
+    trees.push(global(&tl.step_var(), span));
+    for (idx, step) in steps.iter().enumerate() {
+        if !matches!(step.kind, StepKind::Cond(_)) {
+            trees.push(global(&tl.slot_var(idx + 1), step.span));
+        }
+    }
+
+    // enter the first step at the first call
+    let mut init = tl.enter(1, span);
+    init.push(tl.goto(1, span));
+
+    let body = vec![
+        Body::If(
+            op(Op2::Eq, var(&tl.step_var(), span), float(0.0, span), span),
+            init,
+            vec![],
+            span,
+        ),
+        Body::While(Expr::Bool(true, span), vec![tl.dispatch(1)], span),
+    ];
+
+    trees.push(SyntaxTree::DefProc(
+        Name(name.to_string()),
+        signature.clone(),
+        body,
+        span,
+    ));
+
+    trees
+}
+
+struct Timeline<'a> {
+    name: &'a str,
+    steps: &'a [TimelineStep],
+    span: Span,
+}
+
+impl<'a> Timeline<'a> {
+    fn step_var(&self) -> String {
+        format!("#{}.step", self.name)
+    }
+
+    fn slot_var(&self, k: usize) -> String {
+        format!("#{}.{}", self.name, k)
+    }
+
+    fn end(&self) -> usize {
+        self.steps.len() + 1
+    }
+
+    fn goto(&self, k: usize, span: Span) -> Body {
+        assign(&self.step_var(), float(k as f32, span), span)
+    }
+
+    // Resets the counter of the step `k` when the timeline reaches it.
+    fn enter(&self, k: usize, span: Span) -> Vec<Body> {
+        match self.steps.get(k - 1).map(|step| &step.kind) {
+            Some(StepKind::FramesElapsed(_)) => vec![assign(
+                &self.slot_var(k),
+                Expr::Symbol(Symbol::Ref(BulletId::Itself, StateId::Frames), span),
+                span,
+            )],
+            Some(StepKind::Wait(_)) => vec![assign(&self.slot_var(k), float(0.0, span), span)],
+            _ => vec![],
+        }
+    }
+
+    fn advance(&self, k: usize, span: Span) -> Vec<Body> {
+        let mut body = self.enter(k + 1, span);
+        body.push(self.goto(k + 1, span));
+        body
+    }
+
+    // Builds `if step == k { ... } else if step == k + 1 { ... } ... else { break }`.
+    fn dispatch(&self, k: usize) -> Body {
+        let span = self.span;
+        let (then, span_k) = if k == self.end() {
+            (self.at_end(), span)
+        } else {
+            let step = &self.steps[k - 1];
+            (self.at_step(k, step), step.span)
+        };
+        let els = if k == self.end() {
+            vec![Body::Break(span)]
+        } else {
+            vec![self.dispatch(k + 1)]
+        };
+
+        Body::If(
+            op(
+                Op2::Eq,
+                var(&self.step_var(), span_k),
+                float(k as f32, span_k),
+                span_k,
+            ),
+            then,
+            els,
+            span_k,
+        )
+    }
+
+    fn at_step(&self, k: usize, step: &TimelineStep) -> Vec<Body> {
+        let span = step.span;
+        let slot = self.slot_var(k);
+        let mut action: Vec<Body> = step
+            .action
+            .iter()
+            .map(|expr| Body::Expr(Box::new(expr.clone())))
+            .collect();
+
+        let (cond, mut then, mut els) = match &step.kind {
+            StepKind::Cond(cond) => (cond.clone(), action, vec![]),
+            StepKind::FramesElapsed(n) => {
+                let frames = Expr::Symbol(Symbol::Ref(BulletId::Itself, StateId::Frames), span);
+                let elapsed = op(Op2::Sub, frames, var(&slot, span), span);
+                (op(Op2::Gte, elapsed, n.clone(), span), action, vec![])
+            }
+            StepKind::Wait(n) => {
+                let count = op(Op2::Add, var(&slot, span), float(1.0, span), span);
+                let els = vec![assign(&slot, count, span)];
+                (op(Op2::Gte, var(&slot, span), n.clone(), span), action, els)
+            }
+            StepKind::Repeat(n) => {
+                let cond = op(Op2::Lt, var(&slot, span), n.clone(), span);
+                let count = op(Op2::Add, var(&slot, span), float(1.0, span), span);
+                let then = vec![assign(&slot, count, span)];
+
+                // all rounds are done so finish the timeline with the action
+                action.push(self.goto(self.end() + 1, span));
+                action.push(Body::Break(span));
+                return vec![Body::If(
+                    cond,
+                    [then, self.advance(k, span)].concat(),
+                    action,
+                    span,
+                )];
+            }
+        };
+        then.extend(self.advance(k, span));
+        els.push(Body::Break(span));
+
+        vec![Body::If(cond, then, els, span)]
+    }
+
+    fn at_end(&self) -> Vec<Body> {
+        let span = self.span;
+        let last_repeat = self
+            .steps
+            .iter()
+            .rposition(|step| matches!(step.kind, StepKind::Repeat(_)));
+
+        match last_repeat {
+            Some(idx) => vec![self.goto(idx + 1, span)],
+            None => vec![Body::Break(span)],
+        }
+    }
+}
+
+fn global(name: &str, span: Span) -> SyntaxTree {
+    SyntaxTree::GlobalDefine(Symbol::Var(Name(name.to_string())), float(0.0, span), span)
+}
+
+fn var(name: &str, span: Span) -> Expr {
+    Expr::Symbol(Symbol::Var(Name(name.to_string())), span)
+}
+
+fn float(f: f32, span: Span) -> Expr {
+    Expr::Float(f, span)
+}
+
+fn op(op: Op2, lhs: Expr, rhs: Expr, span: Span) -> Expr {
+    Expr::Op2(op, Box::new(lhs), Box::new(rhs), span)
+}
+
+fn assign(name: &str, expr: Expr, span: Span) -> Body {
+    Body::Assignment(Symbol::Var(Name(name.to_string())), expr, span)
+}
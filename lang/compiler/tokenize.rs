@@ -1,15 +1,19 @@
 use nom::{
     branch::alt,
-    bytes::complete::tag,
+    bytes::complete::{is_not, tag, take_until},
     character::complete::{char, digit1, none_of, one_of, space0, space1},
-    combinator::{opt, peek},
+    combinator::{map, opt, peek},
     error::{Error, ErrorKind},
     multi::{many0, many1},
-    sequence::tuple,
+    sequence::{preceded, tuple},
     Err, IResult,
 };
 
-use lang_component::{syntax::Type, token::*};
+use lang_component::{
+    span::{Pos, Span},
+    syntax::Type,
+    token::*,
+};
 
 fn tokenize_float(s: &str) -> IResult<&str, Token> {
     let (s, minus) = opt(char('-'))(s)?;
@@ -44,10 +48,14 @@ fn tokenize_delimiter_str(s: &str) -> IResult<&str, &str> {
         tag(")"),
         tag("{"),
         tag("}"),
+        tag("["),
+        tag("]"),
         tag("\n"),
         tag(":"),
         tag(","),
         tag("->"),
+        tag("=>"),
+        tag(".."),
         tag("."),
     ))(s)
 }
@@ -58,9 +66,13 @@ fn tokenize_delimiter(s: &str) -> IResult<&str, Token> {
         (s, ")") => Ok((s, Token::Delim(Box::new(Delimiter::CloseParen)))),
         (s, "{") => Ok((s, Token::Delim(Box::new(Delimiter::OpenBrace)))),
         (s, "}") => Ok((s, Token::Delim(Box::new(Delimiter::CloseBrace)))),
+        (s, "[") => Ok((s, Token::Delim(Box::new(Delimiter::OpenBracket)))),
+        (s, "]") => Ok((s, Token::Delim(Box::new(Delimiter::CloseBracket)))),
         (s, ":") => Ok((s, Token::Delim(Box::new(Delimiter::Colon)))),
         (s, ",") => Ok((s, Token::Delim(Box::new(Delimiter::Camma)))),
         (s, "->") => Ok((s, Token::Delim(Box::new(Delimiter::Arrow)))),
+        (s, "=>") => Ok((s, Token::Delim(Box::new(Delimiter::FatArrow)))),
+        (s, "..") => Ok((s, Token::Delim(Box::new(Delimiter::Range)))),
         (s, ".") => Ok((s, Token::Delim(Box::new(Delimiter::Dot)))),
         (s, _) => Err(Err::Error(Error::new(s, ErrorKind::Char))),
     }
@@ -86,25 +98,47 @@ fn tokenize_keyword(s: &str) -> IResult<&str, Token> {
     match tuple((
         alt((
             tag("proc"),
+            tag("bullet"),
+            tag("timeline"),
+            tag("nil"),
             tag("return"),
+            tag("times"),
+            tag("while"),
+            tag("for"),
+            tag("in"),
+            tag("break"),
+            tag("continue"),
             tag("if"),
             tag("else"),
             tag("let"),
             tag("global"),
             tag("player"),
             tag("self"),
+            tag("parent"),
+            tag("child"),
         )),
-        alt((space1, peek(tokenize_delimiter_str))),
+        alt((peek(space1), peek(tokenize_delimiter_str))),
     ))(s)?
     {
         (s, ("proc", _)) => Ok((s, Token::Keyword(Box::new(Keyword::Proc)))),
+        (s, ("bullet", _)) => Ok((s, Token::Keyword(Box::new(Keyword::Bullet)))),
+        (s, ("timeline", _)) => Ok((s, Token::Keyword(Box::new(Keyword::Timeline)))),
+        (s, ("nil", _)) => Ok((s, Token::Keyword(Box::new(Keyword::Nil)))),
         (s, ("return", _)) => Ok((s, Token::Keyword(Box::new(Keyword::Return)))),
+        (s, ("times", _)) => Ok((s, Token::Keyword(Box::new(Keyword::Times)))),
+        (s, ("while", _)) => Ok((s, Token::Keyword(Box::new(Keyword::While)))),
+        (s, ("for", _)) => Ok((s, Token::Keyword(Box::new(Keyword::For)))),
+        (s, ("in", _)) => Ok((s, Token::Keyword(Box::new(Keyword::In)))),
+        (s, ("break", _)) => Ok((s, Token::Keyword(Box::new(Keyword::Break)))),
+        (s, ("continue", _)) => Ok((s, Token::Keyword(Box::new(Keyword::Continue)))),
         (s, ("if", _)) => Ok((s, Token::Keyword(Box::new(Keyword::If)))),
         (s, ("else", _)) => Ok((s, Token::Keyword(Box::new(Keyword::Else)))),
         (s, ("let", _)) => Ok((s, Token::Keyword(Box::new(Keyword::Let)))),
         (s, ("global", _)) => Ok((s, Token::Keyword(Box::new(Keyword::Global)))),
         (s, ("player", _)) => Ok((s, Token::Keyword(Box::new(Keyword::Player)))),
         (s, ("self", _)) => Ok((s, Token::Keyword(Box::new(Keyword::SelfKw)))),
+        (s, ("parent", _)) => Ok((s, Token::Keyword(Box::new(Keyword::Parent)))),
+        (s, ("child", _)) => Ok((s, Token::Keyword(Box::new(Keyword::Child)))),
         (s, _) => Err(Err::Error(Error::new(s, ErrorKind::Char))),
     }
 }
@@ -116,10 +150,10 @@ fn tokenize_op(s: &str) -> IResult<&str, Token> {
         tag("*"),
         tag("/"),
         tag("%"),
-        tag(">"),
-        tag("<"),
         tag(">="),
         tag("<="),
+        tag(">"),
+        tag("<"),
         tag("=="),
         tag("||"),
         tag("&&"),
@@ -154,7 +188,8 @@ fn tokenize_ident(s: &str) -> IResult<&str, Token> {
     let alpha = "abcdefghijklmnopqrstuvwxyz";
     let alpha_cap = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
     let underbar = "_";
-    let first_chars = ["$", alpha, alpha_cap, underbar].concat();
+    // `#` is for implicit variables like `#n` in `times` loops
+    let first_chars = ["$", "#", alpha, alpha_cap, underbar].concat();
     let rest_chars = [digits, alpha, alpha_cap, underbar].concat();
 
     let (s, (first, rest)) = tuple((
@@ -168,17 +203,53 @@ fn tokenize_ident(s: &str) -> IResult<&str, Token> {
     Ok((s, Token::Ident(ident)))
 }
 
-pub fn tokenize(s: &str) -> IResult<&str, Vec<Token>> {
-    let mut tokens: Vec<Token> = Vec::new();
+// Skips a line comment `// ...` or a block comment `/* ... */`. Line comments leave the
+// newline, and block comments over multiple lines are read as a newline to end statements.
+fn tokenize_comment(s: &str) -> IResult<&str, Option<Token>> {
+    if let Ok((body, _)) = tag::<&str, &str, Error<&str>>("/*")(s) {
+        return match take_until::<&str, &str, Error<&str>>("*/")(body) {
+            Ok((rest, comment)) if comment.contains('\n') => Ok((&rest[2..], Some(Token::Newline))),
+            Ok((rest, _)) => Ok((&rest[2..], None)),
+            Err(_) => Err(Err::Failure(Error::new(s, ErrorKind::TakeUntil))),
+        };
+    }
+
+    map(preceded(tag("//"), opt(is_not("\n"))), |_| None)(s)
+}
+
+// Returns the position of `rest` in `source`, where `rest` is a suffix of `source`.
+pub fn position(source: &str, rest: &str) -> Pos {
+    let offset = source.len() - rest.len();
+    Pos::new(0, 1, 1).advance(&source[..offset])
+}
+
+pub fn tokenize(s: &str) -> IResult<&str, Vec<SpannedToken>> {
+    let mut tokens: Vec<SpannedToken> = Vec::new();
     let mut input = s;
+    let mut pos = Pos::new(0, 1, 1);
 
     loop {
         let (s, _) = opt(space0)(input)?;
+        pos = pos.advance(&input[..input.len() - s.len()]);
         input = s;
         if s == "" {
             break;
         }
 
+        match tokenize_comment(input) {
+            Ok((s, token)) => {
+                let start = pos;
+                pos = pos.advance(&input[..input.len() - s.len()]);
+                input = s;
+                if let Some(token) = token {
+                    tokens.push(SpannedToken::new(token, Span::new(start, pos)));
+                }
+                continue;
+            }
+            Err(Err::Error(_)) => (),
+            Err(err) => return Err(err),
+        }
+
         let (s, token) = alt((
             tokenize_float,
             tokenize_string,
@@ -190,10 +261,12 @@ pub fn tokenize(s: &str) -> IResult<&str, Vec<Token>> {
             tokenize_misc,
             tokenize_ident,
         ))(input)?;
+        let start = pos;
+        pos = pos.advance(&input[..input.len() - s.len()]);
         input = s;
-        tokens.push(token);
+        tokens.push(SpannedToken::new(token, Span::new(start, pos)));
     }
-    tokens.push(Token::Eof);
+    tokens.push(SpannedToken::new(Token::Eof, Span::new(pos, pos)));
 
     Ok((input, tokens))
 }
@@ -252,6 +325,7 @@ mod tokenizer_test {
 
     fn test_tokenize_1(expected: Vec<Token>, input: &str) {
         if let Ok(("", result)) = tokenize(input) {
+            let result: Vec<Token> = result.into_iter().map(|t| t.token).collect();
             assert_eq!(expected, result);
         } else {
             println!("result = {:?}", tokenize(input));
@@ -481,4 +555,164 @@ mod tokenizer_test {
             "##,
         )
     }
+
+    #[test]
+    fn test_tokenize_parent_and_child_reference() {
+        test_tokenize_1(
+            vec![
+                Token::Newline,
+                Token::Keyword(Box::new(Keyword::Child)),
+                Token::Delim(Box::new(Delimiter::Dot)),
+                Token::Ident("x".to_string()),
+                Token::Assign,
+                Token::Keyword(Box::new(Keyword::Parent)),
+                Token::Delim(Box::new(Delimiter::Dot)),
+                Token::Ident("x".to_string()),
+                Token::Newline,
+                Token::Eof,
+            ],
+            r##"
+            child.x = parent.x
+            "##,
+        )
+    }
+
+    #[test]
+    fn test_tokenize_times() {
+        test_tokenize_1(
+            vec![
+                Token::Keyword(Box::new(Keyword::Times)),
+                Token::Float(Float(5.0)),
+                Token::Delim(Box::new(Delimiter::OpenBrace)),
+                Token::Ident("#n".to_string()),
+                Token::Delim(Box::new(Delimiter::CloseBrace)),
+                Token::Ident("times_2".to_string()),
+                Token::Eof,
+            ],
+            "times 5 { #n } times_2",
+        )
+    }
+
+    #[test]
+    fn test_tokenize_loops() {
+        test_tokenize_1(
+            vec![
+                Token::Keyword(Box::new(Keyword::For)),
+                Token::Ident("i".to_string()),
+                Token::Keyword(Box::new(Keyword::In)),
+                Token::Float(Float(0.0)),
+                Token::Delim(Box::new(Delimiter::Range)),
+                Token::Ident("n".to_string()),
+                Token::Delim(Box::new(Delimiter::OpenBrace)),
+                Token::Keyword(Box::new(Keyword::While)),
+                Token::Ident("i".to_string()),
+                Token::Op(Box::new(BinOp::Gte)),
+                Token::Float(Float(1.0)),
+                Token::Delim(Box::new(Delimiter::OpenBrace)),
+                Token::Keyword(Box::new(Keyword::Break)),
+                Token::Delim(Box::new(Delimiter::CloseBrace)),
+                Token::Keyword(Box::new(Keyword::Continue)),
+                Token::Delim(Box::new(Delimiter::CloseBrace)),
+                Token::Eof,
+            ],
+            "for i in 0..n { while i >= 1 { break } continue }",
+        )
+    }
+
+    #[test]
+    fn test_tokenize_timeline() {
+        test_tokenize_1(
+            vec![
+                Token::Keyword(Box::new(Keyword::Timeline)),
+                Token::Ident("t".to_string()),
+                Token::Delim(Box::new(Delimiter::OpenParen)),
+                Token::Delim(Box::new(Delimiter::CloseParen)),
+                Token::Delim(Box::new(Delimiter::OpenBracket)),
+                Token::Ident("a".to_string()),
+                Token::Op(Box::new(BinOp::Eq)),
+                Token::Float(Float(1.0)),
+                Token::Delim(Box::new(Delimiter::FatArrow)),
+                Token::Keyword(Box::new(Keyword::Nil)),
+                Token::Delim(Box::new(Delimiter::CloseBracket)),
+                Token::Eof,
+            ],
+            "timeline t() [ a == 1 => nil ]",
+        )
+    }
+
+    #[test]
+    fn test_tokenize_spans() {
+        let (_, tokens) = tokenize("proc main() {\n  return 42\n}").unwrap();
+        let spans: Vec<((usize, usize), (usize, usize))> = tokens
+            .iter()
+            .map(|t| {
+                (
+                    (t.span.start.line, t.span.start.column),
+                    (t.span.end.line, t.span.end.column),
+                )
+            })
+            .collect();
+
+        assert_eq!(
+            vec![
+                ((1, 1), (1, 5)),   // proc
+                ((1, 6), (1, 10)),  // main
+                ((1, 10), (1, 11)), // (
+                ((1, 11), (1, 12)), // )
+                ((1, 13), (1, 14)), // {
+                ((1, 14), (2, 1)),  // newline
+                ((2, 3), (2, 9)),   // return
+                ((2, 10), (2, 12)), // 42
+                ((2, 12), (3, 1)),  // newline
+                ((3, 1), (3, 2)),   // }
+                ((3, 2), (3, 2)),   // EOF
+            ],
+            spans
+        );
+        assert_eq!(16, tokens[6].span.start.offset);
+    }
+
+    #[test]
+    fn test_tokenize_comments() {
+        test_tokenize_1(
+            vec![
+                Token::Newline,
+                Token::Keyword(Box::new(Keyword::Let)),
+                Token::Ident("a".to_string()),
+                Token::Assign,
+                Token::Float(Float(1.0)),
+                Token::Newline,
+                Token::Eof,
+            ],
+            "//// full line comment\nlet a = 1 // trailing comment\n",
+        );
+        test_tokenize_1(
+            vec![
+                Token::Float(Float(1.0)),
+                Token::Op(Box::new(BinOp::Plus)),
+                Token::Float(Float(2.0)),
+                Token::Newline,
+                Token::Float(Float(3.0)),
+                Token::Eof,
+            ],
+            "1 /* inline */ + 2 /* multi\nline */ 3",
+        );
+        test_tokenize_1(
+            vec![
+                Token::Float(Float(4.0)),
+                Token::Op(Box::new(BinOp::Slash)),
+                Token::Float(Float(2.0)),
+                Token::Eof,
+            ],
+            "4 / 2 // end of file",
+        );
+    }
+
+    #[test]
+    fn test_tokenize_unterminated_block_comment() {
+        match tokenize("1 /* comment") {
+            Err(Err::Failure(err)) => assert_eq!("/* comment", err.input),
+            result => panic!("result = {:?}", result),
+        }
+    }
 }
@@ -1,9 +1,14 @@
-use crate::{token::Keyword, vm::Data};
+use crate::{syntax::Type, token::Keyword, vm::Data};
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum BulletType {
     Player,
+    // bullets fired by the player
+    PlayerShot,
     Bullet1,
+    // score items left by bullets cleared with bombs
+    Item,
+    Enemy1,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -15,6 +20,10 @@ pub enum BulletColor {
 pub enum BulletId {
     Itself,
     Player,
+    // the object which fired or spawned itself
+    Parent,
+    // the object last fired or spawned by itself
+    Child,
     Enemy(usize),
     Bullet(usize),
 }
@@ -24,6 +33,8 @@ impl From<BulletId> for String {
         match bid {
             BulletId::Itself => "self".to_string(),
             BulletId::Player => "player".to_string(),
+            BulletId::Parent => "parent".to_string(),
+            BulletId::Child => "child".to_string(),
             BulletId::Enemy(id) => format!("enemy_{}", id),
             BulletId::Bullet(id) => format!("bullet_{}", id),
         }
@@ -37,6 +48,8 @@ impl TryFrom<Keyword> for BulletId {
         match kw {
             Keyword::SelfKw => Ok(BulletId::Itself),
             Keyword::Player => Ok(BulletId::Player),
+            Keyword::Parent => Ok(BulletId::Parent),
+            Keyword::Child => Ok(BulletId::Child),
             _ => Err(()),
         }
     }
@@ -57,7 +70,28 @@ pub enum StateId {
     InputRight,
     InputShot,
     InputSlow,
+    InputBomb,
     Enabled,
+    // frames since the stage started, which is the same for all bullets
+    Frames,
+    // frames since the bullet was fired
+    Age,
+    // motion integrated by the engine after scripts run; angles are in degrees
+    VelX,
+    VelY,
+    Speed,
+    Angle,
+    Accel,
+    AngularVelocity,
+    // whether the bullet hit something in the last frame
+    Hit,
+    // hit points of enemies
+    Hp,
+    // counters of the stage, which are the same for all bullets
+    Score,
+    Graze,
+    Lives,
+    Bombs,
 }
 
 impl TryFrom<&str> for StateId {
@@ -73,8 +107,55 @@ impl TryFrom<&str> for StateId {
             "input_right" => Ok(StateId::InputRight),
             "input_shot" => Ok(StateId::InputShot),
             "input_slow" => Ok(StateId::InputSlow),
+            "input_bomb" => Ok(StateId::InputBomb),
             "input_enabled" => Ok(StateId::Enabled),
+            "enabled" => Ok(StateId::Enabled),
+            "frames" => Ok(StateId::Frames),
+            "age" => Ok(StateId::Age),
+            "vx" => Ok(StateId::VelX),
+            "vy" => Ok(StateId::VelY),
+            "speed" => Ok(StateId::Speed),
+            "angle" => Ok(StateId::Angle),
+            "accel" => Ok(StateId::Accel),
+            "angular_velocity" => Ok(StateId::AngularVelocity),
+            "hit" => Ok(StateId::Hit),
+            "hp" => Ok(StateId::Hp),
+            "score" => Ok(StateId::Score),
+            "graze" => Ok(StateId::Graze),
+            "lives" => Ok(StateId::Lives),
+            "bombs" => Ok(StateId::Bombs),
             _ => Err(()),
         }
     }
 }
+
+impl StateId {
+    pub fn r#type(&self) -> Type {
+        match self {
+            StateId::InputUp
+            | StateId::InputDown
+            | StateId::InputLeft
+            | StateId::InputRight
+            | StateId::InputShot
+            | StateId::InputSlow
+            | StateId::InputBomb
+            | StateId::Enabled
+            | StateId::Hit => Type::Bool,
+            StateId::PosX
+            | StateId::PosY
+            | StateId::Frames
+            | StateId::Age
+            | StateId::VelX
+            | StateId::VelY
+            | StateId::Speed
+            | StateId::Angle
+            | StateId::Accel
+            | StateId::AngularVelocity
+            | StateId::Hp
+            | StateId::Score
+            | StateId::Graze
+            | StateId::Lives
+            | StateId::Bombs => Type::Float,
+        }
+    }
+}
@@ -1,4 +1,5 @@
 pub mod bullet;
+pub mod span;
 pub mod syntax;
 pub mod token;
 pub mod vm;
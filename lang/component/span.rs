@@ -0,0 +1,54 @@
+// A position in source code. `line` and `column` start from 1 and `column` counts characters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Pos {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Pos {
+    pub fn new(offset: usize, line: usize, column: usize) -> Self {
+        Self {
+            offset,
+            line,
+            column,
+        }
+    }
+
+    // Returns the position after reading `s` from this position.
+    pub fn advance(&self, s: &str) -> Self {
+        let mut pos = *self;
+
+        for ch in s.chars() {
+            pos.offset += ch.len_utf8();
+            if ch == '\n' {
+                pos.line += 1;
+                pos.column = 1;
+            } else {
+                pos.column += 1;
+            }
+        }
+
+        pos
+    }
+}
+
+// A range in source code; `end` points just after the last character.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: Pos,
+    pub end: Pos,
+}
+
+impl Span {
+    pub fn new(start: Pos, end: Pos) -> Self {
+        Self { start, end }
+    }
+
+    pub fn join(&self, other: &Span) -> Self {
+        Self {
+            start: self.start,
+            end: other.end,
+        }
+    }
+}
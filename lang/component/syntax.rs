@@ -1,4 +1,7 @@
-use crate::bullet::{BulletId, StateId};
+use crate::{
+    bullet::{BulletId, StateId},
+    span::Span,
+};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Type {
@@ -7,6 +10,17 @@ pub enum Type {
     Bool,
 }
 
+impl Type {
+    // Returns the size of values of the type in VM memory.
+    pub fn size(&self) -> usize {
+        match self {
+            Type::Float => 4,
+            Type::Bool => 1,
+            Type::String => 0, // string stored as [size, ch0, ch1, ...]
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Name(pub String);
 
@@ -72,28 +86,110 @@ pub enum Symbol {
     Ref(BulletId, StateId), // player.x
 }
 
+// Each node has the span where it is written in the source.
 #[derive(Debug, Clone, PartialEq)]
 pub enum Body {
-    LexicalDefine(Symbol, Expr),
-    Assignment(Symbol, Expr),
-    Return(Option<Expr>),
+    LexicalDefine(Symbol, Expr, Span),
+    Assignment(Symbol, Expr, Span),
+    Return(Option<Expr>, Span),
+    // `times N { ... }` runs the block N times with the loop counter `#n` from 0
+    Times(Expr, Vec<Body>, Span),
+    While(Expr, Vec<Body>, Span),
+    // `for NAME in FROM..TO { ... }` runs the block with NAME from FROM up to TO (exclusive)
+    For(Name, Expr, Expr, Vec<Body>, Span),
+    Break(Span),
+    Continue(Span),
+    // `if COND { ... } else { ... }` as a statement; `else if` is nested in the else block
+    If(Expr, Vec<Body>, Vec<Body>, Span),
     Expr(Box<Expr>),
 }
 
+impl Body {
+    pub fn span(&self) -> Span {
+        match self {
+            Body::LexicalDefine(_, _, span) => *span,
+            Body::Assignment(_, _, span) => *span,
+            Body::Return(_, span) => *span,
+            Body::Times(_, _, span) => *span,
+            Body::While(_, _, span) => *span,
+            Body::For(_, _, _, _, span) => *span,
+            Body::Break(span) => *span,
+            Body::Continue(span) => *span,
+            Body::If(_, _, _, span) => *span,
+            Body::Expr(expr) => expr.span(),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Expr {
-    Float(f32),
-    Bool(bool),
-    String(String),
-    Symbol(Symbol),
-    Op2(Op2, Box<Expr>, Box<Expr>),
-    // If(Box<Expr>, Vec<Body>, Vec<Body>),
-    If(Box<Expr>, Box<Expr>, Box<Expr>),
-    ProcCall(Name, Vec<Expr>),
+    Float(f32, Span),
+    Bool(bool, Span),
+    String(String, Span),
+    Symbol(Symbol, Span),
+    Op2(Op2, Box<Expr>, Box<Expr>, Span),
+    If(Box<Expr>, Box<Expr>, Box<Expr>, Span),
+    ProcCall(Name, Vec<Expr>, Span),
+}
+
+impl Expr {
+    pub fn span(&self) -> Span {
+        match self {
+            Expr::Float(_, span) => *span,
+            Expr::Bool(_, span) => *span,
+            Expr::String(_, span) => *span,
+            Expr::Symbol(_, span) => *span,
+            Expr::Op2(_, _, _, span) => *span,
+            Expr::If(_, _, _, span) => *span,
+            Expr::ProcCall(_, _, span) => *span,
+        }
+    }
+}
+
+// Conditions of timeline steps.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StepKind {
+    // `repeat(N)` runs the following steps N times, then runs the action and finishes
+    Repeat(Expr),
+    // `frames_elapsed(N)` passes when N frames have elapsed since the step is reached
+    FramesElapsed(Expr),
+    // `wait(N)` passes when the timeline is called N more times after reaching the step
+    Wait(Expr),
+    // other expressions pass when they are true
+    Cond(Expr),
+}
+
+// A step of timelines written as `CONDITION => ACTION`. `nil` action is `None`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimelineStep {
+    pub kind: StepKind,
+    pub action: Option<Expr>,
+    pub span: Span,
+}
+
+impl TimelineStep {
+    pub fn new(kind: StepKind, action: Option<Expr>, span: Span) -> Self {
+        Self { kind, action, span }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum SyntaxTree {
-    GlobalDefine(Symbol, Expr),
-    DefProc(Name, Signature, Vec<Body>),
+    GlobalDefine(Symbol, Expr, Span),
+    DefProc(Name, Signature, Vec<Body>, Span),
+    // a bullet program fired with `fire(x, y, name(args...))`; `args` are stored in its memory
+    DefBullet(Name, Signature, Vec<Body>, Span),
+    // a proc running its steps in order over calls, with hidden states in memory
+    DefTimeline(Name, Signature, Vec<TimelineStep>, Span),
+}
+
+impl SyntaxTree {
+    pub fn span(&self) -> Span {
+        match self {
+            SyntaxTree::GlobalDefine(_, _, span) => *span,
+            SyntaxTree::DefProc(_, _, _, span) => *span,
+            SyntaxTree::DefBullet(_, _, _, span) => *span,
+            SyntaxTree::DefTimeline(_, _, _, span) => *span,
+        }
+    }
 }
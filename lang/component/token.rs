@@ -1,4 +1,6 @@
-use crate::syntax::Type;
+use std::fmt;
+
+use crate::{span::Span, syntax::Type};
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Float(pub f32);
@@ -7,40 +9,66 @@ impl Eq for Float {}
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Keyword {
     Proc,
+    Bullet,
+    Timeline,
+    Nil,
     Return,
+    Times,
+    While,
+    For,
+    In,
+    Break,
+    Continue,
     If,
     Else,
     Let,
     Global,
     Player,
     SelfKw,
+    Parent,
+    Child,
 }
 
 impl From<Keyword> for String {
     fn from(kw: Keyword) -> String {
         match kw {
             Keyword::Proc => "proc".to_string(),
+            Keyword::Bullet => "bullet".to_string(),
+            Keyword::Timeline => "timeline".to_string(),
+            Keyword::Nil => "nil".to_string(),
             Keyword::Return => "return".to_string(),
+            Keyword::Times => "times".to_string(),
+            Keyword::While => "while".to_string(),
+            Keyword::For => "for".to_string(),
+            Keyword::In => "in".to_string(),
+            Keyword::Break => "break".to_string(),
+            Keyword::Continue => "continue".to_string(),
             Keyword::If => "if".to_string(),
             Keyword::Else => "else".to_string(),
             Keyword::Let => "let".to_string(),
             Keyword::Global => "global".to_string(),
             Keyword::Player => "player".to_string(),
             Keyword::SelfKw => "self".to_string(),
+            Keyword::Parent => "parent".to_string(),
+            Keyword::Child => "child".to_string(),
         }
     }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Delimiter {
-    OpenParen,  // '('
-    CloseParen, // ')'
-    OpenBrace,  // '{'
-    CloseBrace, // '}'
-    Colon,      // ':'
-    Camma,      // ','
-    Arrow,      // '->'
-    Dot,        // '.'
+    OpenParen,    // '('
+    CloseParen,   // ')'
+    OpenBrace,    // '{'
+    CloseBrace,   // '}'
+    Colon,        // ':'
+    Camma,        // ','
+    Arrow,        // '->'
+    Dot,          // '.'
+    Range,        // '..'
+    OpenBracket,  // '['
+    CloseBracket, // ']'
+    FatArrow,     // '=>'
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -75,6 +103,74 @@ pub enum Token {
     Type(Box<Type>),
 }
 
+// Prints tokens as they are written in source code, for error messages.
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Float(Float(v)) => write!(f, "`{:?}`", v),
+            Token::String(s) => write!(f, "`\"{}\"`", s),
+            Token::Keyword(kw) => write!(f, "`{}`", String::from(*kw.clone())),
+            Token::Delim(delim) => {
+                let s = match **delim {
+                    Delimiter::OpenParen => "(",
+                    Delimiter::CloseParen => ")",
+                    Delimiter::OpenBrace => "{",
+                    Delimiter::CloseBrace => "}",
+                    Delimiter::Colon => ":",
+                    Delimiter::Camma => ",",
+                    Delimiter::Arrow => "->",
+                    Delimiter::Dot => ".",
+                    Delimiter::Range => "..",
+                    Delimiter::OpenBracket => "[",
+                    Delimiter::CloseBracket => "]",
+                    Delimiter::FatArrow => "=>",
+                };
+                write!(f, "`{}`", s)
+            }
+            Token::Op(op) => {
+                let s = match **op {
+                    BinOp::Asterisk => "*",
+                    BinOp::Slash => "/",
+                    BinOp::Percent => "%",
+                    BinOp::Plus => "+",
+                    BinOp::Minus => "-",
+                    BinOp::Gt => ">",
+                    BinOp::Lt => "<",
+                    BinOp::Gte => ">=",
+                    BinOp::Lte => "<=",
+                    BinOp::Eq => "==",
+                    BinOp::LogOr => "||",
+                    BinOp::LogAnd => "&&",
+                };
+                write!(f, "`{}`", s)
+            }
+            Token::Assign => write!(f, "`=`"),
+            Token::Newline => write!(f, "newline"),
+            Token::Ident(name) => write!(f, "`{}`", name),
+            Token::Eof => write!(f, "end of file"),
+            Token::True => write!(f, "`true`"),
+            Token::False => write!(f, "`false`"),
+            Token::Type(t) => match **t {
+                Type::Float => write!(f, "`float`"),
+                Type::String => write!(f, "`string`"),
+                Type::Bool => write!(f, "`bool`"),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpannedToken {
+    pub token: Token,
+    pub span: Span,
+}
+
+impl SpannedToken {
+    pub fn new(token: Token, span: Span) -> Self {
+        Self { token, span }
+    }
+}
+
 pub fn token_type_eq(t1: &Token, t2: &Token) -> bool {
     match t1 {
         Token::Float(_) => matches!(t2, Token::Float(_)),
@@ -23,13 +23,18 @@ impl Data {
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum ExternalOperation {
-    Fire(usize),
+    // fires the bullet code of the id with the number of params
+    Fire(usize, usize),
+    // spawns an enemy running the bullet code of the id with the number of params
+    Spawn(usize, usize),
     Die,
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum OperationQuery {
-    Fire(usize, (f32, f32), BulletType, BulletColor, Vec<Data>),
+    // the last field of `Fire` and `Spawn` is the index of the object which queued them
+    Fire(usize, (f32, f32), BulletType, BulletColor, Vec<Data>, usize),
+    Spawn(usize, (f32, f32), Vec<Data>, usize),
     Die(usize),
 }
 
@@ -47,12 +52,25 @@ pub enum Inst {
     // state accessors
     RefRead(BulletId, StateId),
     RefWrite(BulletId, StateId),
+    // random numbers in [0, 1) given by the host to be reproducible
+    Rand,
+    // pops frames to wait and suspends the program until the next frame while they remain
+    Yield,
     // arithmetics
     Add,
     Sub,
     Mul,
     Div,
     Mod,
+    // math functions; angles are in degrees
+    Sin,
+    Cos,
+    Atan2, // atan2(y, x)
+    Sqrt,
+    Abs,
+    Min,
+    Max,
+    Floor,
     // comparators
     EqInt,
     EqFloat,
@@ -68,6 +86,7 @@ pub enum Inst {
     Dup,
     Drop,
     Index,
+    Store, // replaces the nth value from the stack top with the value under n
     // control flows
     JumpIfFalse(i32),
     Jump(i32),
@@ -86,6 +105,7 @@ pub fn get_vm_name(state_name: &str) -> Option<String> {
         "input_right" => Some("Input:Right".to_string()),
         "input_shot" => Some("Input:Shot".to_string()),
         "input_slow" => Some("Input:Slow".to_string()),
+        "input_bomb" => Some("Input:Bomb".to_string()),
         _ => None,
     }
 }
@@ -13,16 +13,47 @@ pub enum SuspendingReason {
     Running,
     ToReadState(BulletId, StateId),
     ToWriteState(BulletId, StateId, Data),
+    // the host pushes a random float in [0, 1)
+    ToGetRandom,
+    // the program waits for the next frame, where `start()` resumes it
+    Yielded,
 }
 
 impl VM {
+    pub fn write_memory(&mut self, offset: usize, data: Data) -> Result<(), RuntimeError> {
+        check_memory_bound!(self.memory, offset, data.r#type());
+
+        match data {
+            Data::Float(f) => {
+                let le_4bytes = f.to_le_bytes();
+                for (idx, byte) in self.memory.as_mut_slice()[offset..offset + 4]
+                    .iter_mut()
+                    .enumerate()
+                {
+                    *byte = le_4bytes[idx];
+                }
+            }
+            Data::Bool(b) => {
+                self.memory[offset] = if b { 1 } else { 0 };
+            }
+            Data::String(_) => todo!("storing strings in memory is not implemented"),
+        };
+
+        Ok(())
+    }
+
     pub fn start(
         &mut self,
         id: usize,
         op_queue: &mut VecDeque<OperationQuery>,
     ) -> Result<SuspendingReason, RuntimeError> {
         //self.stack.clear();
-        self.pc = 0;
+        // a program suspended by `wait()` continues with its stacks
+        if self.suspended {
+            self.suspended = false;
+        } else {
+            self.pc = 0;
+        }
 
         self.resume(id, op_queue)
     }
@@ -58,7 +89,13 @@ impl VM {
             Some(inst) => match inst {
                 Inst::Term => Ok(SuspendingReason::Terminated),
                 Inst::Operate(op) => match op {
-                    ExternalOperation::Fire(id) => {
+                    ExternalOperation::Fire(bullet_id, param_num) => {
+                        let mut params = Vec::new();
+                        for _ in 0..*param_num {
+                            params.push(stack_pop!(self.stack));
+                        }
+                        params.reverse();
+
                         let y = stack_pop!(self.stack);
                         let x = stack_pop!(self.stack);
                         #[allow(irrefutable_let_patterns)]
@@ -67,19 +104,45 @@ impl VM {
                         let y = float_data!(y);
 
                         let query = OperationQuery::Fire(
-                            *id,
+                            *bullet_id,
                             (x, y),
                             BulletType::Bullet1,
                             BulletColor::White,
-                            vec![],
+                            params,
+                            id,
                         );
                         op_queue.push_front(query);
+                        // fire() is an expression so it leaves a value
+                        self.stack.push(Data::Bool(true));
+
+                        Ok(SuspendingReason::Running)
+                    }
+                    ExternalOperation::Spawn(bullet_id, param_num) => {
+                        let mut params = Vec::new();
+                        for _ in 0..*param_num {
+                            params.push(stack_pop!(self.stack));
+                        }
+                        params.reverse();
+
+                        let y = stack_pop!(self.stack);
+                        let x = stack_pop!(self.stack);
+                        #[allow(irrefutable_let_patterns)]
+                        let x = float_data!(x);
+                        #[allow(irrefutable_let_patterns)]
+                        let y = float_data!(y);
+
+                        let query = OperationQuery::Spawn(*bullet_id, (x, y), params, id);
+                        op_queue.push_front(query);
+                        // spawn() is an expression so it leaves a value
+                        self.stack.push(Data::Bool(true));
 
                         Ok(SuspendingReason::Running)
                     }
                     ExternalOperation::Die => {
                         let query = OperationQuery::Die(id);
                         op_queue.push_front(query);
+                        // die() is an expression so it leaves a value
+                        self.stack.push(Data::Bool(true));
 
                         Ok(SuspendingReason::Running)
                     }
@@ -109,24 +172,7 @@ impl VM {
                 }
                 Inst::Write(offset) => {
                     let data = stack_pop!(self.stack);
-                    let offset = *offset;
-                    check_memory_bound!(self.memory, offset, data.r#type());
-
-                    match data {
-                        Data::Float(f) => {
-                            let le_4bytes = f.to_le_bytes();
-                            for (idx, byte) in self.memory.as_mut_slice()[offset..offset + 4]
-                                .iter_mut()
-                                .enumerate()
-                            {
-                                *byte = le_4bytes[idx];
-                            }
-                        }
-                        Data::Bool(b) => {
-                            self.memory[offset] = if b { 1 } else { 0 };
-                        }
-                        Data::String(_) => todo!("storing strings in memory is not implemented"),
-                    };
+                    self.write_memory(*offset, data)?;
 
                     Ok(SuspendingReason::Running)
                 }
@@ -143,6 +189,26 @@ impl VM {
                     let d = stack_pop!(self.stack);
                     Ok(SuspendingReason::ToWriteState(*bid, *sid, d))
                 }
+                Inst::Rand => Ok(SuspendingReason::ToGetRandom),
+                Inst::Yield => {
+                    let frames = stack_pop!(self.stack);
+                    #[allow(irrefutable_let_patterns)]
+                    let frames = float_data!(frames);
+
+                    if frames >= 1.0 {
+                        // counts down the frames by running this again at the next frame
+                        self.stack.push(Data::Float(frames - 1.0));
+                        self.pc = pc;
+                        self.suspended = true;
+
+                        Ok(SuspendingReason::Yielded)
+                    } else {
+                        // wait() is an expression so it leaves a value
+                        self.stack.push(Data::Bool(true));
+
+                        Ok(SuspendingReason::Running)
+                    }
+                }
                 Inst::Add | Inst::Sub | Inst::Mul | Inst::Div | Inst::Mod => {
                     let b = stack_pop!(self.stack);
                     let a = stack_pop!(self.stack);
@@ -161,6 +227,37 @@ impl VM {
 
                     Ok(SuspendingReason::Running)
                 }
+                Inst::Sin | Inst::Cos | Inst::Sqrt | Inst::Abs | Inst::Floor => {
+                    let a = stack_pop!(self.stack);
+                    #[allow(irrefutable_let_patterns)]
+                    let a = float_data!(a);
+                    self.stack.push(Data::Float(match inst {
+                        Inst::Sin => a.to_radians().sin(),
+                        Inst::Cos => a.to_radians().cos(),
+                        Inst::Sqrt => a.sqrt(),
+                        Inst::Abs => a.abs(),
+                        Inst::Floor => a.floor(),
+                        _ => unreachable!(),
+                    }));
+
+                    Ok(SuspendingReason::Running)
+                }
+                Inst::Atan2 | Inst::Min | Inst::Max => {
+                    let b = stack_pop!(self.stack);
+                    let a = stack_pop!(self.stack);
+                    #[allow(irrefutable_let_patterns)]
+                    let a = float_data!(a);
+                    #[allow(irrefutable_let_patterns)]
+                    let b = float_data!(b);
+                    self.stack.push(Data::Float(match inst {
+                        Inst::Atan2 => a.atan2(b).to_degrees(),
+                        Inst::Min => a.min(b),
+                        Inst::Max => a.max(b),
+                        _ => unreachable!(),
+                    }));
+
+                    Ok(SuspendingReason::Running)
+                }
                 Inst::EqInt => {
                     let b = stack_pop!(self.stack);
                     let a = stack_pop!(self.stack);
@@ -257,13 +354,29 @@ impl VM {
 
                     Ok(SuspendingReason::Running)
                 }
+                Inst::Store => {
+                    let n = stack_pop!(self.stack);
+                    #[allow(irrefutable_let_patterns)]
+                    let n = float_data!(n) as usize;
+                    let data = stack_pop!(self.stack);
+
+                    if self.stack.len() <= n {
+                        return Err(RuntimeError::StackUnderflow);
+                    }
+
+                    let idx = self.stack.len() - 1 - n;
+                    self.stack[idx] = data;
+
+                    Ok(SuspendingReason::Running)
+                }
+                // jump offsets are relative to the jump instruction and may be negative for loops
                 Inst::Jump(offset) => {
-                    let next_pc = offset - 1;
+                    let next_pc = pc as i32 + offset;
                     if next_pc < 0 || self.code.len() as i32 <= next_pc {
                         return Err(RuntimeError::OutOfCode(next_pc, self.code.to_vec()));
                     }
 
-                    self.pc += next_pc as usize;
+                    self.pc = next_pc as usize;
                     Ok(SuspendingReason::Running)
                 }
                 Inst::JumpIfFalse(offset) => {
@@ -272,12 +385,12 @@ impl VM {
                     let b = bool_data!(b);
 
                     if !b {
-                        let next_pc = offset - 1;
+                        let next_pc = pc as i32 + offset;
                         if next_pc < 0 || self.code.len() as i32 <= next_pc {
                             return Err(RuntimeError::OutOfCode(next_pc, self.code.to_vec()));
                         }
 
-                        self.pc += next_pc as usize;
+                        self.pc = next_pc as usize;
                     }
                     Ok(SuspendingReason::Running)
                 }
@@ -296,11 +409,14 @@ impl VM {
                     self.pc = offset as usize;
                     Ok(SuspendingReason::Running)
                 }
+                // drops args and locals under the return value
                 Inst::Ret(num) => {
                     if let Some(ret) = self.rstack.pop() {
+                        let retval = stack_pop!(self.stack);
                         for _ in 0..*num {
                             let _ = self.stack.pop();
                         }
+                        self.stack.push(retval);
                         self.pc = ret;
 
                         Ok(SuspendingReason::Running)
@@ -316,3 +432,244 @@ impl VM {
         }
     }
 }
+
+#[cfg(test)]
+mod interpret_test {
+    use std::rc::Rc;
+
+    use super::*;
+
+    fn vm(code: Vec<Inst>) -> VM {
+        let mut vm = VM::new();
+        vm.set_code(Rc::new(code));
+        vm
+    }
+
+    // Runs `code` to the end and returns the value on the stack top.
+    fn eval(code: Vec<Inst>) -> Data {
+        let mut vm = vm(code);
+        let mut op_queue = VecDeque::new();
+        assert!(matches!(
+            vm.start(0, &mut op_queue),
+            Ok(SuspendingReason::Terminated)
+        ));
+        vm.stack.pop().unwrap()
+    }
+
+    fn eval_float(code: Vec<Inst>) -> f32 {
+        match eval(code) {
+            Data::Float(f) => f,
+            d => panic!("{:?} is not a float", d),
+        }
+    }
+
+    // Runs `source` for `frames` frames, answering `self.frames` with the frame from 0,
+    // and returns the frames where `die()` was called.
+    fn frames_of_die(source: &str, frames: u32) -> Vec<u32> {
+        let result = lang_compiler::compile(source.to_string(), &Vec::new()).unwrap();
+        let mut vm = vm(result.code);
+        vm.set_memory(result.memory);
+        let mut op_queue = VecDeque::new();
+        let mut died = Vec::new();
+
+        for frame in 0..frames {
+            let mut reason = vm.start(0, &mut op_queue);
+            loop {
+                match reason {
+                    Ok(SuspendingReason::Terminated) | Ok(SuspendingReason::Yielded) => break,
+                    Ok(SuspendingReason::ToReadState(_, StateId::Frames)) => {
+                        vm.push_data(Data::Float(frame as f32))
+                    }
+                    Ok(SuspendingReason::ToReadState(_, sid)) => panic!("reading {:?}", sid),
+                    Ok(_) => (),
+                    Err(err) => panic!("{:?}", err),
+                }
+                reason = vm.resume(0, &mut op_queue);
+            }
+
+            if op_queue.drain(..).any(|op| op == OperationQuery::Die(0)) {
+                died.push(frame);
+            }
+        }
+
+        died
+    }
+
+    #[test]
+    fn test_timeline_over_frames() {
+        // the call reaching `wait(2)` is the first of the 2 frames it waits
+        let source = r##"
+            timeline t() [
+              repeat(3) => nil
+              wait(2) => nil
+              true => die()
+            ]
+            proc main() { t() }
+        "##;
+        assert_eq!(frames_of_die(source, 20), [2, 4, 6]);
+
+        // `frames_elapsed(3)` passes 3 frames after the frame reaching it
+        let source = r##"
+            timeline t() [
+              repeat(2) => nil
+              frames_elapsed(3) => die()
+            ]
+            proc main() { t() }
+        "##;
+        assert_eq!(frames_of_die(source, 20), [3, 6]);
+
+        // steps without waits run in a call, and the action of `repeat` finishes it
+        let source = r##"
+            timeline t() [
+              true => die()
+              repeat(1) => die()
+              wait(0) => nil
+            ]
+            proc main() { t() }
+        "##;
+        assert_eq!(frames_of_die(source, 5), [0]);
+    }
+
+    #[test]
+    fn test_yield_keeps_stacks() {
+        // a local 42 is on the stack while waiting 2 frames
+        let mut vm = vm(vec![
+            Inst::Float(42.0),
+            Inst::Float(2.0),
+            Inst::Yield,
+            Inst::Drop,
+            Inst::RefWrite(BulletId::Itself, StateId::PosX),
+            Inst::Term,
+        ]);
+        let mut op_queue = VecDeque::new();
+
+        for _ in 0..2 {
+            assert!(matches!(
+                vm.start(0, &mut op_queue),
+                Ok(SuspendingReason::Yielded)
+            ));
+            assert!(vm.suspended);
+            assert_eq!(vm.stack[0], Data::Float(42.0));
+        }
+        assert!(matches!(
+            vm.start(0, &mut op_queue),
+            Ok(SuspendingReason::ToWriteState(
+                BulletId::Itself,
+                StateId::PosX,
+                Data::Float(f)
+            )) if f == 42.0
+        ));
+        assert!(matches!(
+            vm.resume(0, &mut op_queue),
+            Ok(SuspendingReason::Terminated)
+        ));
+        assert!(!vm.suspended);
+
+        // not suspended so it runs from the beginning
+        assert!(matches!(
+            vm.start(0, &mut op_queue),
+            Ok(SuspendingReason::Yielded)
+        ));
+        assert_eq!(vm.pc, 2);
+    }
+
+    #[test]
+    fn test_wait_frames() {
+        // `wait(0)` does not suspend, and the script runs again from the beginning at the
+        // next frame after it ends
+        let source = "proc main() {\n  wait(0)\n  die()\n}";
+        assert_eq!(frames_of_die(source, 3), [0, 1, 2]);
+        let source = "proc main() {\n  wait(1)\n  die()\n}";
+        assert_eq!(frames_of_die(source, 6), [1, 3, 5]);
+        let source = "proc main() {\n  wait(2)\n  die()\n}";
+        assert_eq!(frames_of_die(source, 6), [2, 5]);
+
+        // locals and the return stack are kept while waiting in a proc
+        let source = r##"
+            proc f(a: float) -> float {
+              let b = a + 1
+              wait(1)
+              return b
+            }
+            proc main() {
+              let a = 2
+              if f(a) == 3 && a == 2 {
+                die()
+              }
+            }
+        "##;
+        assert_eq!(frames_of_die(source, 4), [1, 3]);
+    }
+
+    fn assert_near(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-5, "{} is not near {}", a, b);
+    }
+
+    #[test]
+    fn test_trigonometric_functions_in_degrees() {
+        assert_near(
+            eval_float(vec![Inst::Float(90.0), Inst::Sin, Inst::Term]),
+            1.0,
+        );
+        assert_near(
+            eval_float(vec![Inst::Float(30.0), Inst::Sin, Inst::Term]),
+            0.5,
+        );
+        assert_near(
+            eval_float(vec![Inst::Float(180.0), Inst::Cos, Inst::Term]),
+            -1.0,
+        );
+        assert_near(
+            eval_float(vec![Inst::Float(60.0), Inst::Cos, Inst::Term]),
+            0.5,
+        );
+
+        // `atan2(y, x)` takes x on the top and y under it
+        let atan2 = |y, x| {
+            eval_float(vec![
+                Inst::Float(y),
+                Inst::Float(x),
+                Inst::Atan2,
+                Inst::Term,
+            ])
+        };
+        assert_near(atan2(1.0, 1.0), 45.0);
+        assert_near(atan2(1.0, 0.0), 90.0);
+        assert_near(atan2(0.0, -1.0), 180.0);
+        assert_near(atan2(-1.0, 0.0), -90.0);
+    }
+
+    #[test]
+    fn test_math_functions() {
+        assert_eq!(
+            eval_float(vec![Inst::Float(9.0), Inst::Sqrt, Inst::Term]),
+            3.0
+        );
+        assert_eq!(
+            eval_float(vec![Inst::Float(-2.5), Inst::Abs, Inst::Term]),
+            2.5
+        );
+        assert_eq!(
+            eval_float(vec![Inst::Float(-2.5), Inst::Floor, Inst::Term]),
+            -3.0
+        );
+        assert_eq!(
+            eval_float(vec![
+                Inst::Float(1.0),
+                Inst::Float(2.0),
+                Inst::Min,
+                Inst::Term
+            ]),
+            1.0
+        );
+        assert_eq!(
+            eval_float(vec![
+                Inst::Float(1.0),
+                Inst::Float(2.0),
+                Inst::Max,
+                Inst::Term
+            ]),
+            2.0
+        );
+    }
+}
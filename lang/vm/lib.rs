@@ -16,6 +16,8 @@ pub struct VM {
     pub stack: Vec<Data>,
     pub rstack: Vec<usize>,
     pub memory: Vec<u8>,
+    // suspended by `wait()` and to be resumed at the next `start()`
+    pub suspended: bool,
 }
 
 impl VM {
@@ -26,6 +28,7 @@ impl VM {
             stack: Vec::new(),
             rstack: Vec::new(),
             memory: Vec::from([0; 128]),
+            suspended: false,
         }
     }
 
@@ -1,7 +1,14 @@
+pub const SCRIPTS_DIR: &str = "scripts";
+pub const RANDOM_SEED: u64 = 20230101;
+
+#[cfg(feature = "graphics")]
 pub const WIDTH: f32 = 1280.0;
+#[cfg(feature = "graphics")]
 pub const HEIGHT: f32 = 960.0;
 
+#[cfg(feature = "graphics")]
 pub const SHOOTER_OFFSET_X: f32 = 200.0;
+#[cfg(feature = "graphics")]
 pub const SHOOTER_OFFSET_Y: f32 = 60.0;
 pub const SHOOTER_WIDTH: f32 = 600.0;
 pub const SHOOTER_HEIGHT: f32 = 840.0;
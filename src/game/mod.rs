@@ -1,25 +1,41 @@
 mod shooter;
 
+#[cfg(feature = "graphics")]
+use std::path::PathBuf;
+
+#[cfg(feature = "graphics")]
 use ggez::{event::EventHandler, input::keyboard::KeyInput, Context, GameResult};
 
+#[cfg(feature = "graphics")]
 use shooter::ShooterScene;
 
+pub use shooter::{play_replay, run_headless, BulletCodes, InputScript, Replay};
+
+#[cfg(feature = "graphics")]
 pub trait Scene: EventHandler {
     fn next(&self) -> Box<dyn Scene>;
 }
 
+#[cfg(feature = "graphics")]
 pub struct BulletsGame {
     scene: Box<dyn Scene>,
 }
 
+#[cfg(feature = "graphics")]
 impl BulletsGame {
-    pub fn new(_ctx: &mut Context) -> Self {
+    pub fn new(
+        _ctx: &mut Context,
+        bullet_codes: BulletCodes,
+        record: Option<PathBuf>,
+        practice: bool,
+    ) -> Self {
         BulletsGame {
-            scene: Box::new(ShooterScene::new()),
+            scene: Box::new(ShooterScene::new(bullet_codes, record, practice)),
         }
     }
 }
 
+#[cfg(feature = "graphics")]
 impl EventHandler for BulletsGame {
     fn update(&mut self, ctx: &mut Context) -> GameResult<()> {
         self.scene.update(ctx)
@@ -36,4 +52,8 @@ impl EventHandler for BulletsGame {
     fn key_up_event(&mut self, ctx: &mut Context, key: KeyInput) -> GameResult<()> {
         self.scene.key_up_event(ctx, key)
     }
+
+    fn quit_event(&mut self, ctx: &mut Context) -> GameResult<bool> {
+        self.scene.quit_event(ctx)
+    }
 }
@@ -1,5 +1,6 @@
 use std::{collections::VecDeque, rc::Rc};
 
+#[cfg(feature = "graphics")]
 use ggez::{
     graphics::{
         self,
@@ -11,7 +12,7 @@ use ggez::{
         MeshBuilder,
     },
     mint::Point2,
-    Context, GameError, GameResult,
+    Context, GameResult,
 };
 use glam;
 
@@ -22,7 +23,15 @@ use lang_component::{
 };
 use lang_vm::{SuspendingReason, VM};
 
-use super::{shooter::Objects, SceneDrawable};
+#[cfg(feature = "graphics")]
+use super::{collision::hit_radius, scene::SceneDrawable};
+use super::{
+    refs::{Link, Refs},
+    rng::Rng,
+    shooter::{Counters, ShooterError},
+    snapshot::{Reader, SnapshotError, Writer},
+};
+#[cfg(feature = "graphics")]
 use crate::constant;
 
 pub struct Appearance {
@@ -44,6 +53,7 @@ pub struct InputState {
     pub right: bool,
     pub shot: bool,
     pub slow: bool,
+    pub bomb: bool,
 }
 
 impl Default for InputState {
@@ -55,20 +65,74 @@ impl Default for InputState {
             right: false,
             shot: false,
             slow: false,
+            bomb: false,
         }
     }
 }
 
+// Velocity of bullets, which moves them after their scripts run.
+// `vx` and `vy` are added to the move by `speed` toward `angle` in degrees. The angle 0 is
+// toward +x and angles turn clockwise on screen, since y grows downward: 90 is toward +y.
+#[derive(Debug, Default)]
+pub struct Motion {
+    pub vx: f32,
+    pub vy: f32,
+    pub speed: f32,
+    pub angle: f32,
+    pub accel: f32,
+    pub angular_velocity: f32,
+}
+
+impl Motion {
+    // Moves `pos` with the current speed and angle, then changes them by `accel` and
+    // `angular_velocity` for the next frame.
+    pub fn apply(&mut self, pos: &mut glam::Vec2) {
+        let (sin, cos) = self.angle.to_radians().sin_cos();
+        pos.x += self.vx + self.speed * cos;
+        pos.y += self.vy + self.speed * sin;
+
+        self.speed += self.accel;
+        self.angle += self.angular_velocity;
+    }
+
+    // Moves toward `target` from `pos` straight at `speed`.
+    pub fn home(&mut self, pos: glam::Vec2, target: glam::Vec2, speed: f32) {
+        let d = target - pos;
+        *self = Self {
+            speed: speed.min(d.length()),
+            angle: d.y.atan2(d.x).to_degrees(),
+            ..Self::default()
+        };
+    }
+}
+
 pub struct Bullet {
     pub enabled: bool,
     pub visible: bool,
+    // frames since fired
+    pub age: u32,
+    // set by collision checks every frame
+    pub hit: bool,
+    // a bullet is grazed only once
+    pub grazed: bool,
+    // hit points of enemies
+    pub hp: f32,
+    // incremented every time the slot is loaded, to find dead links to the slot
+    pub generation: u32,
+    pub parent: Option<Link>,
+    // the last one fired or spawned
+    pub child: Option<Link>,
     pub input: InputState,
     pub pos: glam::Vec2,
+    pub motion: Motion,
     pub appearance: Appearance,
     pub vm: VM,
 }
 
 impl Bullet {
+    // items fly toward the player at this speed
+    const ITEM_SPEED: f32 = 8.0;
+
     pub fn new(x: f32, y: f32, atype: BulletType, acolor: BulletColor, bc: Rc<BulletCode>) -> Self {
         let mut vm = VM::new();
         vm.set_code(bc.code.clone());
@@ -77,46 +141,178 @@ impl Bullet {
         Self {
             enabled: false,
             visible: false,
+            age: 0,
+            hit: false,
+            grazed: false,
+            hp: 0.0,
+            generation: 0,
+            parent: None,
+            child: None,
             input: InputState::default(),
             pos: glam::vec2(x, y),
+            motion: Motion::default(),
             appearance: Appearance::new(atype, acolor),
             vm,
         }
     }
 
+    // Resets the state and loads `bullet_code` to run from the start with `params`.
+    pub fn load(&mut self, x: f32, y: f32, bullet_code: &BulletCode, params: Vec<Data>) {
+        self.enabled = true;
+        self.visible = true;
+        self.age = 0;
+        self.hit = false;
+        self.grazed = false;
+        self.hp = 0.0;
+        self.generation = self.generation.wrapping_add(1);
+        self.parent = None;
+        self.child = None;
+        self.motion = Motion::default();
+        self.pos.x = x;
+        self.pos.y = y;
+
+        let vm = &mut self.vm;
+        vm.code = bullet_code.code.clone();
+        vm.memory
+            .as_mut_slice()
+            .copy_from_slice(bullet_code.initial_memory.as_slice());
+        vm.stack.clear();
+        vm.rstack.clear();
+        vm.suspended = false;
+
+        // params are placed at the head of memory in order of the signature
+        let mut offset = 0;
+        for (arg, data) in bullet_code.signature.args.iter().zip(params) {
+            vm.write_memory(offset, data)
+                .expect("params are type-checked by the compiler");
+            offset += arg.r#type.size();
+        }
+    }
+
+    #[cfg_attr(not(feature = "graphics"), allow(dead_code))]
+    pub fn save(&self, w: &mut Writer) {
+        w.bool(self.enabled);
+        w.bool(self.visible);
+        w.u32(self.age);
+        w.bool(self.hit);
+        w.bool(self.grazed);
+        w.f32(self.hp);
+        w.u32(self.generation);
+        w.link(self.parent);
+        w.link(self.child);
+        let input = &self.input;
+        for b in [
+            input.up,
+            input.down,
+            input.left,
+            input.right,
+            input.shot,
+            input.slow,
+            input.bomb,
+        ] {
+            w.bool(b);
+        }
+        w.f32(self.pos.x);
+        w.f32(self.pos.y);
+        let motion = &self.motion;
+        for f in [
+            motion.vx,
+            motion.vy,
+            motion.speed,
+            motion.angle,
+            motion.accel,
+            motion.angular_velocity,
+        ] {
+            w.f32(f);
+        }
+        w.bullet_type(self.appearance.r#type);
+        w.bullet_color(self.appearance.color);
+        w.vm(&self.vm);
+    }
+
+    #[cfg_attr(not(feature = "graphics"), allow(dead_code))]
+    pub fn restore(&mut self, r: &mut Reader) -> Result<(), SnapshotError> {
+        self.enabled = r.bool()?;
+        self.visible = r.bool()?;
+        self.age = r.u32()?;
+        self.hit = r.bool()?;
+        self.grazed = r.bool()?;
+        self.hp = r.f32()?;
+        self.generation = r.u32()?;
+        self.parent = r.link()?;
+        self.child = r.link()?;
+        self.input = InputState {
+            up: r.bool()?,
+            down: r.bool()?,
+            left: r.bool()?,
+            right: r.bool()?,
+            shot: r.bool()?,
+            slow: r.bool()?,
+            bomb: r.bool()?,
+        };
+        self.pos = glam::vec2(r.f32()?, r.f32()?);
+        self.motion = Motion {
+            vx: r.f32()?,
+            vy: r.f32()?,
+            speed: r.f32()?,
+            angle: r.f32()?,
+            accel: r.f32()?,
+            angular_velocity: r.f32()?,
+        };
+        self.appearance = Appearance::new(r.bullet_type()?, r.bullet_color()?);
+        r.vm(&mut self.vm)
+    }
+
+    // Runs the script for a frame and moves the bullet. `idx` is the index in the pool,
+    // which operations queued by the script carry, and `id` is how `refs` address it.
     pub fn update(
         &mut self,
+        idx: usize,
         id: BulletId,
-        objects: &Objects,
+        refs: &Refs,
         op_queue: &mut VecDeque<OperationQuery>,
-    ) -> GameResult<()> {
-        let mut reason = self.vm.start(0, op_queue);
+        rng: &mut Rng,
+        counters: &Counters,
+    ) -> Result<(), ShooterError> {
+        let mut reason = self.vm.start(idx, op_queue);
 
         loop {
             match reason {
                 Ok(SuspendingReason::Terminated) => break,
+                // the script continues from there at the next frame
+                Ok(SuspendingReason::Yielded) => break,
                 Ok(SuspendingReason::Running) => unreachable!(),
-                Ok(SuspendingReason::ToReadState(bid, sid)) => match bid {
-                    BulletId::Itself => self.vm.push_data(self.read(&bid, &sid)),
-                    BulletId::Player => {
-                        let d = if id == BulletId::Player {
-                            self.read(&bid, &sid)
-                        } else {
-                            let p = objects.player.borrow();
-                            p.read(&bid, &sid)
-                        };
-                        self.vm.push_data(d);
-                    }
-                    _ => todo!(),
-                },
+                Ok(SuspendingReason::ToReadState(
+                    bid,
+                    sid @ (StateId::Frames
+                    | StateId::Score
+                    | StateId::Graze
+                    | StateId::Lives
+                    | StateId::Bombs),
+                )) => {
+                    self.vm.push_data(counters.read(&bid, &sid));
+                }
+                Ok(SuspendingReason::ToReadState(bid, sid)) => {
+                    let d = refs.read(self, id, bid, &sid);
+                    self.vm.push_data(d);
+                }
                 Ok(SuspendingReason::ToWriteState(bid, sid, d)) => {
-                    self.write(&bid, &sid, d);
+                    refs.write(self, id, bid, &sid, d);
                 }
-                Err(err) => return Err(GameError::CustomError(format!("error = {:?}", err))),
+                Ok(SuspendingReason::ToGetRandom) => {
+                    self.vm.push_data(Data::Float(rng.next_f32()));
+                }
+                Err(err) => return Err(ShooterError::Script(err)),
             }
 
-            reason = self.vm.resume(0, op_queue);
+            reason = self.vm.resume(idx, op_queue);
+        }
+        if self.appearance.r#type == BulletType::Item {
+            let target = refs.player.borrow().pos;
+            self.motion.home(self.pos, target, Self::ITEM_SPEED);
         }
+        self.motion.apply(&mut self.pos);
+        self.age += 1;
 
         Ok(())
     }
@@ -133,7 +329,20 @@ impl StateIO for Bullet {
             StateId::InputRight => Data::Bool(self.input.right),
             StateId::InputShot => Data::Bool(self.input.shot),
             StateId::InputSlow => Data::Bool(self.input.slow),
+            StateId::InputBomb => Data::Bool(self.input.bomb),
             StateId::Enabled => Data::Bool(self.enabled),
+            StateId::Frames | StateId::Score | StateId::Graze | StateId::Lives | StateId::Bombs => {
+                unreachable!("{:?} is counted by the shooter", sid)
+            }
+            StateId::Age => Data::Float(self.age as f32),
+            StateId::VelX => Data::Float(self.motion.vx),
+            StateId::VelY => Data::Float(self.motion.vy),
+            StateId::Speed => Data::Float(self.motion.speed),
+            StateId::Angle => Data::Float(self.motion.angle),
+            StateId::Accel => Data::Float(self.motion.accel),
+            StateId::AngularVelocity => Data::Float(self.motion.angular_velocity),
+            StateId::Hit => Data::Bool(self.hit),
+            StateId::Hp => Data::Float(self.hp),
         }
     }
 
@@ -179,15 +388,61 @@ impl StateIO for Bullet {
                     self.input.slow = b
                 }
             }
-            StateId::Enabled => {
+            StateId::InputBomb => {
                 if let Data::Bool(b) = d {
-                    self.enabled = b
+                    self.input.bomb = b
+                }
+            }
+            StateId::VelX => {
+                if let Data::Float(f) = d {
+                    self.motion.vx = f
+                }
+            }
+            StateId::Hp => {
+                if let Data::Float(f) = d {
+                    self.hp = f
+                }
+            }
+            StateId::VelY => {
+                if let Data::Float(f) = d {
+                    self.motion.vy = f
+                }
+            }
+            StateId::Speed => {
+                if let Data::Float(f) = d {
+                    self.motion.speed = f
+                }
+            }
+            StateId::Angle => {
+                if let Data::Float(f) = d {
+                    self.motion.angle = f
+                }
+            }
+            StateId::Accel => {
+                if let Data::Float(f) = d {
+                    self.motion.accel = f
                 }
             }
+            StateId::AngularVelocity => {
+                if let Data::Float(f) = d {
+                    self.motion.angular_velocity = f
+                }
+            }
+            // counters and results of collision checks are read-only, and so is `enabled`
+            // because only `die()` returns slots to the pools
+            StateId::Enabled
+            | StateId::Frames
+            | StateId::Age
+            | StateId::Hit
+            | StateId::Score
+            | StateId::Graze
+            | StateId::Lives
+            | StateId::Bombs => (),
         }
     }
 }
 
+#[cfg(feature = "graphics")]
 impl SceneDrawable for Bullet {
     fn draw(&self, ctx: &mut Context, canvas: &mut graphics::Canvas) -> GameResult<()> {
         let color = match self.appearance.color {
@@ -210,13 +465,40 @@ impl SceneDrawable for Bullet {
                 canvas.draw(&mesh, param);
 
                 let mut mb = MeshBuilder::new();
+                let radius = hit_radius(BulletType::Player);
                 let hit_area = mb
-                    .circle(DrawMode::stroke(1.0), [0.0, 0.0], 5.0, 1.0, color)?
+                    .circle(DrawMode::stroke(1.0), [0.0, 0.0], radius, 1.0, color)?
                     //.circle(DrawMode::stroke(1.0), glam::vec2(0.0, 0.0), 3.0, 1.0, color)?
                     .build();
                 let hit_area = Mesh::from_data(ctx, hit_area);
                 canvas.draw(&hit_area, param);
             }
+            BulletType::PlayerShot => {
+                let radius = hit_radius(BulletType::PlayerShot);
+                let mut mb = MeshBuilder::new();
+                let shot = mb
+                    .circle(DrawMode::fill(), pos, radius, 1.0, color)?
+                    .build();
+                let shot = Mesh::from_data(ctx, shot);
+                canvas.draw(&shot, param);
+            }
+            BulletType::Item => {
+                let size = hit_radius(BulletType::Item);
+                let rect = graphics::Rect::new(pos.x - size / 2.0, pos.y - size / 2.0, size, size);
+                let mut mb = MeshBuilder::new();
+                let item = mb.rectangle(DrawMode::stroke(1.0), rect, color)?.build();
+                let item = Mesh::from_data(ctx, item);
+                canvas.draw(&item, param);
+            }
+            BulletType::Enemy1 => {
+                let radius = hit_radius(BulletType::Enemy1);
+                let mut mb = MeshBuilder::new();
+                let enemy = mb
+                    .circle(DrawMode::stroke(2.0), pos, radius, 1.0, color)?
+                    .build();
+                let enemy = Mesh::from_data(ctx, enemy);
+                canvas.draw(&enemy, param);
+            }
             BulletType::Bullet1 => {
                 //                let dest = glam::vec2(-5.0, -5.0) + pos;
                 //                let param = param.dest::<Point2<f32>>(dest.into());
@@ -232,3 +514,69 @@ impl SceneDrawable for Bullet {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod motion_test {
+    use super::*;
+
+    fn assert_near(a: glam::Vec2, b: glam::Vec2) {
+        assert!((a - b).length() < 1e-4, "{:?} is not near {:?}", a, b);
+    }
+
+    #[test]
+    fn test_angle_convention() {
+        let mut pos = glam::vec2(0.0, 0.0);
+        for (angle, to) in [
+            (0.0, glam::vec2(1.0, 0.0)),
+            (90.0, glam::vec2(1.0, 1.0)),
+            (180.0, glam::vec2(0.0, 1.0)),
+            (270.0, glam::vec2(0.0, 0.0)),
+        ] {
+            let mut motion = Motion {
+                speed: 1.0,
+                angle,
+                ..Motion::default()
+            };
+            motion.apply(&mut pos);
+            assert_near(pos, to);
+        }
+    }
+
+    #[test]
+    fn test_apply_moves_before_changing_speed_and_angle() {
+        let mut pos = glam::vec2(0.0, 0.0);
+        let mut motion = Motion {
+            vx: 0.5,
+            speed: 1.0,
+            accel: 1.0,
+            angular_velocity: 90.0,
+            ..Motion::default()
+        };
+
+        // the first move is with the initial speed and angle
+        motion.apply(&mut pos);
+        assert_near(pos, glam::vec2(1.5, 0.0));
+        assert_eq!((motion.speed, motion.angle), (2.0, 90.0));
+
+        motion.apply(&mut pos);
+        assert_near(pos, glam::vec2(2.0, 2.0));
+    }
+
+    #[test]
+    fn test_home() {
+        let mut motion = Motion {
+            vx: 3.0,
+            accel: 1.0,
+            ..Motion::default()
+        };
+        motion.home(glam::vec2(0.0, 0.0), glam::vec2(0.0, 100.0), 8.0);
+        assert_eq!(motion.angle, 90.0);
+        assert_eq!((motion.speed, motion.vx, motion.accel), (8.0, 0.0, 0.0));
+
+        // does not overshoot the target
+        let mut pos = glam::vec2(0.0, 0.0);
+        motion.home(pos, glam::vec2(-3.0, -4.0), 8.0);
+        motion.apply(&mut pos);
+        assert_near(pos, glam::vec2(-3.0, -4.0));
+    }
+}
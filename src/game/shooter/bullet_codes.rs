@@ -1,92 +1,280 @@
-use std::{collections::HashMap, rc::Rc};
+use std::{
+    collections::HashMap,
+    ffi::OsStr,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
 
-use lang_compiler::{compile, BulletCode};
+use lang_compiler::{
+    compile, BulletCode, CodegenError, CodegenErrorKind, CompileError, Diagnostic,
+};
 
-const CODE_MAP: [(&str, &str); 2] = [
-    (
-        "bullet1",
-        r##"
+use super::hash::Fnv1a;
 
-        proc die_out_of_screen() {
-          if self.x < -10 || 610 < self.x || self.y < -10 || 850 < self.y {
-            die()
-          } else {
-            false
-          }
+const SCRIPT_EXTENSION: &str = "bl";
+
+#[derive(Debug)]
+pub enum LoadError {
+    CannotReadDir(PathBuf, io::Error),
+    CannotReadFile(PathBuf, io::Error),
+    // holds the script source to show the error with it
+    CompilationFailed(PathBuf, String, Box<CompileError>),
+    DuplicatedBulletName(PathBuf, String),
+    NoPlayerScript(PathBuf),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::CannotReadDir(path, err) => {
+                write!(
+                    f,
+                    "cannot read scripts directory '{}': {}",
+                    path.display(),
+                    err
+                )
+            }
+            LoadError::CannotReadFile(path, err) => {
+                write!(f, "cannot read script '{}': {}", path.display(), err)
+            }
+            LoadError::CompilationFailed(path, source, err) => {
+                let name = path.display().to_string();
+                write!(f, "{}", Diagnostic::new(&name, source, err))
+            }
+            LoadError::DuplicatedBulletName(path, name) => {
+                write!(
+                    f,
+                    "bullet '{}' in '{}' is already defined",
+                    name,
+                    path.display()
+                )
+            }
+            LoadError::NoPlayerScript(dir) => {
+                write!(
+                    f,
+                    "'player.{}' is not found in '{}'",
+                    SCRIPT_EXTENSION,
+                    dir.display()
+                )
+            }
         }
+    }
+}
 
-        global vx = -1
-        global vy = -1
+struct Script {
+    name: String,
+    path: PathBuf,
+    source: String,
+}
 
-        proc main() {
-          die_out_of_screen()
+fn find_scripts(dir: &Path) -> Result<Vec<Script>, LoadError> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) => return Err(LoadError::CannotReadDir(dir.to_path_buf(), err)),
+    };
 
-          vx = if vx == -1 { (player.x - self.x) / 10 } else { vx }
-          vy = if vy == -1 { (player.y - self.y) / 10 } else { vy }
-          self.x = self.x + vx
-          self.y = self.y + vy
-        }
-        "##,
-    ),
-    (
-        "player",
-        r##"
-        global slow_v = 4.0
-        global fast_v = 7.0
-
-        proc velocity() -> float {
-          return if player.input_slow { slow_v } else { fast_v }
+    let mut scripts = Vec::new();
+    for entry in entries {
+        let path = match entry {
+            Ok(entry) => entry.path(),
+            Err(err) => return Err(LoadError::CannotReadDir(dir.to_path_buf(), err)),
+        };
+
+        if !path.is_file() || path.extension() != Some(OsStr::new(SCRIPT_EXTENSION)) {
+            continue;
         }
 
-        proc main() {
-          self.x = self.x - if player.input_left { velocity() } else { 0.0 }
-          self.x = self.x + if player.input_right { velocity() } else { 0.0 }
-          self.y = self.y - if player.input_up { velocity() } else { 0.0 }
-          self.y = self.y + if player.input_down { velocity() } else { 0.0 }
+        let name = match path.file_stem().and_then(|stem| stem.to_str()) {
+            Some(name) => name.to_string(),
+            None => continue,
+        };
+        let source = match fs::read_to_string(&path) {
+            Ok(source) => source,
+            Err(err) => return Err(LoadError::CannotReadFile(path, err)),
+        };
 
-          fire("bullet1", 200, 100)
-        }
-        "##,
-        //              if self.input_shot { fire(self.x, self.y) } else { false }
-    ),
-];
+        scripts.push(Script { name, path, source });
+    }
+    // read_dir() does not define any order so sort them to get stable bullet code ids
+    scripts.sort_by(|a, b| a.name.cmp(&b.name));
 
+    Ok(scripts)
+}
+
+#[derive(Clone)]
 pub struct BulletCodes {
     pub by_name: HashMap<String, Rc<BulletCode>>,
     pub by_id: Vec<Rc<BulletCode>>,
+    // hash of names and sources of the scripts, to check replays are of the same scripts
+    pub hash: u64,
 }
 
 impl BulletCodes {
-    pub fn compile_codes() -> Self {
+    // Compiles all `.bl` files in `dir`. Each bullet code is named after its file name,
+    // and bullets defined with `bullet` in the scripts are named as they are defined.
+    //
+    // A script can `fire()` bullets defined in other scripts only if they are already
+    // compiled, so scripts referring to unknown bullets are retried after the others.
+    pub fn load(dir: &Path) -> Result<Self, LoadError> {
         let mut map = HashMap::new();
         let mut vec: Vec<Rc<BulletCode>> = Vec::new();
-        let mut id = 0;
-
-        for (name, codestr) in CODE_MAP.iter() {
-            let result = match compile(codestr.to_string(), &vec) {
-                Ok(result) => result,
-                Err(err) => panic!("compilation '{}' fails: {:?}", name, err),
-            };
-
-            let bc = BulletCode {
-                id: id,
-                name: name.to_string(),
-                code: Rc::new(result.code),
-                initial_memory: result.memory,
-                signature: result.signature,
-            };
-            let bc = Rc::new(bc);
-
-            eprintln!("[Bullet: {}] VM code = {:?}", bc.name, bc.code);
-
-            id += 1;
-            map.insert(name.to_string(), bc.clone());
-            vec.push(bc.clone());
+        let mut pending = find_scripts(dir)?;
+
+        let mut hasher = Fnv1a::new();
+        for script in pending.iter() {
+            hasher.write(script.name.as_bytes());
+            hasher.write(&[0]);
+            hasher.write(script.source.as_bytes());
+            hasher.write(&[0]);
         }
 
-        Self {
+        while !pending.is_empty() {
+            let pending_num = pending.len();
+            let mut deferred = Vec::new();
+
+            for script in pending.into_iter() {
+                let result = match compile(script.source.clone(), &vec) {
+                    Ok(result) => result,
+                    Err(
+                        err @ CompileError::CodegenError(CodegenError {
+                            kind: CodegenErrorKind::UnknownName(_),
+                            ..
+                        }),
+                    ) => {
+                        deferred.push((script, err));
+                        continue;
+                    }
+                    Err(err) => {
+                        return Err(LoadError::CompilationFailed(
+                            script.path,
+                            script.source,
+                            Box::new(err),
+                        ))
+                    }
+                };
+
+                // bullets in the script have ids just after the compiled ones
+                let mut bullets = result.bullets;
+                bullets.push(BulletCode {
+                    id: vec.len() + bullets.len(),
+                    name: script.name.clone(),
+                    code: Rc::new(result.code),
+                    initial_memory: result.memory,
+                    signature: result.signature,
+                });
+
+                for bc in bullets.into_iter() {
+                    if map.contains_key(&bc.name) {
+                        return Err(LoadError::DuplicatedBulletName(script.path, bc.name));
+                    }
+
+                    let bc = Rc::new(bc);
+                    eprintln!("[Bullet: {}] VM code = {:?}", bc.name, bc.code);
+
+                    map.insert(bc.name.clone(), bc.clone());
+                    vec.push(bc);
+                }
+            }
+
+            // no scripts compiled in this round so the unknown names are really unknown
+            if deferred.len() == pending_num {
+                let (script, err) = deferred.remove(0);
+                return Err(LoadError::CompilationFailed(
+                    script.path,
+                    script.source,
+                    Box::new(err),
+                ));
+            }
+
+            pending = deferred.into_iter().map(|(script, _)| script).collect();
+        }
+
+        if !map.contains_key("player") {
+            return Err(LoadError::NoPlayerScript(dir.to_path_buf()));
+        }
+
+        Ok(Self {
             by_name: map,
             by_id: vec,
+            hash: hasher.finish(),
+        })
+    }
+}
+
+// Writes scripts into a new directory named after `name` and loads them.
+#[cfg(test)]
+fn try_load_scripts(name: &str, scripts: &[(&str, &str)]) -> Result<BulletCodes, LoadError> {
+    let dir = std::env::temp_dir().join(format!("bullets-{}-{}", name, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    for (name, source) in scripts {
+        fs::write(dir.join(format!("{}.{}", name, SCRIPT_EXTENSION)), source).unwrap();
+    }
+
+    let result = BulletCodes::load(&dir);
+    fs::remove_dir_all(&dir).unwrap();
+    result
+}
+
+#[cfg(test)]
+pub fn load_scripts(name: &str, scripts: &[(&str, &str)]) -> BulletCodes {
+    try_load_scripts(name, scripts).unwrap()
+}
+
+#[cfg(test)]
+mod bullet_codes_test {
+    use super::*;
+
+    #[test]
+    fn test_load_deferred_script() {
+        // `a` is compiled after `z`, which it fires, even though it comes first
+        let bullet_codes = load_scripts(
+            "deferred",
+            &[
+                ("a", r#"proc main() { fire("z", 0, 0) }"#),
+                ("player", "proc main() {}"),
+                ("z", "proc main() {}"),
+            ],
+        );
+        let a = &bullet_codes.by_name["a"];
+        let z = &bullet_codes.by_name["z"];
+        assert!(z.id < a.id);
+        for bc in bullet_codes.by_id.iter() {
+            assert_eq!(bullet_codes.by_name[&bc.name].id, bc.id);
         }
+
+        let result = try_load_scripts(
+            "unknown",
+            &[
+                ("a", r#"proc main() { fire("nothing", 0, 0) }"#),
+                ("player", "proc main() {}"),
+            ],
+        );
+        assert!(matches!(
+            result,
+            Err(LoadError::CompilationFailed(path, _, _)) if path.ends_with("a.bl")
+        ));
+    }
+
+    #[test]
+    fn test_load_duplicated_bullet_name() {
+        let result = try_load_scripts(
+            "duplicated",
+            &[
+                ("a", "bullet b() {}\nproc main() {}"),
+                ("b", "proc main() {}"),
+                ("player", "proc main() {}"),
+            ],
+        );
+        assert!(matches!(
+            result,
+            Err(LoadError::DuplicatedBulletName(path, name)) if path.ends_with("b.bl") && name == "b"
+        ));
+    }
+
+    #[test]
+    fn test_load_no_player_script() {
+        let result = try_load_scripts("no-player", &[("a", "proc main() {}")]);
+        assert!(matches!(result, Err(LoadError::NoPlayerScript(_))));
     }
 }
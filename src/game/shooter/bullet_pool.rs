@@ -1,15 +1,24 @@
-use std::{cell::RefCell, collections::VecDeque, ops::DerefMut, rc::Rc};
+use std::{cell::RefCell, collections::VecDeque, rc::Rc};
 
+#[cfg(feature = "graphics")]
 use ggez::{graphics, Context, GameError, GameResult};
 
 use lang_compiler::BulletCode;
 use lang_component::{
-    bullet::{BulletColor, BulletId, BulletType, StateIO},
+    bullet::{BulletColor, BulletId, BulletType},
     vm::{Data, Inst, OperationQuery},
 };
-use lang_vm::SuspendingReason;
 
-use super::{bullet::Bullet, shooter::OperationProcessor, SceneDrawable};
+#[cfg(feature = "graphics")]
+use super::scene::SceneDrawable;
+use super::{
+    bullet::{Bullet, Motion},
+    enemy_pool::EnemySet,
+    refs::Refs,
+    rng::Rng,
+    shooter::{Counters, OperationProcessor, ShooterError},
+    snapshot::{Reader, SnapshotError, Writer},
+};
 
 pub struct BulletSet {
     pub states: Vec<Rc<RefCell<Bullet>>>,
@@ -55,49 +64,110 @@ impl BulletSet {
     pub fn update(
         &mut self,
         player: Rc<RefCell<Bullet>>,
+        enemies: &EnemySet,
         op_queue: &mut VecDeque<OperationQuery>,
-    ) -> GameResult<()> {
+        rng: &mut Rng,
+        counters: &Counters,
+    ) -> Result<(), ShooterError> {
+        let refs = Refs {
+            player: &player,
+            enemies: &enemies.states,
+            bullets: &self.states,
+        };
+
         for idx in 0..Self::BULLET_MAX {
             let mut state = self.states[idx].borrow_mut();
-            let state = state.deref_mut();
 
             if state.enabled {
-                let mut reason = state.vm.start(idx, op_queue);
-
-                loop {
-                    match reason {
-                        Ok(SuspendingReason::Terminated) => break,
-                        Ok(SuspendingReason::Running) => unreachable!(),
-                        Ok(SuspendingReason::ToReadState(bid, sid)) => {
-                            let d = match bid {
-                                BulletId::Player => {
-                                    let player = player.borrow();
-                                    player.read(&bid, &sid)
-                                }
-                                BulletId::Itself => state.read(&bid, &sid),
-                                _ => todo!("bullet {:?} is not implemented yet", bid),
-                            };
-
-                            state.vm.push_data(d);
-                        }
-                        Ok(SuspendingReason::ToWriteState(bid, sid, d)) => match bid {
-                            BulletId::Itself => state.write(&bid, &sid, d),
-                            _ => todo!("bullet {:?} is not implemented yet", bid),
-                        },
-                        Err(err) => {
-                            return Err(GameError::CustomError(format!("error = {:?}", err)))
-                        }
-                    }
-
-                    reason = state.vm.resume(idx, op_queue);
-                }
+                state.update(idx, BulletId::Bullet(idx), &refs, op_queue, rng, counters)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Disabled bullets are saved only with their generations, which links need.
+    #[cfg_attr(not(feature = "graphics"), allow(dead_code))]
+    pub fn save(&self, w: &mut Writer) {
+        for (state, next) in self.states.iter().zip(self.nexts.iter()) {
+            let state = state.borrow();
+            w.u32(state.generation);
+            w.bool(state.enabled);
+            if state.enabled {
+                state.save(w);
+            }
+            w.u32(next.map_or(u32::MAX, |n| n as u32));
+        }
+        w.u32(self.first_disabled.map_or(u32::MAX, |n| n as u32));
+    }
+
+    #[cfg_attr(not(feature = "graphics"), allow(dead_code))]
+    pub fn restore(&mut self, r: &mut Reader) -> Result<(), SnapshotError> {
+        let index = |r: &mut Reader| match r.u32()? {
+            u32::MAX => Ok(None),
+            n if (n as usize) < Self::BULLET_MAX => Ok(Some(n as usize)),
+            _ => Err(r.invalid("bullet index out of the pool")),
+        };
+
+        self.enabled_count = 0;
+        for idx in 0..Self::BULLET_MAX {
+            let mut state = self.states[idx].borrow_mut();
+            let generation = r.u32()?;
+            if r.bool()? {
+                state.restore(r)?;
+                self.enabled_count += 1;
+            } else {
+                state.enabled = false;
+                state.visible = false;
             }
+            state.generation = generation;
+            self.nexts[idx] = index(r)?;
         }
+        self.first_disabled = index(r)?;
 
         Ok(())
     }
 
-    pub fn draw(&mut self, ctx: &mut Context, canvas: &mut graphics::Canvas) -> GameResult<()> {
+    // Turns enemy bullets into items which stop their scripts and fly to the player.
+    // Returns the number of cleared bullets.
+    pub fn clear_to_items(&mut self) -> u32 {
+        let mut cleared = 0;
+
+        for state in self.states.iter() {
+            let mut state = state.borrow_mut();
+            if !state.enabled
+                || matches!(
+                    state.appearance.r#type,
+                    BulletType::PlayerShot | BulletType::Item
+                )
+            {
+                continue;
+            }
+
+            state.appearance.r#type = BulletType::Item;
+            state.hit = false;
+            state.grazed = false;
+            state.motion = Motion::default();
+
+            let vm = &mut state.vm;
+            vm.set_code(Rc::new(Self::EMPTY_BULLET_CODE.to_vec()));
+            vm.stack.clear();
+            vm.rstack.clear();
+            vm.suspended = false;
+
+            cleared += 1;
+        }
+
+        cleared
+    }
+
+    #[cfg(feature = "graphics")]
+    pub fn draw(
+        &mut self,
+        ctx: &mut Context,
+        canvas: &mut graphics::Canvas,
+        counters: &Counters,
+    ) -> GameResult<()> {
         for idx in 0..Self::BULLET_MAX {
             let state = self.states[idx].borrow();
 
@@ -112,9 +182,17 @@ impl BulletSet {
             r##"
 fps: {}
 object num: {}
+score: {}
+graze: {}
+lives: {}
+bombs: {}
 "##,
             ctx.time.fps(),
-            self.enabled_count
+            self.enabled_count,
+            counters.score,
+            counters.graze,
+            counters.lives,
+            counters.bombs
         );
         let debug_msg = graphics::Text::new(debug_msg);
         let param = graphics::DrawParam::default().dest(glam::vec2(10.0, 0.0));
@@ -131,41 +209,31 @@ impl OperationProcessor for BulletSet {
         y: f32,
         r#type: BulletType,
         color: BulletColor,
-        _params: Vec<Data>,
+        params: Vec<Data>,
         bullet_code: Rc<BulletCode>,
-    ) -> bool {
-        if self.first_disabled.is_none() {
-            return false;
-        }
-
-        let idx = self.first_disabled.unwrap();
+    ) -> Option<usize> {
+        let idx = self.first_disabled?;
 
         self.first_disabled = self.nexts[idx];
         self.nexts[idx] = None;
         self.enabled_count += 1;
 
         let mut state = self.states[idx].borrow_mut();
-        state.enabled = true;
-        state.visible = true;
-        state.pos.x = x;
-        state.pos.y = y;
+        state.load(x, y, &bullet_code, params);
         state.appearance.r#type = r#type;
         state.appearance.color = color;
 
-        let vm = &mut state.vm;
-        vm.code = bullet_code.code.clone();
-        vm.memory
-            .as_mut_slice()
-            .copy_from_slice(bullet_code.initial_memory.as_slice());
-        vm.stack.clear();
-
-        true
+        Some(idx)
     }
 
+    // Killing a disabled bullet does nothing, as scripts may call `die()` twice in a frame.
     fn kill(&mut self, id: usize) {
+        let mut state = self.states[id].borrow_mut();
+        if !state.enabled {
+            return;
+        }
         self.enabled_count -= 1;
 
-        let mut state = self.states[id].borrow_mut();
         state.enabled = false;
         state.visible = false;
 
@@ -174,3 +242,42 @@ impl OperationProcessor for BulletSet {
         self.first_disabled = Some(id);
     }
 }
+
+#[cfg(test)]
+mod bullet_pool_test {
+    use super::*;
+
+    fn fire(bullets: &mut BulletSet) -> usize {
+        let bc = Rc::new(BulletCode::new(""));
+        bullets
+            .fire(
+                0.0,
+                0.0,
+                BulletType::Bullet1,
+                BulletColor::White,
+                vec![],
+                bc,
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn test_kill_twice() {
+        let mut bullets = BulletSet::new();
+        let a = fire(&mut bullets);
+        let b = fire(&mut bullets);
+
+        bullets.kill(a);
+        bullets.kill(a);
+        assert_eq!(bullets.enabled_count, 1);
+        assert_ne!(bullets.nexts[a], Some(a));
+
+        // the slot is handed out only once
+        let c = fire(&mut bullets);
+        let d = fire(&mut bullets);
+        assert_eq!(c, a);
+        assert_ne!(d, a);
+        assert_ne!(d, b);
+        assert_eq!(bullets.enabled_count, 3);
+    }
+}
@@ -0,0 +1,451 @@
+use lang_component::bullet::BulletType;
+
+use super::{bullet::Bullet, bullet_pool::BulletSet, enemy_pool::EnemySet, grid::Grid};
+
+// The largest radius returned by `hit_radius()` for bullets in the grid.
+const MAX_HIT_RADIUS: f32 = 6.0;
+
+// Hit points taken from an enemy by a player shot.
+pub const SHOT_DAMAGE: f32 = 1.0;
+
+// Enemy bullets within this distance from the player are grazed.
+pub const GRAZE_RADIUS: f32 = 20.0;
+
+// Returns the radius of the hit circle of bullets of `r#type`.
+pub fn hit_radius(r#type: BulletType) -> f32 {
+    match r#type {
+        BulletType::Player => 5.0,
+        BulletType::PlayerShot => 3.0,
+        BulletType::Bullet1 => 4.0,
+        BulletType::Item => 6.0,
+        BulletType::Enemy1 => 16.0,
+    }
+}
+
+pub fn overlaps(a: &Bullet, b: &Bullet) -> bool {
+    let r = hit_radius(a.appearance.r#type) + hit_radius(b.appearance.r#type);
+    a.pos.distance_squared(b.pos) < r * r
+}
+
+pub fn grazes(player: &Bullet, bullet: &Bullet) -> bool {
+    let r = GRAZE_RADIUS + hit_radius(bullet.appearance.r#type);
+    player.pos.distance_squared(bullet.pos) < r * r
+}
+
+// Results of collision checks in a frame.
+#[derive(Debug, Default)]
+pub struct Collisions {
+    // the number of bullets grazed for the first time
+    pub grazes: u32,
+    // indices of items touched by the player
+    pub items: Vec<usize>,
+}
+
+// Checks hits between the player and enemy bullets, and sets `hit` of both of them.
+// Flags are cleared every frame so scripts see the hits in the last frame as `self.hit`.
+// While the player is `invulnerable`, bullets pass through it but are still grazed.
+//
+// `grid` is rebuilt here to check only bullets near the player.
+pub fn check_collisions(
+    player: &mut Bullet,
+    bullets: &BulletSet,
+    grid: &mut Grid,
+    invulnerable: bool,
+) -> Collisions {
+    player.hit = false;
+    for state in bullets.states.iter() {
+        state.borrow_mut().hit = false;
+    }
+    grid.rebuild(bullets);
+
+    let mut collisions = Collisions::default();
+    let radius = hit_radius(player.appearance.r#type).max(GRAZE_RADIUS) + MAX_HIT_RADIUS;
+    grid.query(player.pos.x, player.pos.y, radius, |idx| {
+        let mut bullet = bullets.states[idx].borrow_mut();
+
+        match bullet.appearance.r#type {
+            // player shots hit only enemies
+            BulletType::PlayerShot => (),
+            BulletType::Item => {
+                if overlaps(player, &bullet) {
+                    collisions.items.push(idx);
+                }
+            }
+            _ => {
+                if !invulnerable && overlaps(player, &bullet) {
+                    player.hit = true;
+                    bullet.hit = true;
+                }
+                if !bullet.grazed && grazes(player, &bullet) {
+                    bullet.grazed = true;
+                    collisions.grazes += 1;
+                }
+            }
+        }
+    });
+
+    collisions
+}
+
+// Checks hits between player shots and enemies. Enemies lose hit points and get `hit` set,
+// and `hit` of the shots is set so that a shot damages only one enemy.
+// Returns indices of the shots which hit.
+//
+// `grid` must be rebuilt by `check_collisions()` in the frame.
+pub fn check_shots(enemies: &EnemySet, bullets: &BulletSet, grid: &Grid) -> Vec<usize> {
+    let mut shots = Vec::new();
+
+    for state in enemies.states.iter() {
+        let mut enemy = state.borrow_mut();
+        enemy.hit = false;
+        if !enemy.enabled {
+            continue;
+        }
+
+        let radius = hit_radius(enemy.appearance.r#type) + MAX_HIT_RADIUS;
+        grid.query(enemy.pos.x, enemy.pos.y, radius, |idx| {
+            let mut shot = bullets.states[idx].borrow_mut();
+            if shot.appearance.r#type != BulletType::PlayerShot || shot.hit {
+                return;
+            }
+
+            if overlaps(&enemy, &shot) {
+                shot.hit = true;
+                enemy.hit = true;
+                enemy.hp -= SHOT_DAMAGE;
+                shots.push(idx);
+            }
+        });
+    }
+
+    shots
+}
+
+#[cfg(test)]
+mod collision_test {
+    use std::{
+        rc::Rc,
+        time::{Duration, Instant},
+    };
+
+    use lang_compiler::BulletCode;
+    use lang_component::bullet::BulletColor;
+
+    use super::*;
+    use crate::{
+        constant,
+        game::shooter::{rng::Rng, shooter::OperationProcessor},
+    };
+
+    fn bullet(x: f32, y: f32, r#type: BulletType) -> Bullet {
+        Bullet::new(
+            x,
+            y,
+            r#type,
+            BulletColor::White,
+            Rc::new(BulletCode::new("")),
+        )
+    }
+
+    fn fire(bullets: &mut BulletSet, x: f32, y: f32, r#type: BulletType) {
+        let bc = Rc::new(BulletCode::new(""));
+        assert!(bullets
+            .fire(x, y, r#type, BulletColor::White, vec![], bc)
+            .is_some());
+    }
+
+    #[test]
+    fn test_overlaps() {
+        let player = bullet(100.0, 100.0, BulletType::Player);
+
+        assert!(overlaps(
+            &player,
+            &bullet(108.0, 100.0, BulletType::Bullet1)
+        ));
+        assert!(!overlaps(
+            &player,
+            &bullet(109.0, 100.0, BulletType::Bullet1)
+        ));
+        assert!(overlaps(
+            &player,
+            &bullet(106.0, 106.0, BulletType::Bullet1)
+        ));
+        assert!(!overlaps(
+            &player,
+            &bullet(107.0, 107.0, BulletType::Bullet1)
+        ));
+    }
+
+    #[test]
+    fn test_check_collisions() {
+        let mut player = bullet(100.0, 100.0, BulletType::Player);
+        let mut bullets = BulletSet::new();
+        fire(&mut bullets, 100.0, 105.0, BulletType::Bullet1);
+        fire(&mut bullets, 200.0, 100.0, BulletType::Bullet1);
+        fire(&mut bullets, 100.0, 100.0, BulletType::PlayerShot);
+        let mut grid = Grid::new();
+
+        check_collisions(&mut player, &bullets, &mut grid, false);
+        assert!(player.hit);
+        assert!(bullets.states[0].borrow().hit);
+        assert!(!bullets.states[1].borrow().hit);
+        assert!(!bullets.states[2].borrow().hit);
+
+        // flags are only for the frame
+        player.pos.x = 300.0;
+        check_collisions(&mut player, &bullets, &mut grid, false);
+        assert!(!player.hit);
+        assert!(!bullets.states[0].borrow().hit);
+    }
+
+    #[test]
+    fn test_hit_radius() {
+        for r#type in [
+            BulletType::Player,
+            BulletType::PlayerShot,
+            BulletType::Bullet1,
+            BulletType::Item,
+        ] {
+            assert!(hit_radius(r#type) <= MAX_HIT_RADIUS);
+        }
+    }
+
+    #[test]
+    fn test_check_collisions_near_cell_borders() {
+        // the player and the bullet are in different cells
+        let mut player = bullet(Grid::CELL_SIZE - 1.0, 100.0, BulletType::Player);
+        let mut bullets = BulletSet::new();
+        fire(
+            &mut bullets,
+            Grid::CELL_SIZE + 7.0,
+            100.0,
+            BulletType::Bullet1,
+        );
+        let mut grid = Grid::new();
+
+        check_collisions(&mut player, &bullets, &mut grid, false);
+        assert!(player.hit);
+        assert!(bullets.states[0].borrow().hit);
+    }
+
+    #[test]
+    fn test_check_grazes() {
+        let mut player = bullet(100.0, 100.0, BulletType::Player);
+        let mut bullets = BulletSet::new();
+        fire(&mut bullets, 100.0, 123.0, BulletType::Bullet1);
+        fire(&mut bullets, 100.0, 125.0, BulletType::Bullet1);
+        fire(&mut bullets, 100.0, 90.0, BulletType::PlayerShot);
+        let mut grid = Grid::new();
+
+        assert_eq!(
+            check_collisions(&mut player, &bullets, &mut grid, false).grazes,
+            1
+        );
+        assert!(!player.hit);
+        assert!(bullets.states[0].borrow().grazed);
+        assert!(!bullets.states[1].borrow().grazed);
+
+        // each bullet is grazed only once
+        bullets.states[1].borrow_mut().pos.y = 115.0;
+        assert_eq!(
+            check_collisions(&mut player, &bullets, &mut grid, false).grazes,
+            1
+        );
+        assert_eq!(
+            check_collisions(&mut player, &bullets, &mut grid, false).grazes,
+            0
+        );
+    }
+
+    #[test]
+    fn test_check_collisions_invulnerable() {
+        let mut player = bullet(100.0, 100.0, BulletType::Player);
+        let mut bullets = BulletSet::new();
+        fire(&mut bullets, 100.0, 105.0, BulletType::Bullet1);
+        let mut grid = Grid::new();
+
+        // grazes still count while invulnerable
+        let collisions = check_collisions(&mut player, &bullets, &mut grid, true);
+        assert!(!player.hit);
+        assert!(!bullets.states[0].borrow().hit);
+        assert_eq!(collisions.grazes, 1);
+    }
+
+    #[test]
+    fn test_clear_to_items() {
+        let mut player = bullet(100.0, 100.0, BulletType::Player);
+        let mut bullets = BulletSet::new();
+        fire(&mut bullets, 100.0, 105.0, BulletType::Bullet1);
+        fire(&mut bullets, 300.0, 300.0, BulletType::Bullet1);
+        fire(&mut bullets, 100.0, 100.0, BulletType::PlayerShot);
+        let mut grid = Grid::new();
+
+        assert_eq!(bullets.clear_to_items(), 2);
+        assert_eq!(
+            bullets.states[0].borrow().appearance.r#type,
+            BulletType::Item
+        );
+        assert_eq!(
+            bullets.states[1].borrow().appearance.r#type,
+            BulletType::Item
+        );
+        assert_eq!(
+            bullets.states[2].borrow().appearance.r#type,
+            BulletType::PlayerShot
+        );
+        // items are not cleared again
+        assert_eq!(bullets.clear_to_items(), 0);
+
+        // items do not hit the player but are collected
+        let collisions = check_collisions(&mut player, &bullets, &mut grid, false);
+        assert!(!player.hit);
+        assert_eq!(collisions.grazes, 0);
+        assert_eq!(collisions.items, vec![0]);
+    }
+
+    #[test]
+    fn test_check_shots() {
+        let mut player = bullet(300.0, 600.0, BulletType::Player);
+        let mut bullets = BulletSet::new();
+        fire(&mut bullets, 100.0, 110.0, BulletType::PlayerShot);
+        fire(&mut bullets, 100.0, 120.0, BulletType::PlayerShot);
+        fire(&mut bullets, 100.0, 105.0, BulletType::Bullet1);
+        let mut enemies = EnemySet::new();
+        assert!(enemies
+            .spawn(100.0, 100.0, vec![], Rc::new(BulletCode::new("")))
+            .is_some());
+        assert!(enemies
+            .spawn(105.0, 100.0, vec![], Rc::new(BulletCode::new("")))
+            .is_some());
+        let mut grid = Grid::new();
+
+        check_collisions(&mut player, &bullets, &mut grid, false);
+        // each shot damages one enemy and enemy bullets do not
+        assert_eq!(check_shots(&enemies, &bullets, &grid), vec![0]);
+        let enemy = enemies.states[0].borrow();
+        assert!(enemy.hit);
+        assert_eq!(enemy.hp, EnemySet::ENEMY_HP - SHOT_DAMAGE);
+        let enemy = enemies.states[1].borrow();
+        assert!(!enemy.hit);
+        assert_eq!(enemy.hp, EnemySet::ENEMY_HP);
+    }
+
+    // Checks the results through the grid against checking all pairs, with the full pools.
+    #[test]
+    fn test_full_pools_match_all_pairs() {
+        let mut rng = Rng::new(1);
+        let mut rand_pos = || {
+            (
+                rng.next_f32() * constant::SHOOTER_WIDTH,
+                rng.next_f32() * constant::SHOOTER_HEIGHT,
+            )
+        };
+
+        let mut player = bullet(300.0, 420.0, BulletType::Player);
+        let mut bullets = BulletSet::new();
+        for n in 0..BulletSet::BULLET_MAX {
+            let (x, y) = rand_pos();
+            let r#type = if n % 10 == 0 {
+                BulletType::PlayerShot
+            } else {
+                BulletType::Bullet1
+            };
+            fire(&mut bullets, x, y, r#type);
+        }
+        let mut enemies = EnemySet::new();
+        for _ in 0..EnemySet::ENEMY_MAX {
+            let (x, y) = rand_pos();
+            let bc = Rc::new(BulletCode::new(""));
+            assert!(enemies.spawn(x, y, vec![], bc).is_some());
+        }
+        let mut grid = Grid::new();
+
+        let collisions = check_collisions(&mut player, &bullets, &mut grid, false);
+        let shots = check_shots(&enemies, &bullets, &grid);
+
+        let is_shot = |b: &Bullet| b.appearance.r#type == BulletType::PlayerShot;
+        let mut hits = Vec::new();
+        let mut grazed = 0;
+        for (idx, state) in bullets.states.iter().enumerate() {
+            let bullet = state.borrow();
+            if !is_shot(&bullet) && overlaps(&player, &bullet) {
+                hits.push(idx);
+            }
+            if !is_shot(&bullet) && grazes(&player, &bullet) {
+                grazed += 1;
+            }
+        }
+        let hit_by_grid: Vec<usize> = (0..BulletSet::BULLET_MAX)
+            .filter(|idx| !is_shot(&bullets.states[*idx].borrow()))
+            .filter(|idx| bullets.states[*idx].borrow().hit)
+            .collect();
+        assert_eq!(hit_by_grid, hits);
+        assert_eq!(player.hit, !hits.is_empty());
+        assert_eq!(collisions.grazes, grazed);
+
+        // enemies take overlapping shots in order, each shot only once
+        let mut taken = vec![false; BulletSet::BULLET_MAX];
+        let mut expected = Vec::new();
+        for state in enemies.states.iter() {
+            let enemy = state.borrow();
+            let mut damage = 0.0;
+            for (idx, shot) in bullets.states.iter().enumerate() {
+                let shot = shot.borrow();
+                if is_shot(&shot) && !taken[idx] && overlaps(&enemy, &shot) {
+                    taken[idx] = true;
+                    expected.push(idx);
+                    damage += SHOT_DAMAGE;
+                }
+            }
+            assert_eq!(enemy.hp, EnemySet::ENEMY_HP - damage);
+        }
+        let mut shots = shots;
+        shots.sort();
+        expected.sort();
+        assert!(!expected.is_empty());
+        assert_eq!(shots, expected);
+    }
+
+    // Measures collision checks with the full pools. Run with `cargo test --release -- --ignored`.
+    #[test]
+    #[ignore]
+    fn bench_check_collisions_full_pool() {
+        let mut player = bullet(300.0, 420.0, BulletType::Player);
+        let mut bullets = BulletSet::new();
+        for n in 0..BulletSet::BULLET_MAX {
+            let x = (n % 60) as f32 * 10.0;
+            let y = (n / 60) as f32 * 12.5;
+            let r#type = if n % 10 == 0 {
+                BulletType::PlayerShot
+            } else {
+                BulletType::Bullet1
+            };
+            fire(&mut bullets, x, y, r#type);
+        }
+        let mut enemies = EnemySet::new();
+        for n in 0..EnemySet::ENEMY_MAX {
+            let x = (n % 8) as f32 * 75.0;
+            let y = (n / 8) as f32 * 100.0;
+            let bc = Rc::new(BulletCode::new(""));
+            assert!(enemies.spawn(x, y, vec![], bc).is_some());
+        }
+        let mut grid = Grid::new();
+
+        let frames = 600;
+        let start = Instant::now();
+        for _ in 0..frames {
+            check_collisions(&mut player, &bullets, &mut grid, false);
+            check_shots(&enemies, &bullets, &grid);
+        }
+        let elapsed = start.elapsed();
+
+        println!(
+            "{} bullets and {} enemies: {:?} per frame",
+            BulletSet::BULLET_MAX,
+            EnemySet::ENEMY_MAX,
+            elapsed / frames
+        );
+        // a frame at 60fps is 16.6ms and collision checks should take a small part of it
+        assert!(elapsed / frames < Duration::from_millis(4));
+    }
+}
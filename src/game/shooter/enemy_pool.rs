@@ -0,0 +1,147 @@
+use std::{cell::RefCell, collections::VecDeque, rc::Rc};
+
+#[cfg(feature = "graphics")]
+use ggez::{graphics, Context, GameResult};
+
+use lang_compiler::BulletCode;
+use lang_component::{
+    bullet::{BulletColor, BulletId, BulletType},
+    vm::{Data, Inst, OperationQuery},
+};
+
+#[cfg(feature = "graphics")]
+use super::scene::SceneDrawable;
+use super::{
+    bullet::Bullet,
+    bullet_pool::BulletSet,
+    refs::Refs,
+    rng::Rng,
+    shooter::{Counters, ShooterError},
+    snapshot::{Reader, SnapshotError, Writer},
+};
+
+// Enemies spawned by scripts, which have hit points and are addressed as `BulletId::Enemy(n)`.
+// Enemies are few so the pool is searched linearly.
+pub struct EnemySet {
+    pub states: Vec<Rc<RefCell<Bullet>>>,
+}
+
+impl EnemySet {
+    pub const ENEMY_MAX: usize = 64;
+    // hit points of spawned enemies, which scripts may change with `self.hp`
+    pub const ENEMY_HP: f32 = 20.0;
+    const EMPTY_BULLET_CODE: [Inst; 1] = [Inst::Term];
+
+    pub fn new() -> Self {
+        let mut states = Vec::new();
+
+        for _ in 0..Self::ENEMY_MAX {
+            let mut state = Bullet::new(
+                0.0,
+                0.0,
+                BulletType::Enemy1,
+                BulletColor::White,
+                Rc::new(BulletCode::new("")),
+            );
+            state.vm.set_code(Rc::new(Self::EMPTY_BULLET_CODE.to_vec()));
+
+            states.push(Rc::new(RefCell::new(state)));
+        }
+
+        Self { states }
+    }
+
+    pub fn spawn(
+        &mut self,
+        x: f32,
+        y: f32,
+        params: Vec<Data>,
+        bullet_code: Rc<BulletCode>,
+    ) -> Option<usize> {
+        let idx = self
+            .states
+            .iter()
+            .position(|state| !state.borrow().enabled)?;
+
+        let mut state = self.states[idx].borrow_mut();
+        state.load(x, y, &bullet_code, params);
+        state.hp = Self::ENEMY_HP;
+        state.appearance.r#type = BulletType::Enemy1;
+
+        Some(idx)
+    }
+
+    pub fn kill(&mut self, id: usize) {
+        let mut state = self.states[id].borrow_mut();
+        state.enabled = false;
+        state.visible = false;
+    }
+
+    // Disabled enemies are saved only with their generations, which links need.
+    #[cfg_attr(not(feature = "graphics"), allow(dead_code))]
+    pub fn save(&self, w: &mut Writer) {
+        for state in self.states.iter() {
+            let state = state.borrow();
+            w.u32(state.generation);
+            w.bool(state.enabled);
+            if state.enabled {
+                state.save(w);
+            }
+        }
+    }
+
+    #[cfg_attr(not(feature = "graphics"), allow(dead_code))]
+    pub fn restore(&mut self, r: &mut Reader) -> Result<(), SnapshotError> {
+        for state in self.states.iter() {
+            let mut state = state.borrow_mut();
+            let generation = r.u32()?;
+            if r.bool()? {
+                state.restore(r)?;
+            } else {
+                state.enabled = false;
+                state.visible = false;
+            }
+            state.generation = generation;
+        }
+
+        Ok(())
+    }
+
+    pub fn update(
+        &mut self,
+        player: Rc<RefCell<Bullet>>,
+        bullets: &BulletSet,
+        op_queue: &mut VecDeque<OperationQuery>,
+        rng: &mut Rng,
+        counters: &Counters,
+    ) -> Result<(), ShooterError> {
+        let refs = Refs {
+            player: &player,
+            enemies: &self.states,
+            bullets: &bullets.states,
+        };
+
+        for idx in 0..Self::ENEMY_MAX {
+            let mut state = self.states[idx].borrow_mut();
+
+            if state.enabled {
+                state.update(idx, BulletId::Enemy(idx), &refs, op_queue, rng, counters)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    #[cfg(feature = "graphics")]
+    pub fn draw(&self, ctx: &mut Context, canvas: &mut graphics::Canvas) -> GameResult<()> {
+        for state in self.states.iter() {
+            let state = state.borrow();
+
+            if state.visible {
+                state.draw(ctx, canvas)?;
+            }
+        }
+
+        Ok(())
+    }
+}
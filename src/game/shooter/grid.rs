@@ -0,0 +1,61 @@
+use super::bullet_pool::BulletSet;
+use crate::constant;
+
+// Uniform grid over the shooter field to find bullets near a position, rebuilt every frame.
+// Bullets out of the field are put in the cells on the edges.
+pub struct Grid {
+    cols: usize,
+    rows: usize,
+    cells: Vec<Vec<usize>>,
+}
+
+impl Grid {
+    pub const CELL_SIZE: f32 = 32.0;
+
+    pub fn new() -> Self {
+        let cols = (constant::SHOOTER_WIDTH / Self::CELL_SIZE).ceil() as usize;
+        let rows = (constant::SHOOTER_HEIGHT / Self::CELL_SIZE).ceil() as usize;
+
+        Self {
+            cols,
+            rows,
+            cells: vec![Vec::new(); cols * rows],
+        }
+    }
+
+    fn cell_of(&self, x: f32, y: f32) -> (usize, usize) {
+        let col = (x / Self::CELL_SIZE).floor().max(0.0) as usize;
+        let row = (y / Self::CELL_SIZE).floor().max(0.0) as usize;
+        (col.min(self.cols - 1), row.min(self.rows - 1))
+    }
+
+    // Puts indices of enabled bullets into cells. Cells keep their allocations.
+    pub fn rebuild(&mut self, bullets: &BulletSet) {
+        for cell in self.cells.iter_mut() {
+            cell.clear();
+        }
+
+        for (idx, state) in bullets.states.iter().enumerate() {
+            let bullet = state.borrow();
+            if bullet.enabled {
+                let (col, row) = self.cell_of(bullet.pos.x, bullet.pos.y);
+                self.cells[row * self.cols + col].push(idx);
+            }
+        }
+    }
+
+    // Calls `f` with indices of bullets in cells within `radius` from (x, y).
+    // Bullets in the cells may be farther than `radius` so check them precisely.
+    pub fn query<F: FnMut(usize)>(&self, x: f32, y: f32, radius: f32, mut f: F) {
+        let (col1, row1) = self.cell_of(x - radius, y - radius);
+        let (col2, row2) = self.cell_of(x + radius, y + radius);
+
+        for row in row1..=row2 {
+            for col in col1..=col2 {
+                for idx in self.cells[row * self.cols + col].iter() {
+                    f(*idx);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,472 @@
+use std::{cell::RefCell, fmt, io, io::Write, rc::Rc};
+
+use super::{
+    bullet::Bullet,
+    bullet_codes::BulletCodes,
+    replay::{self, Replay},
+    shooter::{Input, Shooter, ShooterError},
+};
+use crate::constant;
+
+const INPUT_NAMES: [(&str, Input); 7] = [
+    ("up", Input::Up),
+    ("down", Input::Down),
+    ("left", Input::Left),
+    ("right", Input::Right),
+    ("shot", Input::Shot),
+    ("slow", Input::Slow),
+    ("bomb", Input::Bomb),
+];
+
+#[derive(Debug)]
+pub enum InputScriptError {
+    // the line number from 1 and the line
+    InvalidLine(usize, String),
+    // frames of lines must increase
+    FrameNotIncreasing(usize, String),
+}
+
+impl fmt::Display for InputScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InputScriptError::InvalidLine(n, line) => {
+                write!(f, "invalid input at line {}: '{}'", n, line)
+            }
+            InputScriptError::FrameNotIncreasing(n, line) => {
+                write!(f, "frame does not increase at line {}: '{}'", n, line)
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum HeadlessError {
+    Shooter(ShooterError),
+    Io(io::Error),
+    // the replay was recorded with other scripts
+    OtherScripts,
+    // the frame where the state differs from the replay
+    Diverged(u32),
+}
+
+impl fmt::Display for HeadlessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HeadlessError::Shooter(err) => write!(f, "{}", err),
+            HeadlessError::Io(err) => write!(f, "cannot write states: {}", err),
+            HeadlessError::OtherScripts => write!(f, "replay was recorded with other scripts"),
+            HeadlessError::Diverged(frame) => write!(f, "replay diverged at frame {}", frame),
+        }
+    }
+}
+
+impl From<ShooterError> for HeadlessError {
+    fn from(err: ShooterError) -> Self {
+        HeadlessError::Shooter(err)
+    }
+}
+
+impl From<io::Error> for HeadlessError {
+    fn from(err: io::Error) -> Self {
+        HeadlessError::Io(err)
+    }
+}
+
+// Inputs of the player by frames, written in lines of `FRAME INPUT...` like `60 shot left`.
+// Inputs in a line are held from the frame until the frame of the next line.
+// Empty lines and lines starting with `#` are ignored.
+#[derive(Debug, Default)]
+pub struct InputScript {
+    steps: Vec<(u32, Vec<Input>)>,
+}
+
+impl InputScript {
+    pub fn parse(s: &str) -> Result<Self, InputScriptError> {
+        let mut steps: Vec<(u32, Vec<Input>)> = Vec::new();
+
+        for (n, line) in s.lines().enumerate() {
+            let invalid = || InputScriptError::InvalidLine(n + 1, line.to_string());
+            let mut words = line.split_whitespace();
+
+            let frame = match words.next() {
+                None => continue,
+                Some(word) if word.starts_with('#') => continue,
+                Some(word) => word.parse::<u32>().map_err(|_| invalid())?,
+            };
+            if matches!(steps.last(), Some((last, _)) if *last >= frame) {
+                return Err(InputScriptError::FrameNotIncreasing(
+                    n + 1,
+                    line.to_string(),
+                ));
+            }
+
+            let mut inputs = Vec::new();
+            for word in words {
+                match INPUT_NAMES.iter().find(|(name, _)| *name == word) {
+                    Some((_, input)) => inputs.push(*input),
+                    None => return Err(invalid()),
+                }
+            }
+            steps.push((frame, inputs));
+        }
+
+        Ok(Self { steps })
+    }
+
+    fn inputs_at(&self, frame: u32) -> &[Input] {
+        match self.steps.iter().rev().find(|(f, _)| *f <= frame) {
+            Some((_, inputs)) => inputs,
+            None => &[],
+        }
+    }
+}
+
+fn enabled_count(states: &[Rc<RefCell<Bullet>>]) -> usize {
+    states.iter().filter(|state| state.borrow().enabled).count()
+}
+
+// Writes a line of the state after the frame.
+fn dump<W: Write>(frame: u32, shooter: &Shooter, out: &mut W) -> io::Result<()> {
+    let objects = shooter.objects();
+    let counters = shooter.counters();
+    let player = objects.player.borrow();
+
+    writeln!(
+        out,
+        "{} x={:.2} y={:.2} lives={} bombs={} score={} graze={} enemies={} bullets={}",
+        frame,
+        player.pos.x,
+        player.pos.y,
+        counters.lives,
+        counters.bombs,
+        counters.score,
+        counters.graze,
+        enabled_count(&objects.enemies.states),
+        enabled_count(&objects.bullets.states),
+    )?;
+
+    Ok(())
+}
+
+// Runs the stage for `frames` frames without a window, and writes the state every frame.
+// Returns the replay of the run.
+pub fn run_headless<W: Write>(
+    bullet_codes: BulletCodes,
+    frames: u32,
+    inputs: &InputScript,
+    out: &mut W,
+) -> Result<Replay, HeadlessError> {
+    let mut shooter = Shooter::new(bullet_codes, constant::RANDOM_SEED);
+
+    for frame in 0..frames {
+        let held = inputs.inputs_at(frame);
+        for (_, input) in INPUT_NAMES.iter() {
+            shooter.input(input, held.contains(input));
+        }
+
+        shooter.update()?;
+        dump(frame, &shooter, out)?;
+    }
+
+    Ok(shooter.replay().clone())
+}
+
+// Plays `replay` back without a window, and writes the state every frame as `run_headless`.
+// Fails when the state differs from the recorded checksums.
+pub fn play_replay<W: Write>(
+    bullet_codes: BulletCodes,
+    replay: &Replay,
+    out: &mut W,
+) -> Result<(), HeadlessError> {
+    if replay.scripts_hash != bullet_codes.hash {
+        return Err(HeadlessError::OtherScripts);
+    }
+    let mut shooter = Shooter::new(bullet_codes, replay.seed);
+
+    for (frame, bits) in (0..replay.frames()).zip(replay.inputs()) {
+        for (n, input) in replay::INPUTS.iter().enumerate() {
+            shooter.input(input, bits & 1 << n != 0);
+        }
+
+        shooter.update()?;
+        dump(frame, &shooter, out)?;
+
+        match replay.checksum_at(frame) {
+            Some(checksum) if checksum != shooter.checksum() => {
+                return Err(HeadlessError::Diverged(frame));
+            }
+            _ => (),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod headless_test {
+    use super::*;
+    use crate::game::shooter::{bullet_codes::load_scripts as load, snapshot::SnapshotError};
+
+    fn run(bullet_codes: BulletCodes, frames: u32, inputs: &str) -> Vec<String> {
+        let inputs = InputScript::parse(inputs).unwrap();
+        let mut out = Vec::new();
+        run_headless(bullet_codes, frames, &inputs, &mut out).unwrap();
+
+        String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|line| line.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_input_script() {
+        let inputs = InputScript::parse(
+            r##"
+            # comments and empty lines are skipped
+            0
+            10 right shot
+
+            20 slow
+            "##,
+        )
+        .unwrap();
+        assert_eq!(inputs.inputs_at(0), &[]);
+        assert_eq!(inputs.inputs_at(15), &[Input::Right, Input::Shot]);
+        assert_eq!(inputs.inputs_at(100), &[Input::Slow]);
+
+        assert!(matches!(
+            InputScript::parse("0 jump"),
+            Err(InputScriptError::InvalidLine(1, _))
+        ));
+        assert!(matches!(
+            InputScript::parse("right"),
+            Err(InputScriptError::InvalidLine(1, _))
+        ));
+        assert!(matches!(
+            InputScript::parse("10\n5 shot"),
+            Err(InputScriptError::FrameNotIncreasing(2, _))
+        ));
+    }
+
+    #[test]
+    fn test_run_headless() {
+        let scripts = [
+            (
+                "player",
+                r##"
+                proc main() {
+                  if player.input_right {
+                    self.x = self.x + 2
+                  }
+                  if player.input_shot && self.frames % 10 == 0 {
+                    fire("shot", self.x, self.y)
+                  }
+                }
+                "##,
+            ),
+            (
+                "shot",
+                r##"
+                proc main() {
+                  self.vy = -10
+                  if self.y < 0 {
+                    die()
+                  }
+                }
+                "##,
+            ),
+        ];
+        let dumps = run(load("run", &scripts), 100, "0\n10 right shot\n20");
+
+        assert_eq!(dumps.len(), 100);
+        assert_eq!(
+            dumps[0],
+            "0 x=200.00 y=400.00 lives=3 bombs=3 score=0 graze=0 enemies=0 bullets=0"
+        );
+        // moved for 10 frames and fired at the frame 10
+        assert_eq!(
+            dumps[19],
+            "19 x=220.00 y=400.00 lives=3 bombs=3 score=0 graze=0 enemies=0 bullets=1"
+        );
+        // the shot went out of the field
+        assert_eq!(
+            dumps[99],
+            "99 x=220.00 y=400.00 lives=3 bombs=3 score=0 graze=0 enemies=0 bullets=0"
+        );
+    }
+
+    #[test]
+    fn test_run_headless_is_deterministic() {
+        let scripts = [
+            (
+                "player",
+                r##"
+                proc main() {
+                  if self.frames % 3 == 0 {
+                    fire("spread", rand_range(0, 600), 0)
+                  }
+                }
+                "##,
+            ),
+            (
+                "spread",
+                r##"
+                proc main() {
+                  if self.age == 0 {
+                    self.speed = rand_range(1, 5)
+                    self.angle = rand_range(0, 180)
+                  }
+                }
+                "##,
+            ),
+        ];
+
+        let dumps1 = run(load("det1", &scripts), 300, "");
+        let dumps2 = run(load("det2", &scripts), 300, "");
+        assert_eq!(dumps1, dumps2);
+        // the stage really ran
+        assert_ne!(dumps1.first(), dumps1.last());
+    }
+
+    #[test]
+    fn test_snapshot_and_restore() {
+        let scripts = [
+            (
+                "player",
+                r##"
+                proc main() {
+                  if self.frames % 50 == 0 {
+                    spawn("ring", rand_range(100, 300), 0)
+                  }
+                }
+                "##,
+            ),
+            (
+                "ring",
+                r##"
+                proc main() {
+                  self.vy = 0.5
+                  for i in 0..6 {
+                    fire("spread", self.x, self.y, i * 60 + rand_range(0, 10))
+                    wait(7)
+                  }
+                }
+                "##,
+            ),
+            (
+                "spread",
+                r##"
+                proc main(dir: float) {
+                  self.speed = 2
+                  self.angle = dir + parent.x / 100
+                }
+                "##,
+            ),
+        ];
+        let bullet_codes = load("snapshot", &scripts);
+
+        // runs frames and returns lines of the state and checksums
+        fn run_frames(shooter: &mut Shooter, frames: std::ops::Range<u32>) -> Vec<String> {
+            let mut lines = Vec::new();
+            for frame in frames {
+                shooter.input(&Input::Left, frame & 16 == 0);
+                shooter.update().unwrap();
+
+                let mut out = Vec::new();
+                dump(frame, shooter, &mut out).unwrap();
+                let line = String::from_utf8(out).unwrap();
+                lines.push(format!("{} {:x}", line.trim(), shooter.checksum()));
+            }
+            lines
+        }
+
+        let mut shooter = Shooter::new(bullet_codes.clone(), 1);
+        run_frames(&mut shooter, 0..103);
+        let snapshot = shooter.snapshot();
+        let expected = run_frames(&mut shooter, 103..200);
+        let replay = shooter.replay().clone();
+        // scripts are suspended by `wait()` at the snapshot
+        assert!(expected.iter().any(|line| !line.contains("bullets=0")));
+
+        shooter.restore(&snapshot).unwrap();
+        assert_eq!(run_frames(&mut shooter, 103..200), expected);
+        assert_eq!(shooter.replay(), &replay);
+
+        // into another stage with the same scripts
+        let mut other = Shooter::new(bullet_codes, 2);
+        other.restore(&snapshot).unwrap();
+        assert_eq!(run_frames(&mut other, 103..200), expected);
+
+        // broken snapshots leave the stage as it is
+        let checksum = shooter.checksum();
+        assert_eq!(
+            shooter.restore(&snapshot[..snapshot.len() - 1]),
+            Err(SnapshotError::Truncated)
+        );
+        assert_eq!(shooter.checksum(), checksum);
+        let mut other = Shooter::new(load("snapshot-other", &[("player", "proc main() {}")]), 1);
+        assert_eq!(other.restore(&snapshot), Err(SnapshotError::OtherScripts));
+    }
+
+    #[test]
+    fn test_play_replay() {
+        let scripts = [
+            (
+                "player",
+                r##"
+                proc main() {
+                  if player.input_left {
+                    self.x = self.x - 1
+                  }
+                  if self.frames % 5 == 0 {
+                    fire("spread", rand_range(0, 400), 0)
+                  }
+                }
+                "##,
+            ),
+            (
+                "spread",
+                r##"
+                proc main() {
+                  if self.age == 0 {
+                    self.speed = rand_range(1, 5)
+                    self.angle = rand_range(0, 180)
+                  }
+                }
+                "##,
+            ),
+        ];
+
+        let inputs = InputScript::parse("30 left shot\n90\n150 left").unwrap();
+        let mut recorded = Vec::new();
+        let replay = run_headless(load("rec", &scripts), 200, &inputs, &mut recorded).unwrap();
+        assert_eq!(replay.frames(), 200);
+
+        // through a file
+        let mut buf = Vec::new();
+        replay.write(&mut buf).unwrap();
+        let replay = Replay::read(&mut buf.as_slice()).unwrap();
+
+        let mut played = Vec::new();
+        play_replay(load("play", &scripts), &replay, &mut played).unwrap();
+        assert_eq!(recorded, played);
+
+        // with other inputs than recorded
+        let mut tampered = Replay::new(replay.seed, replay.scripts_hash);
+        for (n, bits) in replay.inputs().enumerate() {
+            tampered.record(if n == 100 { 0b100 } else { bits });
+            if let Some(checksum) = replay.checksum_at(n as u32) {
+                tampered.record_checksum(checksum);
+            }
+        }
+        let err = play_replay(load("tampered", &scripts), &tampered, &mut Vec::new());
+        assert!(matches!(err, Err(HeadlessError::Diverged(119))));
+
+        // with other scripts than recorded
+        let mut scripts = scripts;
+        scripts[1].1 = "proc main() {}";
+        let err = play_replay(load("other", &scripts), &replay, &mut Vec::new());
+        assert!(matches!(err, Err(HeadlessError::OtherScripts)));
+    }
+}
@@ -0,0 +1,227 @@
+use std::collections::VecDeque;
+
+use super::shooter::{Shooter, ShooterError};
+
+// Controls of the stage to inspect patterns while writing them: pause, frame step,
+// slow motion and rewind.
+//
+// Snapshots of the last frames are kept in a ring buffer, and rewinding restores them
+// one per update from the newest. The ring is bounded by frames and by bytes, since
+// snapshots grow with the number of bullets.
+pub struct Practice {
+    paused: bool,
+    // steps a frame at the next update while paused
+    step: bool,
+    // the stage steps once per this number of updates
+    slowness: u32,
+    ticks: u32,
+    rewinding: bool,
+    history: VecDeque<Vec<u8>>,
+    // bytes of snapshots in `history`, which older ones are dropped to keep under `max_bytes`
+    history_bytes: usize,
+    max_bytes: usize,
+}
+
+impl Practice {
+    // 3 seconds
+    pub const HISTORY_FRAMES: usize = 180;
+    pub const HISTORY_BYTES: usize = 64 * 1024 * 1024;
+    const SLOWNESS: [u32; 3] = [1, 2, 4];
+
+    pub fn new() -> Self {
+        Self {
+            paused: false,
+            step: false,
+            slowness: 1,
+            ticks: 0,
+            rewinding: false,
+            history: VecDeque::with_capacity(Self::HISTORY_FRAMES),
+            history_bytes: 0,
+            max_bytes: Self::HISTORY_BYTES,
+        }
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+    }
+
+    // Steps a frame and pauses.
+    pub fn step(&mut self) {
+        self.paused = true;
+        self.step = true;
+    }
+
+    // Cycles the speed through 1x, 0.5x and 0.25x.
+    pub fn cycle_speed(&mut self) {
+        let idx = Self::SLOWNESS.iter().position(|s| *s == self.slowness);
+        let next = idx.map_or(0, |idx| (idx + 1) % Self::SLOWNESS.len());
+        self.slowness = Self::SLOWNESS[next];
+        self.ticks = 0;
+    }
+
+    pub fn set_rewinding(&mut self, rewinding: bool) {
+        self.rewinding = rewinding;
+    }
+
+    // Updates the stage for an update of the window.
+    pub fn update(&mut self, shooter: &mut Shooter) -> Result<(), ShooterError> {
+        if self.rewinding {
+            if let Some(snapshot) = self.history.pop_back() {
+                self.history_bytes -= snapshot.len();
+                shooter.restore(&snapshot)?;
+            }
+            return Ok(());
+        }
+
+        if self.paused {
+            if !self.step {
+                return Ok(());
+            }
+            self.step = false;
+        } else {
+            self.ticks += 1;
+            if self.ticks < self.slowness {
+                return Ok(());
+            }
+            self.ticks = 0;
+        }
+
+        let snapshot = shooter.snapshot();
+        while self.history.len() == Self::HISTORY_FRAMES
+            || !self.history.is_empty() && self.history_bytes + snapshot.len() > self.max_bytes
+        {
+            if let Some(oldest) = self.history.pop_front() {
+                self.history_bytes -= oldest.len();
+            }
+        }
+        self.history_bytes += snapshot.len();
+        self.history.push_back(snapshot);
+        shooter.update()
+    }
+
+    // Returns the text to show the controls in effect.
+    pub fn status(&self) -> Option<String> {
+        if self.rewinding {
+            Some(format!("rewind ({} frames left)", self.history.len()))
+        } else if self.paused {
+            Some("paused".to_string())
+        } else if self.slowness > 1 {
+            Some(format!("x{}", 1.0 / self.slowness as f32))
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod practice_test {
+    use super::*;
+    use crate::game::shooter::bullet_codes::load_scripts;
+
+    fn shooter(name: &str) -> Shooter {
+        let scripts = [(
+            "player",
+            r##"
+            proc main() {
+              self.x = self.x + 1
+            }
+            "##,
+        )];
+        Shooter::new(load_scripts(name, &scripts), 1)
+    }
+
+    fn x(shooter: &Shooter) -> f32 {
+        shooter.objects().player.borrow().pos.x
+    }
+
+    #[test]
+    fn test_pause_and_step() {
+        let mut shooter = shooter("pause");
+        let mut practice = Practice::new();
+        practice.update(&mut shooter).unwrap();
+        assert_eq!(x(&shooter), 201.0);
+
+        practice.toggle_pause();
+        practice.update(&mut shooter).unwrap();
+        assert_eq!(x(&shooter), 201.0);
+        assert_eq!(practice.status(), Some("paused".to_string()));
+
+        practice.step();
+        practice.update(&mut shooter).unwrap();
+        practice.update(&mut shooter).unwrap();
+        assert_eq!(x(&shooter), 202.0);
+
+        practice.toggle_pause();
+        practice.update(&mut shooter).unwrap();
+        assert_eq!(x(&shooter), 203.0);
+    }
+
+    #[test]
+    fn test_slow_motion() {
+        let mut shooter = shooter("slow");
+        let mut practice = Practice::new();
+        practice.cycle_speed();
+        practice.cycle_speed();
+        assert_eq!(practice.status(), Some("x0.25".to_string()));
+        for _ in 0..8 {
+            practice.update(&mut shooter).unwrap();
+        }
+        assert_eq!(x(&shooter), 202.0);
+
+        practice.cycle_speed();
+        assert_eq!(practice.status(), None);
+        practice.update(&mut shooter).unwrap();
+        assert_eq!(x(&shooter), 203.0);
+    }
+
+    #[test]
+    fn test_rewind() {
+        let mut shooter = shooter("rewind");
+        let mut practice = Practice::new();
+        for _ in 0..Practice::HISTORY_FRAMES + 20 {
+            practice.update(&mut shooter).unwrap();
+        }
+        assert_eq!(x(&shooter), 400.0);
+
+        practice.set_rewinding(true);
+        for _ in 0..10 {
+            practice.update(&mut shooter).unwrap();
+        }
+        // restored the state before the last 10th frame
+        assert_eq!(x(&shooter), 390.0);
+        assert_eq!(shooter.replay().frames(), 190);
+
+        // the history keeps only the last frames
+        for _ in 0..Practice::HISTORY_FRAMES {
+            practice.update(&mut shooter).unwrap();
+        }
+        assert_eq!(x(&shooter), 220.0);
+
+        practice.set_rewinding(false);
+        practice.update(&mut shooter).unwrap();
+        assert_eq!(x(&shooter), 221.0);
+    }
+
+    #[test]
+    fn test_history_bytes() {
+        let mut shooter = shooter("bytes");
+        let mut practice = Practice::new();
+        let size = shooter.snapshot().len();
+        practice.max_bytes = size * 10;
+        for _ in 0..20 {
+            practice.update(&mut shooter).unwrap();
+        }
+        // snapshots grow a little with the replay, so fewer than 10 fit
+        let kept = practice.history.len();
+        assert!(0 < kept && kept <= 10);
+        assert!(practice.history_bytes <= practice.max_bytes);
+
+        // the oldest kept is the state `kept` frames before
+        practice.set_rewinding(true);
+        for _ in 0..20 {
+            practice.update(&mut shooter).unwrap();
+        }
+        assert_eq!(x(&shooter), 220.0 - kept as f32);
+        assert_eq!(practice.history_bytes, 0);
+    }
+}
@@ -0,0 +1,232 @@
+use std::{cell::RefCell, rc::Rc};
+
+use lang_component::{
+    bullet::{BulletId, StateIO, StateId},
+    syntax::Type,
+    vm::Data,
+};
+
+use super::bullet::Bullet;
+
+// A reference to the player, an enemy or a bullet in pools, recorded at fire time.
+// Slots in pools are reused so the link is dead when `generation` of the slot changes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Link {
+    pub id: BulletId,
+    pub generation: u32,
+}
+
+// Objects which scripts refer to as `player`, `parent`, `child` and so on.
+//
+// References to dead objects are well-defined: reads return `false` or `0` by the type of
+// the state, so `parent.enabled` tells whether the parent is alive, and writes are ignored.
+pub struct Refs<'a> {
+    pub player: &'a RefCell<Bullet>,
+    pub enemies: &'a [Rc<RefCell<Bullet>>],
+    pub bullets: &'a [Rc<RefCell<Bullet>>],
+}
+
+impl<'a> Refs<'a> {
+    // Returns the cell of `id`, which must be `Player`, `Enemy` or `Bullet`.
+    pub fn cell(&self, id: BulletId) -> Option<&'a RefCell<Bullet>> {
+        match id {
+            BulletId::Player => Some(self.player),
+            BulletId::Enemy(n) => self.enemies.get(n).map(|state| state.as_ref()),
+            BulletId::Bullet(n) => self.bullets.get(n).map(|state| state.as_ref()),
+            BulletId::Itself | BulletId::Parent | BulletId::Child => None,
+        }
+    }
+
+    // Returns the link to `id` as it is now.
+    pub fn link(&self, id: BulletId) -> Option<Link> {
+        self.cell(id).map(|state| Link {
+            id,
+            generation: state.borrow().generation,
+        })
+    }
+
+    // Resolves `bid` seen from `state`, which is the object `own` and already borrowed,
+    // into the id of a living object.
+    fn resolve(&self, state: &Bullet, own: BulletId, bid: BulletId) -> Option<BulletId> {
+        let link = match bid {
+            BulletId::Itself => return Some(own),
+            BulletId::Player => return Some(BulletId::Player),
+            BulletId::Parent => state.parent?,
+            BulletId::Child => state.child?,
+            BulletId::Enemy(_) | BulletId::Bullet(_) => {
+                let alive = if bid == own {
+                    state.enabled
+                } else {
+                    self.cell(bid)?.borrow().enabled
+                };
+                return if alive { Some(bid) } else { None };
+            }
+        };
+
+        let alive = if link.id == own {
+            state.enabled && state.generation == link.generation
+        } else {
+            let target = self.cell(link.id)?.borrow();
+            target.enabled && target.generation == link.generation
+        };
+        if alive {
+            Some(link.id)
+        } else {
+            None
+        }
+    }
+
+    pub fn read(&self, state: &Bullet, own: BulletId, bid: BulletId, sid: &StateId) -> Data {
+        match self.resolve(state, own, bid) {
+            Some(id) if id == own => state.read(&bid, sid),
+            Some(id) => self.cell(id).unwrap().borrow().read(&bid, sid),
+            None => match sid.r#type() {
+                Type::Bool => Data::Bool(false),
+                _ => Data::Float(0.0),
+            },
+        }
+    }
+
+    pub fn write(&self, state: &mut Bullet, own: BulletId, bid: BulletId, sid: &StateId, d: Data) {
+        match self.resolve(state, own, bid) {
+            Some(id) if id == own => state.write(&bid, sid, d),
+            Some(id) => self.cell(id).unwrap().borrow_mut().write(&bid, sid, d),
+            None => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod refs_test {
+    use lang_compiler::BulletCode;
+    use lang_component::bullet::{BulletColor, BulletType};
+
+    use super::*;
+    use crate::game::shooter::{bullet_pool::BulletSet, shooter::OperationProcessor};
+
+    fn fire(bullets: &mut BulletSet, x: f32) -> usize {
+        let bc = Rc::new(BulletCode::new(""));
+        bullets
+            .fire(x, 0.0, BulletType::Bullet1, BulletColor::White, vec![], bc)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_parent_and_child() {
+        let player = RefCell::new(Bullet::new(
+            0.0,
+            0.0,
+            BulletType::Player,
+            BulletColor::White,
+            Rc::new(BulletCode::new("")),
+        ));
+        let mut bullets = BulletSet::new();
+        let parent = fire(&mut bullets, 10.0);
+        let child = fire(&mut bullets, 20.0);
+        {
+            let refs = Refs {
+                player: &player,
+                enemies: &[],
+                bullets: &bullets.states,
+            };
+            let link = refs.link(BulletId::Bullet(parent));
+            bullets.states[child].borrow_mut().parent = link;
+            let link = refs.link(BulletId::Bullet(child));
+            bullets.states[parent].borrow_mut().child = link;
+        }
+
+        let refs = Refs {
+            player: &player,
+            enemies: &[],
+            bullets: &bullets.states,
+        };
+        let mut state = bullets.states[parent].borrow_mut();
+        let own = BulletId::Bullet(parent);
+        assert_eq!(
+            refs.read(&state, own, BulletId::Child, &StateId::PosX),
+            Data::Float(20.0)
+        );
+        refs.write(
+            &mut state,
+            own,
+            BulletId::Child,
+            &StateId::PosX,
+            Data::Float(30.0),
+        );
+        assert_eq!(bullets.states[child].borrow().pos.x, 30.0);
+        // the parent was not fired by anything
+        assert_eq!(
+            refs.read(&state, own, BulletId::Parent, &StateId::Enabled),
+            Data::Bool(false)
+        );
+        drop(state);
+
+        let state = bullets.states[child].borrow();
+        assert_eq!(
+            refs.read(
+                &state,
+                BulletId::Bullet(child),
+                BulletId::Parent,
+                &StateId::PosX
+            ),
+            Data::Float(10.0)
+        );
+    }
+
+    #[test]
+    fn test_dead_links() {
+        let player = RefCell::new(Bullet::new(
+            0.0,
+            0.0,
+            BulletType::Player,
+            BulletColor::White,
+            Rc::new(BulletCode::new("")),
+        ));
+        let mut bullets = BulletSet::new();
+        let parent = fire(&mut bullets, 10.0);
+        let child = fire(&mut bullets, 20.0);
+        let link = bullets.states[child].borrow().generation;
+        bullets.states[parent].borrow_mut().child = Some(Link {
+            id: BulletId::Bullet(child),
+            generation: link,
+        });
+
+        // the child died and another bullet took its slot
+        bullets.kill(child);
+        assert_eq!(fire(&mut bullets, 40.0), child);
+
+        let refs = Refs {
+            player: &player,
+            enemies: &[],
+            bullets: &bullets.states,
+        };
+        let mut state = bullets.states[parent].borrow_mut();
+        let own = BulletId::Bullet(parent);
+        assert_eq!(
+            refs.read(&state, own, BulletId::Child, &StateId::Enabled),
+            Data::Bool(false)
+        );
+        assert_eq!(
+            refs.read(&state, own, BulletId::Child, &StateId::PosX),
+            Data::Float(0.0)
+        );
+        refs.write(
+            &mut state,
+            own,
+            BulletId::Child,
+            &StateId::PosX,
+            Data::Float(30.0),
+        );
+        assert_eq!(bullets.states[child].borrow().pos.x, 40.0);
+
+        // bullets in pools are still reachable by their indices while enabled
+        assert_eq!(
+            refs.read(&state, own, BulletId::Bullet(child), &StateId::PosX),
+            Data::Float(40.0)
+        );
+        assert_eq!(
+            refs.read(&state, own, BulletId::Bullet(4000), &StateId::PosX),
+            Data::Float(0.0)
+        );
+    }
+}
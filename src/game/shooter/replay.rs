@@ -0,0 +1,250 @@
+use std::{
+    fmt,
+    io::{self, Read, Write},
+};
+
+use super::{bullet::InputState, shooter::Input};
+
+// Inputs in the order of bits in replays.
+pub const INPUTS: [Input; 7] = [
+    Input::Up,
+    Input::Down,
+    Input::Left,
+    Input::Right,
+    Input::Shot,
+    Input::Slow,
+    Input::Bomb,
+];
+
+pub fn is_held(state: &InputState, input: Input) -> bool {
+    match input {
+        Input::Up => state.up,
+        Input::Down => state.down,
+        Input::Left => state.left,
+        Input::Right => state.right,
+        Input::Shot => state.shot,
+        Input::Slow => state.slow,
+        Input::Bomb => state.bomb,
+    }
+}
+
+pub fn input_bits(state: &InputState) -> u8 {
+    INPUTS
+        .iter()
+        .enumerate()
+        .filter(|(_, input)| is_held(state, **input))
+        .fold(0, |bits, (n, _)| bits | 1 << n)
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(io::Error),
+    NotAReplay,
+    UnsupportedVersion(u8),
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayError::Io(err) => write!(f, "cannot read replay: {}", err),
+            ReplayError::NotAReplay => write!(f, "not a replay file"),
+            ReplayError::UnsupportedVersion(v) => write!(f, "unsupported replay version {}", v),
+        }
+    }
+}
+
+impl From<io::Error> for ReplayError {
+    fn from(err: io::Error) -> Self {
+        ReplayError::Io(err)
+    }
+}
+
+// Inputs of the player in a stage with what is needed to reproduce it.
+//
+// Inputs are kept as runs of the same bits, and checksums of the state are kept every
+// `CHECK_INTERVAL` frames so that playback can tell where it diverged.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Replay {
+    pub seed: u64,
+    pub scripts_hash: u64,
+    frames: u32,
+    // (frames, bits)
+    inputs: Vec<(u32, u8)>,
+    checksums: Vec<u64>,
+}
+
+impl Replay {
+    pub const CHECK_INTERVAL: u32 = 60;
+    const MAGIC: &'static [u8; 4] = b"BLRP";
+    const VERSION: u8 = 1;
+
+    pub fn new(seed: u64, scripts_hash: u64) -> Self {
+        Self {
+            seed,
+            scripts_hash,
+            frames: 0,
+            inputs: Vec::new(),
+            checksums: Vec::new(),
+        }
+    }
+
+    pub fn frames(&self) -> u32 {
+        self.frames
+    }
+
+    // Records input bits of a frame.
+    pub fn record(&mut self, bits: u8) {
+        match self.inputs.last_mut() {
+            Some((frames, last)) if *last == bits && *frames < u32::MAX => *frames += 1,
+            _ => self.inputs.push((1, bits)),
+        }
+        self.frames += 1;
+    }
+
+    // Whether the checksum of the state after the last frame is to be recorded, which is
+    // at every `CHECK_INTERVAL` frames.
+    pub fn checksum_due(&self) -> bool {
+        self.checksums.len() < (self.frames / Self::CHECK_INTERVAL) as usize
+    }
+
+    pub fn record_checksum(&mut self, checksum: u64) {
+        self.checksums.push(checksum);
+    }
+
+    // Returns input bits of each frame.
+    pub fn inputs(&self) -> impl Iterator<Item = u8> + '_ {
+        self.inputs
+            .iter()
+            .flat_map(|(frames, bits)| (0..*frames).map(move |_| *bits))
+    }
+
+    // Returns the checksum after the frame `frame` from 0, if it was recorded.
+    pub fn checksum_at(&self, frame: u32) -> Option<u64> {
+        let n = (frame + 1) / Self::CHECK_INTERVAL;
+        if n == 0 || n * Self::CHECK_INTERVAL != frame + 1 {
+            return None;
+        }
+        self.checksums.get(n as usize - 1).copied()
+    }
+
+    // All numbers are written in little endian.
+    pub fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(Self::MAGIC)?;
+        w.write_all(&[Self::VERSION])?;
+        w.write_all(&self.seed.to_le_bytes())?;
+        w.write_all(&self.scripts_hash.to_le_bytes())?;
+        w.write_all(&self.frames.to_le_bytes())?;
+
+        w.write_all(&(self.inputs.len() as u32).to_le_bytes())?;
+        for (frames, bits) in self.inputs.iter() {
+            w.write_all(&frames.to_le_bytes())?;
+            w.write_all(&[*bits])?;
+        }
+
+        w.write_all(&(self.checksums.len() as u32).to_le_bytes())?;
+        for checksum in self.checksums.iter() {
+            w.write_all(&checksum.to_le_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    pub fn read<R: Read>(r: &mut R) -> Result<Self, ReplayError> {
+        let mut magic = [0; 4];
+        r.read_exact(&mut magic)?;
+        if &magic != Self::MAGIC {
+            return Err(ReplayError::NotAReplay);
+        }
+        let version = read_u8(r)?;
+        if version != Self::VERSION {
+            return Err(ReplayError::UnsupportedVersion(version));
+        }
+
+        let seed = read_u64(r)?;
+        let scripts_hash = read_u64(r)?;
+        let frames = read_u32(r)?;
+
+        let mut inputs = Vec::new();
+        for _ in 0..read_u32(r)? {
+            inputs.push((read_u32(r)?, read_u8(r)?));
+        }
+
+        let mut checksums = Vec::new();
+        for _ in 0..read_u32(r)? {
+            checksums.push(read_u64(r)?);
+        }
+
+        Ok(Self {
+            seed,
+            scripts_hash,
+            frames,
+            inputs,
+            checksums,
+        })
+    }
+}
+
+fn read_u8<R: Read>(r: &mut R) -> io::Result<u8> {
+    let mut buf = [0; 1];
+    r.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_u32<R: Read>(r: &mut R) -> io::Result<u32> {
+    let mut buf = [0; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64<R: Read>(r: &mut R) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+#[cfg(test)]
+mod replay_test {
+    use super::*;
+
+    #[test]
+    fn test_input_bits() {
+        let state = InputState {
+            left: true,
+            shot: true,
+            ..InputState::default()
+        };
+        assert_eq!(input_bits(&state), 0b10100);
+    }
+
+    #[test]
+    fn test_record_and_read() {
+        let mut replay = Replay::new(42, 0x1234);
+        for frame in 0..150 {
+            let bits = if frame < 100 { 0 } else { 0b1000 };
+            replay.record(bits);
+            if replay.checksum_due() {
+                replay.record_checksum(frame as u64);
+            }
+        }
+        assert_eq!(replay.inputs.len(), 2);
+        assert_eq!(replay.inputs().count(), 150);
+        assert_eq!(replay.inputs().nth(100), Some(0b1000));
+        assert_eq!(replay.checksum_at(58), None);
+        assert_eq!(replay.checksum_at(59), Some(59));
+        assert_eq!(replay.checksum_at(119), Some(119));
+        assert_eq!(replay.checksum_at(179), None);
+
+        let mut buf = Vec::new();
+        replay.write(&mut buf).unwrap();
+        assert_eq!(Replay::read(&mut buf.as_slice()).unwrap(), replay);
+
+        assert!(matches!(
+            Replay::read(&mut &b"RIFF...."[..]),
+            Err(ReplayError::NotAReplay)
+        ));
+        assert!(matches!(
+            Replay::read(&mut &buf[..10]),
+            Err(ReplayError::Io(_))
+        ));
+    }
+}
@@ -0,0 +1,80 @@
+use super::snapshot::{Reader, SnapshotError, Writer};
+
+// A xorshift PRNG owned by each stage, to make random patterns reproducible by the seed.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // scramble the seed with splitmix64 since xorshift cannot start with zero
+        let mut z = seed.wrapping_add(0x9e3779b97f4a7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^= z >> 31;
+
+        Self { state: z.max(1) }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.state = x;
+
+        x
+    }
+
+    #[cfg_attr(not(feature = "graphics"), allow(dead_code))]
+    pub fn save(&self, w: &mut Writer) {
+        w.u64(self.state);
+    }
+
+    #[cfg_attr(not(feature = "graphics"), allow(dead_code))]
+    pub fn restore(r: &mut Reader) -> Result<Self, SnapshotError> {
+        match r.u64()? {
+            0 => Err(r.invalid("random state is zero")),
+            state => Ok(Self { state }),
+        }
+    }
+
+    // Returns a float in [0, 1).
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+#[cfg(test)]
+mod rng_test {
+    use super::*;
+
+    #[test]
+    fn test_next_f32_range() {
+        let mut rng = Rng::new(42);
+        for _ in 0..100000 {
+            let f = rng.next_f32();
+            assert!((0.0..1.0).contains(&f), "{} is out of [0, 1)", f);
+        }
+    }
+
+    #[test]
+    fn test_same_seed_same_sequence() {
+        let (mut a, mut b, mut c) = (Rng::new(7), Rng::new(7), Rng::new(8));
+        let a: Vec<u64> = (0..100).map(|_| a.next_u64()).collect();
+        let b: Vec<u64> = (0..100).map(|_| b.next_u64()).collect();
+        let c: Vec<u64> = (0..100).map(|_| c.next_u64()).collect();
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+    }
+
+    #[test]
+    fn test_seed_zero() {
+        let mut rng = Rng::new(0);
+        let values: Vec<u64> = (0..10).map(|_| rng.next_u64()).collect();
+        // xorshift stays at 0 forever if it starts with 0
+        assert!(values.iter().all(|n| *n != 0));
+        assert_ne!(values[0], values[1]);
+    }
+}
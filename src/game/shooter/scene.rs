@@ -0,0 +1,197 @@
+use std::{fs::File, io::BufWriter, path::PathBuf};
+
+use ggez::{
+    event::EventHandler,
+    graphics::{
+        self,
+        // BlendMode, Canvas,
+        Color,
+        DrawMode,
+        DrawParam,
+        Rect,
+    },
+    input::keyboard::{KeyCode, KeyInput, KeyMods},
+    Context, GameError, GameResult,
+};
+use glam;
+
+use crate::{constant, game::Scene};
+
+use super::{
+    bullet_codes::BulletCodes,
+    practice::Practice,
+    shooter::{Input, Shooter},
+};
+
+pub trait SceneDrawable {
+    fn draw(&self, ctx: &mut Context, canvas: &mut graphics::Canvas) -> GameResult<()>;
+}
+
+pub struct ShooterScene {
+    shooter: Shooter,
+    // the replay is written here at quit
+    record: Option<PathBuf>,
+    // the stage saved with F5, which F9 goes back to
+    quick_save: Option<Vec<u8>>,
+    // controls to inspect patterns, only with `--practice`
+    practice: Option<Practice>,
+}
+
+impl ShooterScene {
+    pub fn new(bullet_codes: BulletCodes, record: Option<PathBuf>, practice: bool) -> Self {
+        Self {
+            shooter: Shooter::new(bullet_codes, constant::RANDOM_SEED),
+            record,
+            quick_save: None,
+            practice: if practice {
+                Some(Practice::new())
+            } else {
+                None
+            },
+        }
+    }
+}
+
+impl Scene for ShooterScene {
+    fn next(&self) -> Box<dyn Scene> {
+        Box::new(ShooterScene::new(
+            self.shooter.bullet_codes.clone(),
+            self.record.clone(),
+            self.practice.is_some(),
+        ))
+    }
+}
+
+impl EventHandler for ShooterScene {
+    fn key_down_event(
+        &mut self,
+        _ctx: &mut Context,
+        input: KeyInput,
+        repeat: bool,
+    ) -> GameResult<()> {
+        match input.keycode {
+            Some(KeyCode::Up) => self.shooter.input(&Input::Up, true),
+            Some(KeyCode::Down) => self.shooter.input(&Input::Down, true),
+            Some(KeyCode::Left) => self.shooter.input(&Input::Left, true),
+            Some(KeyCode::Right) => self.shooter.input(&Input::Right, true),
+            Some(KeyCode::Z) => self.shooter.input(&Input::Shot, true),
+            Some(KeyCode::X) => self.shooter.input(&Input::Bomb, true),
+            Some(KeyCode::F5) => self.quick_save = Some(self.shooter.snapshot()),
+            Some(KeyCode::F9) => {
+                if let Some(snapshot) = &self.quick_save {
+                    self.shooter
+                        .restore(snapshot)
+                        .map_err(|err| GameError::CustomError(err.to_string()))?;
+                }
+            }
+            _ => (),
+        }
+        if let Some(practice) = &mut self.practice {
+            match input.keycode {
+                Some(KeyCode::P) if !repeat => practice.toggle_pause(),
+                Some(KeyCode::N) => practice.step(),
+                Some(KeyCode::S) if !repeat => practice.cycle_speed(),
+                Some(KeyCode::R) => practice.set_rewinding(true),
+                _ => (),
+            }
+        }
+        match input.mods {
+            KeyMods::SHIFT => self.shooter.input(&Input::Slow, true),
+            _ => (),
+        }
+
+        Ok(())
+    }
+
+    fn key_up_event(&mut self, _ctx: &mut Context, input: KeyInput) -> GameResult<()> {
+        match input.keycode {
+            Some(KeyCode::Up) => self.shooter.input(&Input::Up, false),
+            Some(KeyCode::Down) => self.shooter.input(&Input::Down, false),
+            Some(KeyCode::Left) => self.shooter.input(&Input::Left, false),
+            Some(KeyCode::Right) => self.shooter.input(&Input::Right, false),
+            Some(KeyCode::Z) => self.shooter.input(&Input::Shot, false),
+            Some(KeyCode::X) => self.shooter.input(&Input::Bomb, false),
+            Some(KeyCode::R) => {
+                if let Some(practice) = &mut self.practice {
+                    practice.set_rewinding(false);
+                }
+            }
+            _ => (),
+        }
+        match input.mods {
+            KeyMods::SHIFT => self.shooter.input(&Input::Slow, false),
+            _ => (),
+        }
+
+        Ok(())
+    }
+
+    fn update(&mut self, _ctx: &mut Context) -> GameResult<()> {
+        match &mut self.practice {
+            Some(practice) => practice.update(&mut self.shooter),
+            None => self.shooter.update(),
+        }
+        .map_err(|err| GameError::CustomError(err.to_string()))?;
+
+        Ok(())
+    }
+
+    fn quit_event(&mut self, _ctx: &mut Context) -> GameResult<bool> {
+        if let Some(path) = &self.record {
+            let mut w = BufWriter::new(File::create(path)?);
+            self.shooter.replay().write(&mut w)?;
+        }
+
+        Ok(false)
+    }
+
+    fn draw(&mut self, ctx: &mut Context) -> GameResult<()> {
+        let mut canvas = graphics::Canvas::from_frame(
+            ctx,
+            graphics::CanvasLoadOp::Clear([0.0, 0.1, 0.1, 1.0].into()),
+        );
+
+        static BG_RECT: Rect = Rect::new(0.0, 0.0, constant::WIDTH, constant::HEIGHT);
+        static BG_COLOR: Color = Color::new(0.0, 0.1, 0.1, 1.0);
+        let mut mb = graphics::MeshBuilder::new();
+        let bg = mb.rectangle(DrawMode::fill(), BG_RECT, BG_COLOR)?;
+        let bg = graphics::Mesh::from_data(ctx, bg.build());
+        canvas.draw(&bg, DrawParam::default().dest(glam::vec2(0.0, 0.0)));
+
+        static SHOOTER_AREA_RECT: Rect = Rect::new(
+            constant::SHOOTER_OFFSET_X,
+            constant::SHOOTER_OFFSET_Y,
+            constant::SHOOTER_WIDTH,
+            constant::SHOOTER_HEIGHT,
+        );
+        static SHOOTER_AREA_COLOR: Color = Color::new(0.0, 0.07, 0.1, 0.98);
+        static SHOOTER_BORDER_COLOR: Color = Color::new(0.0, 0.6, 0.8, 1.0);
+        let mut mb = graphics::MeshBuilder::new();
+        let shooter_area = mb.rectangle(DrawMode::fill(), SHOOTER_AREA_RECT, SHOOTER_AREA_COLOR)?;
+        let shooter_area = graphics::Mesh::from_data(ctx, shooter_area.build());
+        let mut mb = graphics::MeshBuilder::new();
+        let shooter_border = mb.rectangle(
+            DrawMode::stroke(1.0),
+            SHOOTER_AREA_RECT,
+            SHOOTER_BORDER_COLOR,
+        )?;
+        let shooter_border = graphics::Mesh::from_data(ctx, shooter_border.build());
+        canvas.draw(&shooter_area, DrawParam::default());
+        canvas.draw(&shooter_border, DrawParam::default());
+
+        self.shooter.draw(ctx, &mut canvas)?;
+
+        if let Some(status) = self.practice.as_ref().and_then(Practice::status) {
+            let text = graphics::Text::new(status);
+            let param = DrawParam::default().dest(glam::vec2(
+                constant::SHOOTER_OFFSET_X + 10.0,
+                constant::SHOOTER_OFFSET_Y + constant::SHOOTER_HEIGHT - 30.0,
+            ));
+            canvas.draw(&text, param);
+        }
+
+        canvas.finish(ctx)?;
+
+        Ok(())
+    }
+}
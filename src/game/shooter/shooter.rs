@@ -1,46 +1,130 @@
-use std::{cell::RefCell, collections::VecDeque, rc::Rc};
+use std::{cell::RefCell, collections::VecDeque, fmt, rc::Rc};
 
+#[cfg(feature = "graphics")]
 use ggez::{graphics, Context, GameResult};
 
 use lang_compiler::BulletCode;
 use lang_component::{
-    bullet::{BulletColor, BulletId, BulletType},
+    bullet::{BulletColor, BulletId, BulletType, StateIO, StateId},
     vm::{Data, OperationQuery},
 };
+use lang_vm::RuntimeError;
 
-use super::{bullet::Bullet, bullet_codes::BulletCodes, bullet_pool::BulletSet, SceneDrawable};
+#[cfg(feature = "graphics")]
+use super::scene::SceneDrawable;
+#[cfg(feature = "graphics")]
+use crate::constant;
+
+use super::{
+    bullet::{Bullet, Motion},
+    bullet_codes::BulletCodes,
+    bullet_pool::BulletSet,
+    collision,
+    enemy_pool::EnemySet,
+    grid::Grid,
+    hash::Fnv1a,
+    refs::Refs,
+    replay::{self, Replay},
+    rng::Rng,
+    snapshot::{Reader, SnapshotError, Writer},
+};
 
 pub struct Objects {
     pub player: Rc<RefCell<Bullet>>,
     pub bullets: BulletSet,
+    pub enemies: EnemySet,
 }
 
 impl Objects {
     fn new(bullet_codes: &BulletCodes) -> Self {
         let bc = bullet_codes.by_name.get("player").unwrap();
-        let player = Bullet::new(
-            200.0,
-            400.0,
-            BulletType::Player,
-            BulletColor::White,
-            bc.clone(),
-        );
+        let (x, y) = Shooter::PLAYER_START;
+        let player = Bullet::new(x, y, BulletType::Player, BulletColor::White, bc.clone());
         let player = Rc::new(RefCell::new(player));
 
         Self {
             player,
             bullets: BulletSet::new(),
+            enemies: EnemySet::new(),
+        }
+    }
+
+    pub fn refs(&self) -> Refs<'_> {
+        Refs {
+            player: &self.player,
+            enemies: &self.enemies.states,
+            bullets: &self.bullets.states,
+        }
+    }
+}
+
+// Counters of the stage, which scripts read as `frames`, `score`, `graze`, `lives` and
+// `bombs` of any bullets.
+#[derive(Debug, Default)]
+pub struct Counters {
+    // frames since the stage started
+    pub frames: u32,
+    pub score: u32,
+    pub graze: u32,
+    pub lives: u32,
+    pub bombs: u32,
+}
+
+impl StateIO for Counters {
+    fn read(&self, _bid: &BulletId, sid: &StateId) -> Data {
+        match sid {
+            StateId::Frames => Data::Float(self.frames as f32),
+            StateId::Score => Data::Float(self.score as f32),
+            StateId::Graze => Data::Float(self.graze as f32),
+            StateId::Lives => Data::Float(self.lives as f32),
+            StateId::Bombs => Data::Float(self.bombs as f32),
+            _ => unreachable!("{:?} is a state of bullets", sid),
         }
     }
+
+    // counters are read-only
+    fn write(&mut self, _bid: &BulletId, _sid: &StateId, _d: Data) {}
+}
+
+// Errors while running the stage, which does not need a window.
+#[derive(Debug)]
+pub enum ShooterError {
+    // a script failed at runtime
+    Script(RuntimeError),
+    Snapshot(SnapshotError),
+}
+
+impl fmt::Display for ShooterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShooterError::Script(err) => write!(f, "script error: {:?}", err),
+            ShooterError::Snapshot(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl From<SnapshotError> for ShooterError {
+    fn from(err: SnapshotError) -> Self {
+        ShooterError::Snapshot(err)
+    }
 }
 
 pub struct Shooter {
     objects: Objects,
-    bullet_codes: BulletCodes,
+    pub bullet_codes: BulletCodes,
     op_queue: VecDeque<OperationQuery>,
+    rng: Rng,
+    counters: Counters,
+    grid: Grid,
+    // frames left until the player can be hit again
+    invulnerable: u32,
+    // to use a bomb once per press
+    last_bomb: bool,
+    // inputs recorded since the stage started
+    replay: Replay,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Input {
     Up,
     Down,
@@ -48,6 +132,7 @@ pub enum Input {
     Right,
     Shot,
     Slow,
+    Bomb,
 }
 
 pub trait OperationProcessor {
@@ -59,19 +144,38 @@ pub trait OperationProcessor {
         color: BulletColor,
         params: Vec<Data>,
         bullet_code: Rc<BulletCode>,
-    ) -> bool;
+    ) -> Option<usize>;
 
     fn kill(&mut self, id: usize);
 }
 
 impl Shooter {
-    pub fn new() -> Self {
-        let bullet_codes = BulletCodes::compile_codes();
+    const GRAZE_SCORE: u32 = 10;
+    const ITEM_SCORE: u32 = 100;
+    const ENEMY_SCORE: u32 = 1000;
+    const PLAYER_LIVES: u32 = 3;
+    const PLAYER_BOMBS: u32 = 3;
+    const PLAYER_START: (f32, f32) = (200.0, 400.0);
+    const RESPAWN_INVULNERABLE_FRAMES: u32 = 120;
+    const BOMB_INVULNERABLE_FRAMES: u32 = 60;
 
+    // `seed` is for `rand()` in scripts. Stages with the same seed run the same patterns.
+    pub fn new(bullet_codes: BulletCodes, seed: u64) -> Self {
+        let replay = Replay::new(seed, bullet_codes.hash);
         Self {
             objects: Objects::new(&bullet_codes),
             bullet_codes,
             op_queue: VecDeque::new(),
+            rng: Rng::new(seed),
+            counters: Counters {
+                lives: Self::PLAYER_LIVES,
+                bombs: Self::PLAYER_BOMBS,
+                ..Counters::default()
+            },
+            grid: Grid::new(),
+            invulnerable: 0,
+            last_bomb: false,
+            replay,
         }
     }
 
@@ -84,7 +188,131 @@ impl Shooter {
             Input::Right => player.input.right = b,
             Input::Shot => player.input.shot = b,
             Input::Slow => player.input.slow = b,
+            Input::Bomb => player.input.bomb = b,
+        }
+    }
+
+    pub fn is_game_over(&self) -> bool {
+        self.counters.lives == 0
+    }
+
+    pub fn objects(&self) -> &Objects {
+        &self.objects
+    }
+
+    pub fn counters(&self) -> &Counters {
+        &self.counters
+    }
+
+    pub fn replay(&self) -> &Replay {
+        &self.replay
+    }
+
+    // Hashes the state to check that replays reproduce the stage.
+    pub fn checksum(&self) -> u64 {
+        let mut hasher = Fnv1a::new();
+        let counters = &self.counters;
+        for n in [
+            counters.frames,
+            counters.score,
+            counters.graze,
+            counters.lives,
+            counters.bombs,
+        ] {
+            hasher.write(&n.to_le_bytes());
         }
+
+        let objects = &self.objects;
+        let pools = objects.enemies.states.iter();
+        let pools = pools.chain(objects.bullets.states.iter());
+        for (n, state) in std::iter::once(&objects.player).chain(pools).enumerate() {
+            let state = state.borrow();
+            // the player at 0 is always there
+            if n > 0 && !state.enabled {
+                continue;
+            }
+
+            hasher.write(&(n as u32).to_le_bytes());
+            hasher.write(&state.pos.x.to_le_bytes());
+            hasher.write(&state.pos.y.to_le_bytes());
+            hasher.write(&state.age.to_le_bytes());
+        }
+
+        hasher.finish()
+    }
+
+    // Saves the whole stage into bytes, including suspended scripts, pending operations
+    // and the replay so far.
+    #[cfg_attr(not(feature = "graphics"), allow(dead_code))]
+    pub fn snapshot(&self) -> Vec<u8> {
+        let mut w = Writer::new(&self.bullet_codes);
+
+        let counters = &self.counters;
+        for n in [
+            counters.frames,
+            counters.score,
+            counters.graze,
+            counters.lives,
+            counters.bombs,
+        ] {
+            w.u32(n);
+        }
+        self.rng.save(&mut w);
+        w.u32(self.invulnerable);
+        w.bool(self.last_bomb);
+
+        w.usize(self.op_queue.len());
+        for op in self.op_queue.iter() {
+            w.operation(op);
+        }
+        self.objects.player.borrow().save(&mut w);
+        self.objects.enemies.save(&mut w);
+        self.objects.bullets.save(&mut w);
+        w.replay(&self.replay);
+
+        w.finish()
+    }
+
+    // Restores the stage saved by `snapshot()` with the same scripts. The stage is left
+    // as it is if the snapshot is broken. The keys held now are kept, not the saved ones,
+    // since no key event comes for keys which stayed held or released over the restore.
+    #[cfg_attr(not(feature = "graphics"), allow(dead_code))]
+    pub fn restore(&mut self, bytes: &[u8]) -> Result<(), SnapshotError> {
+        let mut r = Reader::new(bytes, &self.bullet_codes)?;
+
+        let counters = Counters {
+            frames: r.u32()?,
+            score: r.u32()?,
+            graze: r.u32()?,
+            lives: r.u32()?,
+            bombs: r.u32()?,
+        };
+        let rng = Rng::restore(&mut r)?;
+        let invulnerable = r.u32()?;
+        let last_bomb = r.bool()?;
+
+        let mut op_queue = VecDeque::new();
+        for _ in 0..r.usize()? {
+            op_queue.push_back(r.operation()?);
+        }
+        let mut objects = Objects::new(&self.bullet_codes);
+        objects.player.borrow_mut().restore(&mut r)?;
+        objects.enemies.restore(&mut r)?;
+        objects.bullets.restore(&mut r)?;
+        let replay = r.replay()?;
+        r.finish()?;
+
+        let input = std::mem::take(&mut self.objects.player.borrow_mut().input);
+        objects.player.borrow_mut().input = input;
+        self.counters = counters;
+        self.rng = rng;
+        self.invulnerable = invulnerable;
+        self.last_bomb = last_bomb;
+        self.op_queue = op_queue;
+        self.objects = objects;
+        self.replay = replay;
+
+        Ok(())
     }
 }
 
@@ -97,7 +325,7 @@ impl OperationProcessor for Shooter {
         color: BulletColor,
         params: Vec<Data>,
         bullet_code: Rc<BulletCode>,
-    ) -> bool {
+    ) -> Option<usize> {
         self.objects
             .bullets
             .fire(x, y, r#type, color, params, bullet_code)
@@ -108,43 +336,396 @@ impl OperationProcessor for Shooter {
     }
 }
 
+// Objects whose scripts queued operations, which decides what `die()` kills.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Runner {
+    Player,
+    Enemies,
+    Bullets,
+}
+
+impl Runner {
+    fn id(&self, idx: usize) -> BulletId {
+        match self {
+            Runner::Player => BulletId::Player,
+            Runner::Enemies => BulletId::Enemy(idx),
+            Runner::Bullets => BulletId::Bullet(idx),
+        }
+    }
+}
+
 impl Shooter {
-    pub fn update(&mut self, _ctx: &mut Context) -> GameResult<()> {
+    // Records links between `parent` and `child`, which was just fired or spawned.
+    fn link(&self, parent: BulletId, child: BulletId) {
+        let refs = self.objects.refs();
+        let (parent_link, child_link) = (refs.link(parent), refs.link(child));
+
+        if let (Some(parent), Some(child)) = (refs.cell(parent), refs.cell(child)) {
+            parent.borrow_mut().child = child_link;
+            child.borrow_mut().parent = parent_link;
+        }
+    }
+
+    // Bullets fired by the player are its shots, and the player does not die by `die()`.
+    fn process_operations(&mut self, runner: Runner) {
+        while let Some(op) = self.op_queue.pop_back() {
+            match op {
+                OperationQuery::Fire(bullet_id, (x, y), r#type, color, params, idx) => {
+                    let bc = self.bullet_codes.by_id[bullet_id].clone();
+                    let r#type = if runner == Runner::Player {
+                        BulletType::PlayerShot
+                    } else {
+                        r#type
+                    };
+                    if let Some(child) = self.fire(x, y, r#type, color, params, bc) {
+                        self.link(runner.id(idx), BulletId::Bullet(child));
+                    }
+                }
+                OperationQuery::Spawn(bullet_id, (x, y), params, idx) => {
+                    let bc = self.bullet_codes.by_id[bullet_id].clone();
+                    if let Some(child) = self.objects.enemies.spawn(x, y, params, bc) {
+                        self.link(runner.id(idx), BulletId::Enemy(child));
+                    }
+                }
+                OperationQuery::Die(id) => match runner {
+                    Runner::Player => (),
+                    Runner::Enemies => self.objects.enemies.kill(id),
+                    Runner::Bullets => self.kill(id),
+                },
+            }
+        }
+    }
+
+    // Clears enemy bullets into items when the bomb key is pressed.
+    fn use_bomb(&mut self) {
+        let bomb = self.objects.player.borrow().input.bomb;
+        if bomb && !self.last_bomb && self.counters.bombs > 0 {
+            self.counters.bombs -= 1;
+            self.invulnerable = self.invulnerable.max(Self::BOMB_INVULNERABLE_FRAMES);
+            self.objects.bullets.clear_to_items();
+        }
+        self.last_bomb = bomb;
+    }
+
+    // Takes a life from the player and puts it back to the start position.
+    fn miss(&mut self) {
+        self.counters.lives -= 1;
+        if self.is_game_over() {
+            return;
+        }
+
+        let mut player = self.objects.player.borrow_mut();
+        let (x, y) = Self::PLAYER_START;
+        player.pos = glam::vec2(x, y);
+        player.motion = Motion::default();
+        self.invulnerable = Self::RESPAWN_INVULNERABLE_FRAMES;
+    }
+
+    // Steps the stage a frame and records inputs of the frame into the replay.
+    // It does not need a window so that stages can run headless.
+    pub fn update(&mut self) -> Result<(), ShooterError> {
+        let bits = replay::input_bits(&self.objects.player.borrow().input);
+        self.step()?;
+
+        self.replay.record(bits);
+        if self.replay.checksum_due() {
+            let checksum = self.checksum();
+            self.replay.record_checksum(checksum);
+        }
+
+        Ok(())
+    }
+
+    fn step(&mut self) -> Result<(), ShooterError> {
+        if self.is_game_over() {
+            return Ok(());
+        }
+
+        self.invulnerable = self.invulnerable.saturating_sub(1);
+        self.use_bomb();
+
         {
+            let refs = self.objects.refs();
             let mut player = self.objects.player.borrow_mut();
-            player.update(BulletId::Player, &self.objects, &mut self.op_queue)?;
+            // the player is alone so its index is always 0
+            player.update(
+                0,
+                BulletId::Player,
+                &refs,
+                &mut self.op_queue,
+                &mut self.rng,
+                &self.counters,
+            )?;
+        }
+        self.process_operations(Runner::Player);
+        {
+            let player = self.objects.player.clone();
+            self.objects.enemies.update(
+                player,
+                &self.objects.bullets,
+                &mut self.op_queue,
+                &mut self.rng,
+                &self.counters,
+            )?;
         }
+        self.process_operations(Runner::Enemies);
         {
             let player = self.objects.player.clone();
-            self.objects.bullets.update(player, &mut self.op_queue)?;
+            self.objects.bullets.update(
+                player,
+                &self.objects.enemies,
+                &mut self.op_queue,
+                &mut self.rng,
+                &self.counters,
+            )?;
         }
+        self.process_operations(Runner::Bullets);
 
-        loop {
-            if let Some(op) = self.op_queue.pop_back() {
-                match op {
-                    OperationQuery::Fire(bullet_id, (x, y), r#type, color, params) => {
-                        let bc = self.bullet_codes.by_id[bullet_id].clone();
-                        self.fire(x, y, r#type, color, params, bc);
-                    }
-                    OperationQuery::Die(bullet_id) => {
-                        self.kill(bullet_id);
-                    }
-                }
-            } else {
-                break;
+        let collisions = {
+            let mut player = self.objects.player.borrow_mut();
+            collision::check_collisions(
+                &mut player,
+                &self.objects.bullets,
+                &mut self.grid,
+                self.invulnerable > 0,
+            )
+        };
+        self.counters.graze += collisions.grazes;
+        self.counters.score += collisions.grazes * Self::GRAZE_SCORE;
+        for idx in collisions.items {
+            self.kill(idx);
+            self.counters.score += Self::ITEM_SCORE;
+        }
+
+        for idx in collision::check_shots(&self.objects.enemies, &self.objects.bullets, &self.grid)
+        {
+            self.kill(idx);
+        }
+        for idx in 0..EnemySet::ENEMY_MAX {
+            let defeated = {
+                let enemy = self.objects.enemies.states[idx].borrow();
+                enemy.enabled && enemy.hp <= 0.0
+            };
+            if defeated {
+                self.objects.enemies.kill(idx);
+                self.counters.score += Self::ENEMY_SCORE;
             }
         }
 
+        let hit = self.objects.player.borrow().hit;
+        if hit {
+            self.miss();
+        }
+        self.counters.frames += 1;
+
         Ok(())
     }
 
+    #[cfg(feature = "graphics")]
     pub fn draw(&mut self, ctx: &mut Context, canvas: &mut graphics::Canvas) -> GameResult<()> {
-        {
+        // the player blinks while invulnerable
+        if self.invulnerable & 4 == 0 && !self.is_game_over() {
             let player = self.objects.player.borrow();
             player.draw(ctx, canvas)?;
         }
-        self.objects.bullets.draw(ctx, canvas)?;
+        self.objects.enemies.draw(ctx, canvas)?;
+        self.objects.bullets.draw(ctx, canvas, &self.counters)?;
+
+        if self.is_game_over() {
+            let text = graphics::Text::new("game over");
+            let param = graphics::DrawParam::default().dest(glam::vec2(
+                constant::SHOOTER_OFFSET_X + constant::SHOOTER_WIDTH / 2.0 - 40.0,
+                constant::SHOOTER_OFFSET_Y + constant::SHOOTER_HEIGHT / 2.0,
+            ));
+            canvas.draw(&text, param);
+        }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod shooter_test {
+    use super::*;
+    use crate::game::shooter::bullet::InputState;
+    use crate::game::shooter::bullet_codes::load_scripts;
+
+    // The player moves right every frame, and an enemy keeps firing bullets at it.
+    fn shooter(name: &str) -> Shooter {
+        let scripts = [
+            (
+                "player",
+                r##"
+                proc main() {
+                  self.x = self.x + 1
+                  if self.frames == 0 {
+                    spawn("enemy", 0, 0)
+                  }
+                }
+                "##,
+            ),
+            (
+                "enemy",
+                r##"
+                proc main() {
+                  fire("aimed", player.x, player.y)
+                }
+                "##,
+            ),
+            (
+                "aimed",
+                r##"
+                proc main() {
+                  if self.age > 0 {
+                    die()
+                  }
+                }
+                "##,
+            ),
+        ];
+        Shooter::new(load_scripts(name, &scripts), 1)
+    }
+
+    #[test]
+    fn test_miss_respawn_and_game_over() {
+        let mut shooter = shooter("miss");
+        shooter.update().unwrap();
+        assert_eq!(shooter.counters.lives, Shooter::PLAYER_LIVES - 1);
+        assert_eq!(shooter.invulnerable, Shooter::RESPAWN_INVULNERABLE_FRAMES);
+        let (x, y) = Shooter::PLAYER_START;
+        assert_eq!(shooter.objects.player.borrow().pos, glam::vec2(x, y));
+
+        // bullets pass through the player while invulnerable
+        for _ in 1..Shooter::RESPAWN_INVULNERABLE_FRAMES {
+            shooter.update().unwrap();
+        }
+        assert_eq!(shooter.counters.lives, Shooter::PLAYER_LIVES - 1);
+        assert_eq!(shooter.invulnerable, 1);
+        assert_eq!(
+            shooter.objects.player.borrow().pos.x,
+            x + Shooter::RESPAWN_INVULNERABLE_FRAMES as f32 - 1.0
+        );
+
+        shooter.update().unwrap();
+        assert_eq!(shooter.counters.lives, Shooter::PLAYER_LIVES - 2);
+        for _ in 0..Shooter::RESPAWN_INVULNERABLE_FRAMES {
+            shooter.update().unwrap();
+        }
+        assert_eq!(shooter.counters.lives, 0);
+        assert!(shooter.is_game_over());
+
+        // the stage stops
+        let frames = shooter.counters.frames;
+        shooter.update().unwrap();
+        assert_eq!(shooter.counters.frames, frames);
+    }
+
+    #[test]
+    fn test_bomb_per_press() {
+        let mut shooter = shooter("bomb");
+        let bomb = |shooter: &mut Shooter, frames: u32| {
+            shooter.input(&Input::Bomb, true);
+            for _ in 0..frames {
+                shooter.update().unwrap();
+            }
+            shooter.input(&Input::Bomb, false);
+            shooter.update().unwrap();
+        };
+
+        // holding the key spends only one bomb
+        bomb(&mut shooter, 10);
+        assert_eq!(shooter.counters.bombs, Shooter::PLAYER_BOMBS - 1);
+        // the bomb protects the player
+        assert_eq!(shooter.counters.lives, Shooter::PLAYER_LIVES);
+
+        for _ in 1..Shooter::PLAYER_BOMBS {
+            bomb(&mut shooter, 1);
+        }
+        assert_eq!(shooter.counters.bombs, 0);
+
+        // a press without bombs neither clears bullets nor protects the player
+        let bc = shooter.bullet_codes.by_name["aimed"].clone();
+        let idx = shooter
+            .fire(
+                10.0,
+                10.0,
+                BulletType::Bullet1,
+                BulletColor::White,
+                vec![],
+                bc,
+            )
+            .unwrap();
+        let invulnerable = shooter.invulnerable;
+        assert!(invulnerable > 0);
+        shooter.input(&Input::Bomb, true);
+        shooter.update().unwrap();
+        assert_eq!(shooter.counters.bombs, 0);
+        let bullet = shooter.objects.bullets.states[idx].borrow();
+        assert!(bullet.enabled);
+        assert_eq!(bullet.appearance.r#type, BulletType::Bullet1);
+        assert_eq!(shooter.invulnerable, invulnerable - 1);
+    }
+
+    #[test]
+    fn test_enabled_is_read_only() {
+        let scripts = [
+            (
+                "player",
+                r##"
+                proc main() {
+                  child.enabled = false
+                  fire("shot", 0, 0)
+                }
+                "##,
+            ),
+            (
+                "shot",
+                r##"
+                proc main() {
+                  self.enabled = false
+                  if self.age > 0 {
+                    die()
+                  }
+                }
+                "##,
+            ),
+        ];
+        let mut shooter = Shooter::new(load_scripts("enabled", &scripts), 1);
+        for _ in 0..10 {
+            shooter.update().unwrap();
+        }
+
+        // shots die by `die()` and their slots are reused
+        let bullets = &shooter.objects.bullets.states;
+        assert!(bullets[0].borrow().generation > 1);
+        assert!(bullets[1].borrow().generation > 1);
+        assert!(bullets[2..]
+            .iter()
+            .all(|state| state.borrow().generation == 0));
+    }
+
+    #[test]
+    fn test_restore_keeps_held_keys() {
+        let mut shooter = shooter("keys");
+        shooter.input(&Input::Shot, true);
+        shooter.update().unwrap();
+        let snapshot = shooter.snapshot();
+
+        shooter.input(&Input::Shot, false);
+        shooter.input(&Input::Left, true);
+        shooter.update().unwrap();
+        shooter.restore(&snapshot).unwrap();
+        {
+            let input = &shooter.objects.player.borrow().input;
+            assert!(input.left && !input.shot);
+        }
+
+        // the next frame is recorded with the keys held now
+        let left = replay::input_bits(&InputState {
+            left: true,
+            ..InputState::default()
+        });
+        shooter.update().unwrap();
+        assert_eq!(shooter.replay.inputs().last(), Some(left));
+    }
+}
@@ -0,0 +1,537 @@
+use std::{fmt, io, rc::Rc};
+
+use lang_component::{
+    bullet::{BulletColor, BulletId, BulletType},
+    vm::{Data, Inst, OperationQuery},
+};
+use lang_vm::VM;
+
+use super::{
+    bullet_codes::BulletCodes,
+    refs::Link,
+    replay::{Replay, ReplayError},
+};
+
+const MAGIC: &[u8; 4] = b"BLSS";
+const VERSION: u8 = 1;
+// the code of pooled objects which do not run any scripts, like items
+const NO_CODE: u32 = u32::MAX;
+
+#[derive(Debug, PartialEq)]
+pub enum SnapshotError {
+    NotASnapshot,
+    UnsupportedVersion(u8),
+    // the snapshot was taken with other scripts
+    OtherScripts,
+    // bytes end in the middle of a value
+    Truncated,
+    // the offset and what is wrong there
+    Invalid(usize, &'static str),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::NotASnapshot => write!(f, "not a snapshot"),
+            SnapshotError::UnsupportedVersion(v) => write!(f, "unsupported snapshot version {}", v),
+            SnapshotError::OtherScripts => write!(f, "snapshot was taken with other scripts"),
+            SnapshotError::Truncated => write!(f, "snapshot is truncated"),
+            SnapshotError::Invalid(offset, what) => {
+                write!(f, "invalid snapshot at byte {}: {}", offset, what)
+            }
+        }
+    }
+}
+
+// Writes states of a stage into bytes. All numbers are written in little endian, and
+// indices and lengths as u32.
+pub struct Writer<'a> {
+    bytes: Vec<u8>,
+    bullet_codes: &'a BulletCodes,
+}
+
+impl<'a> Writer<'a> {
+    pub fn new(bullet_codes: &'a BulletCodes) -> Self {
+        let mut w = Self {
+            bytes: Vec::new(),
+            bullet_codes,
+        };
+        w.bytes.extend_from_slice(MAGIC);
+        w.u8(VERSION);
+        w.u64(bullet_codes.hash);
+
+        w
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    pub fn u8(&mut self, n: u8) {
+        self.bytes.push(n);
+    }
+
+    pub fn bool(&mut self, b: bool) {
+        self.u8(b as u8);
+    }
+
+    pub fn u32(&mut self, n: u32) {
+        self.bytes.extend_from_slice(&n.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, n: u64) {
+        self.bytes.extend_from_slice(&n.to_le_bytes());
+    }
+
+    pub fn f32(&mut self, f: f32) {
+        self.bytes.extend_from_slice(&f.to_le_bytes());
+    }
+
+    pub fn usize(&mut self, n: usize) {
+        self.u32(n as u32);
+    }
+
+    pub fn data(&mut self, d: &Data) {
+        match d {
+            Data::Float(f) => {
+                self.u8(0);
+                self.f32(*f);
+            }
+            Data::Bool(b) => {
+                self.u8(1);
+                self.bool(*b);
+            }
+            Data::String(s) => {
+                self.u8(2);
+                self.usize(s.len());
+                self.bytes.extend_from_slice(s.as_bytes());
+            }
+        }
+    }
+
+    pub fn data_vec(&mut self, v: &[Data]) {
+        self.usize(v.len());
+        for d in v.iter() {
+            self.data(d);
+        }
+    }
+
+    pub fn bullet_type(&mut self, r#type: BulletType) {
+        self.u8(match r#type {
+            BulletType::Player => 0,
+            BulletType::PlayerShot => 1,
+            BulletType::Bullet1 => 2,
+            BulletType::Item => 3,
+            BulletType::Enemy1 => 4,
+        });
+    }
+
+    pub fn bullet_color(&mut self, color: BulletColor) {
+        self.u8(match color {
+            BulletColor::White => 0,
+        });
+    }
+
+    pub fn bullet_id(&mut self, id: BulletId) {
+        match id {
+            BulletId::Itself => self.u8(0),
+            BulletId::Player => self.u8(1),
+            BulletId::Parent => self.u8(2),
+            BulletId::Child => self.u8(3),
+            BulletId::Enemy(n) => {
+                self.u8(4);
+                self.usize(n);
+            }
+            BulletId::Bullet(n) => {
+                self.u8(5);
+                self.usize(n);
+            }
+        }
+    }
+
+    pub fn link(&mut self, link: Option<Link>) {
+        match link {
+            None => self.bool(false),
+            Some(link) => {
+                self.bool(true);
+                self.bullet_id(link.id);
+                self.u32(link.generation);
+            }
+        }
+    }
+
+    pub fn operation(&mut self, op: &OperationQuery) {
+        match op {
+            OperationQuery::Fire(bullet_id, (x, y), r#type, color, params, idx) => {
+                self.u8(0);
+                self.usize(*bullet_id);
+                self.f32(*x);
+                self.f32(*y);
+                self.bullet_type(*r#type);
+                self.bullet_color(*color);
+                self.data_vec(params);
+                self.usize(*idx);
+            }
+            OperationQuery::Spawn(bullet_id, (x, y), params, idx) => {
+                self.u8(1);
+                self.usize(*bullet_id);
+                self.f32(*x);
+                self.f32(*y);
+                self.data_vec(params);
+                self.usize(*idx);
+            }
+            OperationQuery::Die(idx) => {
+                self.u8(2);
+                self.usize(*idx);
+            }
+        }
+    }
+
+    // The code is written as the id of the bullet code sharing it.
+    pub fn vm(&mut self, vm: &VM) {
+        let code = self
+            .bullet_codes
+            .by_id
+            .iter()
+            .position(|bc| Rc::ptr_eq(&bc.code, &vm.code));
+        self.u32(code.map_or(NO_CODE, |id| id as u32));
+
+        self.usize(vm.pc);
+        self.data_vec(&vm.stack);
+        self.usize(vm.rstack.len());
+        for addr in vm.rstack.iter() {
+            self.usize(*addr);
+        }
+        self.usize(vm.memory.len());
+        self.bytes.extend_from_slice(&vm.memory);
+        self.bool(vm.suspended);
+    }
+
+    pub fn replay(&mut self, replay: &Replay) {
+        replay
+            .write(&mut self.bytes)
+            .expect("writing to Vec<u8> does not fail");
+    }
+}
+
+// Reads states written by `Writer` from the bytes.
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+    bullet_codes: &'a BulletCodes,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8], bullet_codes: &'a BulletCodes) -> Result<Self, SnapshotError> {
+        let mut r = Self {
+            bytes,
+            offset: 0,
+            bullet_codes,
+        };
+        if r.take(MAGIC.len()).map_or(true, |magic| magic != MAGIC) {
+            return Err(SnapshotError::NotASnapshot);
+        }
+        let version = r.u8()?;
+        if version != VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+        if r.u64()? != bullet_codes.hash {
+            return Err(SnapshotError::OtherScripts);
+        }
+
+        Ok(r)
+    }
+
+    // Checks that all bytes are read.
+    pub fn finish(self) -> Result<(), SnapshotError> {
+        if self.offset != self.bytes.len() {
+            return Err(self.invalid("trailing bytes"));
+        }
+
+        Ok(())
+    }
+
+    pub fn invalid(&self, what: &'static str) -> SnapshotError {
+        SnapshotError::Invalid(self.offset, what)
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], SnapshotError> {
+        let bytes = self
+            .bytes
+            .get(self.offset..self.offset + len)
+            .ok_or(SnapshotError::Truncated)?;
+        self.offset += len;
+
+        Ok(bytes)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, SnapshotError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, SnapshotError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(self.invalid("bool is neither 0 nor 1")),
+        }
+    }
+
+    pub fn u32(&mut self) -> Result<u32, SnapshotError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, SnapshotError> {
+        let bytes = self.take(8)?;
+        Ok(u64::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    pub fn f32(&mut self) -> Result<f32, SnapshotError> {
+        let bytes = self.take(4)?;
+        Ok(f32::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    pub fn usize(&mut self) -> Result<usize, SnapshotError> {
+        Ok(self.u32()? as usize)
+    }
+
+    // Reads a length, which must not exceed the rest of bytes when an item takes
+    // `item_size` bytes at least, so broken lengths cannot allocate much.
+    fn len(&mut self, item_size: usize) -> Result<usize, SnapshotError> {
+        let len = self.usize()?;
+        if len * item_size > self.bytes.len() - self.offset {
+            return Err(SnapshotError::Truncated);
+        }
+
+        Ok(len)
+    }
+
+    pub fn data(&mut self) -> Result<Data, SnapshotError> {
+        match self.u8()? {
+            0 => Ok(Data::Float(self.f32()?)),
+            1 => Ok(Data::Bool(self.bool()?)),
+            2 => {
+                let len = self.len(1)?;
+                let bytes = self.take(len)?;
+                match std::str::from_utf8(bytes) {
+                    Ok(s) => Ok(Data::String(s.to_string())),
+                    Err(_) => Err(self.invalid("string is not UTF-8")),
+                }
+            }
+            _ => Err(self.invalid("unknown data type")),
+        }
+    }
+
+    pub fn data_vec(&mut self) -> Result<Vec<Data>, SnapshotError> {
+        let len = self.len(2)?;
+        let mut v = Vec::with_capacity(len);
+        for _ in 0..len {
+            v.push(self.data()?);
+        }
+
+        Ok(v)
+    }
+
+    pub fn bullet_type(&mut self) -> Result<BulletType, SnapshotError> {
+        match self.u8()? {
+            0 => Ok(BulletType::Player),
+            1 => Ok(BulletType::PlayerShot),
+            2 => Ok(BulletType::Bullet1),
+            3 => Ok(BulletType::Item),
+            4 => Ok(BulletType::Enemy1),
+            _ => Err(self.invalid("unknown bullet type")),
+        }
+    }
+
+    pub fn bullet_color(&mut self) -> Result<BulletColor, SnapshotError> {
+        match self.u8()? {
+            0 => Ok(BulletColor::White),
+            _ => Err(self.invalid("unknown bullet color")),
+        }
+    }
+
+    pub fn bullet_id(&mut self) -> Result<BulletId, SnapshotError> {
+        match self.u8()? {
+            0 => Ok(BulletId::Itself),
+            1 => Ok(BulletId::Player),
+            2 => Ok(BulletId::Parent),
+            3 => Ok(BulletId::Child),
+            4 => Ok(BulletId::Enemy(self.usize()?)),
+            5 => Ok(BulletId::Bullet(self.usize()?)),
+            _ => Err(self.invalid("unknown bullet id")),
+        }
+    }
+
+    pub fn link(&mut self) -> Result<Option<Link>, SnapshotError> {
+        if !self.bool()? {
+            return Ok(None);
+        }
+
+        Ok(Some(Link {
+            id: self.bullet_id()?,
+            generation: self.u32()?,
+        }))
+    }
+
+    // Reads the id of a bullet code.
+    fn bullet_code_id(&mut self) -> Result<usize, SnapshotError> {
+        let id = self.usize()?;
+        if id >= self.bullet_codes.by_id.len() {
+            return Err(self.invalid("unknown bullet code"));
+        }
+
+        Ok(id)
+    }
+
+    pub fn operation(&mut self) -> Result<OperationQuery, SnapshotError> {
+        match self.u8()? {
+            0 => Ok(OperationQuery::Fire(
+                self.bullet_code_id()?,
+                (self.f32()?, self.f32()?),
+                self.bullet_type()?,
+                self.bullet_color()?,
+                self.data_vec()?,
+                self.usize()?,
+            )),
+            1 => Ok(OperationQuery::Spawn(
+                self.bullet_code_id()?,
+                (self.f32()?, self.f32()?),
+                self.data_vec()?,
+                self.usize()?,
+            )),
+            2 => Ok(OperationQuery::Die(self.usize()?)),
+            _ => Err(self.invalid("unknown operation")),
+        }
+    }
+
+    // Restores `vm` as it was written, sharing the code with the bullet code.
+    pub fn vm(&mut self, vm: &mut VM) -> Result<(), SnapshotError> {
+        vm.code = match self.u32()? {
+            NO_CODE => Rc::new(vec![Inst::Term]),
+            id => match self.bullet_codes.by_id.get(id as usize) {
+                Some(bc) => bc.code.clone(),
+                None => return Err(self.invalid("unknown bullet code")),
+            },
+        };
+
+        vm.pc = self.usize()?;
+        vm.stack = self.data_vec()?;
+        let len = self.len(4)?;
+        vm.rstack.clear();
+        for _ in 0..len {
+            vm.rstack.push(self.usize()?);
+        }
+        let len = self.len(1)?;
+        vm.memory = self.take(len)?.to_vec();
+        vm.suspended = self.bool()?;
+
+        Ok(())
+    }
+
+    pub fn replay(&mut self) -> Result<Replay, SnapshotError> {
+        let mut rest = &self.bytes[self.offset..];
+        let replay = match Replay::read(&mut rest) {
+            Ok(replay) => replay,
+            Err(ReplayError::Io(err)) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                return Err(SnapshotError::Truncated)
+            }
+            Err(_) => return Err(self.invalid("broken replay")),
+        };
+        self.offset = self.bytes.len() - rest.len();
+
+        Ok(replay)
+    }
+}
+
+#[cfg(test)]
+mod snapshot_test {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn bullet_codes(hash: u64) -> BulletCodes {
+        BulletCodes {
+            by_name: HashMap::new(),
+            by_id: Vec::new(),
+            hash,
+        }
+    }
+
+    #[test]
+    fn test_write_and_read() {
+        let bullet_codes = bullet_codes(42);
+        let data = vec![
+            Data::Float(1.5),
+            Data::Bool(true),
+            Data::String("aあ".to_string()),
+        ];
+        let link = Some(Link {
+            id: BulletId::Bullet(3999),
+            generation: 7,
+        });
+        let op = OperationQuery::Fire(
+            0,
+            (1.0, 2.0),
+            BulletType::Item,
+            BulletColor::White,
+            data.clone(),
+            12,
+        );
+
+        let mut w = Writer::new(&bullet_codes);
+        w.data_vec(&data);
+        w.link(link);
+        w.link(None);
+        w.operation(&OperationQuery::Die(5));
+        let bytes = w.finish();
+
+        let mut r = Reader::new(&bytes, &bullet_codes).unwrap();
+        assert_eq!(r.data_vec(), Ok(data));
+        assert_eq!(r.link(), Ok(link));
+        assert_eq!(r.link(), Ok(None));
+        assert_eq!(r.operation(), Ok(OperationQuery::Die(5)));
+        assert_eq!(r.finish(), Ok(()));
+
+        // no bullet codes of the id
+        let mut w = Writer::new(&bullet_codes);
+        w.operation(&op);
+        let bytes = w.finish();
+        let mut r = Reader::new(&bytes, &bullet_codes).unwrap();
+        assert!(matches!(
+            r.operation(),
+            Err(SnapshotError::Invalid(_, "unknown bullet code"))
+        ));
+    }
+
+    #[test]
+    fn test_broken_snapshots() {
+        let bullet_codes = bullet_codes(42);
+        let mut w = Writer::new(&bullet_codes);
+        w.data(&Data::Float(1.0));
+        let bytes = w.finish();
+
+        assert!(matches!(
+            Reader::new(b"RIFF", &bullet_codes),
+            Err(SnapshotError::NotASnapshot)
+        ));
+        assert!(matches!(
+            Reader::new(&bytes, &self::bullet_codes(43)),
+            Err(SnapshotError::OtherScripts)
+        ));
+
+        let mut r = Reader::new(&bytes[..bytes.len() - 1], &bullet_codes).unwrap();
+        assert_eq!(r.data(), Err(SnapshotError::Truncated));
+
+        let mut r = Reader::new(&bytes, &bullet_codes).unwrap();
+        assert_eq!(r.u8(), Ok(0));
+        assert!(matches!(r.finish(), Err(SnapshotError::Invalid(_, _))));
+
+        // a huge length does not allocate
+        let mut w = Writer::new(&bullet_codes);
+        w.u32(u32::MAX - 1);
+        let bytes = w.finish();
+        let mut r = Reader::new(&bytes, &bullet_codes).unwrap();
+        assert_eq!(r.data_vec(), Err(SnapshotError::Truncated));
+    }
+}
@@ -1,14 +1,172 @@
 mod constant;
 mod game;
 
+use std::{
+    env,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter},
+    path::PathBuf,
+    process,
+};
+
+#[cfg(feature = "graphics")]
 use ggez::{
     conf::{WindowMode, WindowSetup},
     event, ContextBuilder,
 };
 
+#[cfg(feature = "graphics")]
 use crate::game::BulletsGame;
+use crate::game::{play_replay, run_headless, BulletCodes, InputScript, Replay};
+
+const USAGE: &str =
+    "usage: bullets [--headless FRAMES [--input FILE]] [--record FILE] [--practice] [SCRIPTS_DIR]
+       bullets --replay FILE [SCRIPTS_DIR]";
+
+struct Args {
+    scripts_dir: PathBuf,
+    // runs the frames without a window and prints states
+    headless: Option<u32>,
+    input: Option<PathBuf>,
+    // writes the replay of the play
+    record: Option<PathBuf>,
+    // plays the replay back without a window and prints states
+    replay: Option<PathBuf>,
+    // enables pause, frame step, slow motion and rewind in the window
+    practice: bool,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut args = Args {
+        scripts_dir: PathBuf::from(constant::SCRIPTS_DIR),
+        headless: None,
+        input: None,
+        record: None,
+        replay: None,
+        practice: false,
+    };
+
+    let mut iter = env::args().skip(1);
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--headless" => {
+                let frames = iter.next().and_then(|frames| frames.parse().ok());
+                args.headless = Some(frames.ok_or("--headless needs the number of frames")?);
+            }
+            "--input" => {
+                let file = iter.next().ok_or("--input needs a file")?;
+                args.input = Some(PathBuf::from(file));
+            }
+            "--record" => {
+                let file = iter.next().ok_or("--record needs a file")?;
+                args.record = Some(PathBuf::from(file));
+            }
+            "--replay" => {
+                let file = iter.next().ok_or("--replay needs a file")?;
+                args.replay = Some(PathBuf::from(file));
+            }
+            "--practice" => args.practice = true,
+            _ if arg.starts_with("--") => return Err(format!("unknown option '{}'", arg)),
+            _ => args.scripts_dir = PathBuf::from(arg),
+        }
+    }
+
+    Ok(args)
+}
+
+fn headless(
+    bullet_codes: BulletCodes,
+    frames: u32,
+    input: Option<PathBuf>,
+    record: Option<PathBuf>,
+) {
+    let inputs = match input {
+        Some(path) => {
+            let source = match fs::read_to_string(&path) {
+                Ok(source) => source,
+                Err(err) => {
+                    eprintln!("cannot read input '{}': {}", path.display(), err);
+                    process::exit(1);
+                }
+            };
+            match InputScript::parse(&source) {
+                Ok(inputs) => inputs,
+                Err(err) => {
+                    eprintln!("{}: {}", path.display(), err);
+                    process::exit(1);
+                }
+            }
+        }
+        None => InputScript::default(),
+    };
+
+    let mut out = io::stdout().lock();
+    let replay = match run_headless(bullet_codes, frames, &inputs, &mut out) {
+        Ok(replay) => replay,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    };
+
+    if let Some(path) = record {
+        let written = File::create(&path).and_then(|f| replay.write(&mut BufWriter::new(f)));
+        if let Err(err) = written {
+            eprintln!("cannot write replay '{}': {}", path.display(), err);
+            process::exit(1);
+        }
+    }
+}
+
+fn replay(bullet_codes: BulletCodes, path: PathBuf) {
+    let replay = match File::open(&path)
+        .map_err(|err| err.into())
+        .and_then(|f| Replay::read(&mut BufReader::new(f)))
+    {
+        Ok(replay) => replay,
+        Err(err) => {
+            eprintln!("{}: {}", path.display(), err);
+            process::exit(1);
+        }
+    };
+
+    let mut out = io::stdout().lock();
+    if let Err(err) = play_replay(bullet_codes, &replay, &mut out) {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
 
 fn main() {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("{}\n{}", err, USAGE);
+            process::exit(2);
+        }
+    };
+    let bullet_codes = match BulletCodes::load(&args.scripts_dir) {
+        Ok(bullet_codes) => bullet_codes,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    };
+
+    if let Some(path) = args.replay {
+        replay(bullet_codes, path);
+        return;
+    }
+    if let Some(frames) = args.headless {
+        headless(bullet_codes, frames, args.input, args.record);
+        return;
+    }
+
+    window(bullet_codes, args.record, args.practice);
+}
+
+#[cfg(feature = "graphics")]
+fn window(bullet_codes: BulletCodes, record: Option<PathBuf>, practice: bool) {
     let title = format!("some nice game v{}", env!("CARGO_PKG_VERSION"));
     let author = "t-sin";
 
@@ -22,7 +180,16 @@ fn main() {
         .window_mode(window_mode)
         .build()
         .expect("cannot create ggez context.");
-    let game = BulletsGame::new(&mut ctx);
+    let game = BulletsGame::new(&mut ctx, bullet_codes, record, practice);
 
     event::run(ctx, event_loop, game);
 }
+
+#[cfg(not(feature = "graphics"))]
+fn window(_bullet_codes: BulletCodes, _record: Option<PathBuf>, _practice: bool) {
+    eprintln!(
+        "built without the window, use --headless or --replay\n{}",
+        USAGE
+    );
+    process::exit(2);
+}